@@ -0,0 +1,5 @@
+-- Add a `status` column to track double opt-in confirmation.
+-- Rows created before the confirmation flow existed are treated as confirmed.
+ALTER TABLE subscriptions ADD COLUMN status TEXT NULL;
+UPDATE subscriptions SET status = 'confirmed' WHERE status IS NULL;
+ALTER TABLE subscriptions ALTER COLUMN status SET NOT NULL;
@@ -0,0 +1,8 @@
+-- Create Users Table
+-- `password_hash` holds a PHC string, so algorithm, parameters and salt
+-- travel together with the hash itself.
+CREATE TABLE users(
+  user_id uuid PRIMARY KEY,
+  username TEXT NOT NULL UNIQUE,
+  password_hash TEXT NOT NULL
+);
@@ -0,0 +1,8 @@
+-- Create Sessions Table
+-- Backs `PostgresSessionStore`: `state` holds the JSON-serialized session map.
+CREATE TABLE sessions(
+  session_key TEXT PRIMARY KEY,
+  state TEXT NOT NULL,
+  expires_at timestamptz NOT NULL
+);
+CREATE INDEX sessions_expires_at_idx ON sessions (expires_at);
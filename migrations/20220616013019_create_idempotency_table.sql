@@ -0,0 +1,12 @@
+-- Create Idempotency Table
+-- Response columns stay NULL while the first request for a key is still being
+-- processed; its transaction holds the row lock until they are filled in.
+CREATE TABLE idempotency(
+  user_id uuid NOT NULL REFERENCES users (user_id),
+  idempotency_key TEXT NOT NULL,
+  response_status_code SMALLINT NULL,
+  response_headers TEXT NULL,
+  response_body BYTEA NULL,
+  created_at timestamptz NOT NULL,
+  PRIMARY KEY (user_id, idempotency_key)
+);
@@ -0,0 +1,16 @@
+-- Create Send Outcomes Table
+-- What the email provider answered, one row per recipient of every email.
+CREATE TABLE send_outcomes(
+  send_outcome_id uuid NOT NULL,
+  recipient TEXT NOT NULL,
+  status TEXT NOT NULL,
+  reject_reason TEXT NULL,
+  provider_message_id TEXT NULL,
+  newsletter_issue_id uuid NULL
+    REFERENCES newsletter_issues (newsletter_issue_id),
+  recorded_at timestamptz NOT NULL,
+  PRIMARY KEY (send_outcome_id)
+);
+-- Provider webhooks refer to emails by their message id.
+CREATE INDEX send_outcomes_provider_message_id_idx
+  ON send_outcomes (provider_message_id);
@@ -0,0 +1,18 @@
+-- Create Delivery Events Table
+-- What providers' webhooks told us about emails after we sent them.
+CREATE TABLE delivery_events(
+  delivery_event_id uuid NOT NULL,
+  provider TEXT NOT NULL,
+  kind TEXT NOT NULL,
+  recipient TEXT NOT NULL,
+  provider_message_id TEXT NULL,
+  -- Providers retry webhooks, this lets us ignore events we already have.
+  provider_event_id TEXT NULL,
+  description TEXT NULL,
+  occurred_at timestamptz NOT NULL,
+  received_at timestamptz NOT NULL,
+  PRIMARY KEY (delivery_event_id)
+);
+CREATE UNIQUE INDEX delivery_events_provider_event_id_idx
+  ON delivery_events (provider, provider_event_id);
+CREATE INDEX delivery_events_recipient_idx ON delivery_events (recipient);
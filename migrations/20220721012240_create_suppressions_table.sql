@@ -0,0 +1,24 @@
+-- Create Suppressions Table
+-- Addresses we must never email, whatever their subscription says.
+CREATE TABLE suppressions(
+  -- A lowercase address, or a domain to suppress all of its addresses
+  address TEXT NOT NULL,
+  reason TEXT NOT NULL,
+  -- What put it there: bounce, complaint, admin or erasure
+  source TEXT NOT NULL,
+  created_at timestamptz NOT NULL,
+  PRIMARY KEY (address)
+);
+-- Subscribers suppressed before the list existed must stay suppressed.
+INSERT INTO suppressions (address, reason, source, created_at)
+SELECT DISTINCT ON (lower(s.email))
+  lower(s.email),
+  'Suppressed before the suppression list existed',
+  CASE WHEN EXISTS (
+    SELECT 1 FROM delivery_events e
+    WHERE e.recipient = s.email AND e.kind = 'complaint'
+  ) THEN 'complaint' ELSE 'bounce' END,
+  now()
+FROM subscriptions s
+WHERE s.status = 'suppressed'
+ON CONFLICT (address) DO NOTHING;
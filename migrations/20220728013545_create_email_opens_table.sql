@@ -0,0 +1,19 @@
+-- Create Email Opens Table
+-- Issues only get a tracking pixel when published with `track_opens`.
+ALTER TABLE newsletter_issues
+  ADD COLUMN track_opens BOOLEAN NOT NULL DEFAULT false;
+-- One row per load of an issue's tracking pixel.
+CREATE TABLE email_opens(
+  email_open_id uuid NOT NULL,
+  newsletter_issue_id uuid NOT NULL
+    REFERENCES newsletter_issues (newsletter_issue_id),
+  recipient TEXT NOT NULL,
+  opened_at timestamptz NOT NULL,
+  user_agent TEXT NULL,
+  -- Why we think software loaded the pixel rather than a reader, if we do:
+  -- apple_mail_privacy, prefetch or scanner
+  machine_open TEXT NULL,
+  PRIMARY KEY (email_open_id)
+);
+CREATE INDEX email_opens_newsletter_issue_id_idx
+  ON email_opens (newsletter_issue_id);
@@ -0,0 +1,13 @@
+-- Create Newsletter Issue Attachments Table
+-- Files sent along with every email of an issue, in the order given.
+CREATE TABLE newsletter_issue_attachments(
+  newsletter_issue_id uuid NOT NULL
+    REFERENCES newsletter_issues (newsletter_issue_id),
+  position INT NOT NULL,
+  filename TEXT NOT NULL,
+  content_type TEXT NOT NULL,
+  content BYTEA NOT NULL,
+  -- Set for inline attachments, referred to as `cid:{content_id}` in the HTML
+  content_id TEXT NULL,
+  PRIMARY KEY (newsletter_issue_id, position)
+);
@@ -0,0 +1,3 @@
+-- Add Lower Email Index To Subscriptions
+-- Providers report recipients in whatever case they like.
+CREATE INDEX subscriptions_lower_email_idx ON subscriptions (lower(email));
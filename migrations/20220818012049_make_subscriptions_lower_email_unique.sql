@@ -0,0 +1,7 @@
+-- Make Subscriptions Lower Email Unique
+-- Addresses are compared without regard to case: `Ursula@Example.com` and
+-- `ursula@example.com` are the same subscriber. Fails if both already exist,
+-- they have to be merged by hand first.
+DROP INDEX subscriptions_lower_email_idx;
+CREATE UNIQUE INDEX subscriptions_lower_email_key
+  ON subscriptions (lower(email));
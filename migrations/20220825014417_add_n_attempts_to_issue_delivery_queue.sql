@@ -0,0 +1,4 @@
+-- Add Attempts To Issue Delivery Queue
+-- Failed attempts at delivering a task, which is given up on past a limit.
+ALTER TABLE issue_delivery_queue
+  ADD COLUMN n_attempts SMALLINT NOT NULL DEFAULT 0;
@@ -0,0 +1,106 @@
+use std::{future::Future, pin::Pin};
+
+use actix_web::{
+  dev::Payload,
+  http::{
+    header::{HeaderMap, HeaderValue},
+    StatusCode,
+  },
+  web::Data,
+  FromRequest, HttpRequest, HttpResponse, ResponseError,
+};
+use anyhow::Context;
+use secrecy::Secret;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::{validate_credentials, AuthError, Credentials};
+
+/// An admin who presented valid credentials along with the request.
+///
+/// Any handler taking an `AuthenticatedUser` argument only runs once the
+/// caller has been authenticated, which is how `startup::run` puts admin
+/// routes behind a login.
+#[derive(Debug, Clone, Copy)]
+pub struct AuthenticatedUser {
+  pub user_id: Uuid,
+}
+
+impl FromRequest for AuthenticatedUser {
+  type Error = AuthError;
+  type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+  fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+    let credentials = basic_authentication(req.headers())
+      .map_err(AuthError::InvalidCredentials);
+    let pool = req.app_data::<Data<PgPool>>().cloned();
+
+    Box::pin(async move {
+      let pool = pool.context("No database pool registered as app data.")?;
+      let user_id = validate_credentials(credentials?, &pool).await?;
+      Ok(Self { user_id })
+    })
+  }
+}
+
+impl ResponseError for AuthError {
+  fn status_code(&self) -> StatusCode {
+    match self {
+      AuthError::InvalidCredentials(_) => StatusCode::UNAUTHORIZED,
+      AuthError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+  }
+
+  fn error_response(&self) -> HttpResponse {
+    match self {
+      AuthError::InvalidCredentials(_) => {
+        let header_value = HeaderValue::from_str(r#"Basic realm="publish""#)
+          .expect("Failed to build `WWW-Authenticate` header value.");
+        HttpResponse::Unauthorized()
+          .insert_header(("WWW-Authenticate", header_value))
+          .finish()
+      }
+      AuthError::UnexpectedError(_) => {
+        HttpResponse::InternalServerError().finish()
+      }
+    }
+  }
+}
+
+/// Extract `Credentials` from an `Authorization: Basic ...` header.
+pub fn basic_authentication(
+  headers: &HeaderMap,
+) -> Result<Credentials, anyhow::Error> {
+  let header_value = headers
+    .get("Authorization")
+    .context("The 'Authorization' header was missing.")?
+    .to_str()
+    .context("The 'Authorization' header was not a valid UTF8 string.")?;
+  let base64encoded_segment = header_value
+    .strip_prefix("Basic ")
+    .context("The authorization scheme was not 'Basic'.")?;
+  let decoded_bytes =
+    base64::decode_config(base64encoded_segment, base64::STANDARD)
+      .context("Failed to base64-decode 'Basic' credentials.")?;
+  let decoded_credentials = String::from_utf8(decoded_bytes)
+    .context("The decoded credential string is not valid UTF8.")?;
+
+  let mut credentials = decoded_credentials.splitn(2, ':');
+  let username = credentials
+    .next()
+    .ok_or_else(|| {
+      anyhow::anyhow!("A username must be provided in 'Basic' auth.")
+    })?
+    .to_string();
+  let password = credentials
+    .next()
+    .ok_or_else(|| {
+      anyhow::anyhow!("A password must be provided in 'Basic' auth.")
+    })?
+    .to_string();
+
+  Ok(Credentials {
+    username,
+    password: Secret::new(password),
+  })
+}
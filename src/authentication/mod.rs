@@ -0,0 +1,9 @@
+mod extractor;
+mod password;
+mod session;
+
+pub use extractor::{basic_authentication, AuthenticatedUser};
+pub use password::{
+  compute_password_hash, validate_credentials, AuthError, Credentials,
+};
+pub use session::LoggedInUser;
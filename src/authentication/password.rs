@@ -0,0 +1,157 @@
+use anyhow::Context;
+use argon2::{
+  password_hash::SaltString, Algorithm, Argon2, Params, PasswordHash,
+  PasswordHasher, PasswordVerifier, Version,
+};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::telementry::spawn_blocking_with_tracing;
+
+/// PHC string verified against when the username does not exist, so that a
+/// failed attempt costs the same whether or not the user is known.
+const DUMMY_PASSWORD_HASH: &str = "$argon2id$v=19$m=15000,t=2,p=1$\
+  gZiV/M1gPc22ElAH/Jh1Hw$\
+  CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno";
+
+pub struct Credentials {
+  pub username: String,
+  pub password: Secret<String>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum AuthError {
+  #[error("Invalid credentials.")]
+  InvalidCredentials(#[source] anyhow::Error),
+  #[error(transparent)]
+  UnexpectedError(#[from] anyhow::Error),
+}
+
+/// Check `credentials` against the `users` table, returning the id of the
+/// matching user.
+///
+/// # Implementation Notes
+///
+/// Hashing is deliberately expensive, so verification runs on tokio's
+/// blocking thread pool instead of stalling an actix worker.
+#[tracing::instrument(name = "Validate credentials", skip(credentials, pool))]
+pub async fn validate_credentials(
+  credentials: Credentials,
+  pool: &PgPool,
+) -> Result<Uuid, AuthError> {
+  let mut user_id = None;
+  let mut expected_password_hash = Secret::new(DUMMY_PASSWORD_HASH.to_string());
+
+  if let Some((stored_user_id, stored_password_hash)) =
+    get_stored_credentials(&credentials.username, pool).await?
+  {
+    user_id = Some(stored_user_id);
+    expected_password_hash = stored_password_hash;
+  }
+
+  spawn_blocking_with_tracing(move || {
+    verify_password_hash(expected_password_hash, credentials.password)
+  })
+  .await
+  .context("Failed to spawn blocking task.")??;
+
+  // Only reached when the password matched, which can't happen with the
+  // dummy hash.
+  user_id
+    .ok_or_else(|| anyhow::anyhow!("Unknown username."))
+    .map_err(AuthError::InvalidCredentials)
+}
+
+#[tracing::instrument(
+  name = "Verify password hash",
+  skip(expected_password_hash, password_candidate)
+)]
+fn verify_password_hash(
+  expected_password_hash: Secret<String>,
+  password_candidate: Secret<String>,
+) -> Result<(), AuthError> {
+  let expected_password_hash =
+    PasswordHash::new(expected_password_hash.expose_secret())
+      .context("Failed to parse hash in PHC string format.")?;
+
+  Argon2::default()
+    .verify_password(
+      password_candidate.expose_secret().as_bytes(),
+      &expected_password_hash,
+    )
+    .context("Invalid password.")
+    .map_err(AuthError::InvalidCredentials)
+}
+
+#[tracing::instrument(name = "Get stored credentials", skip(username, pool))]
+async fn get_stored_credentials(
+  username: &str,
+  pool: &PgPool,
+) -> Result<Option<(Uuid, Secret<String>)>, anyhow::Error> {
+  let row = sqlx::query!(
+    r#"
+    SELECT user_id, password_hash
+    FROM users
+    WHERE username = $1
+    "#,
+    username,
+  )
+  .fetch_optional(pool)
+  .await
+  .context("Failed to perform a query to retrieve stored credentials.")?
+  .map(|row| (row.user_id, Secret::new(row.password_hash)));
+  Ok(row)
+}
+
+/// Hash `password` with Argon2id and a fresh random salt, returning the
+/// result as a PHC string.
+pub fn compute_password_hash(
+  password: Secret<String>,
+) -> Result<Secret<String>, anyhow::Error> {
+  let salt = SaltString::generate(&mut rand::thread_rng());
+  let password_hash = Argon2::new(
+    Algorithm::Argon2id,
+    Version::V0x13,
+    Params::new(15000, 2, 1, None).unwrap(),
+  )
+  .hash_password(password.expose_secret().as_bytes(), &salt)?
+  .to_string();
+  Ok(Secret::new(password_hash))
+}
+
+#[cfg(test)]
+mod tests {
+  use claim::{assert_err, assert_ok};
+  use secrecy::Secret;
+
+  use super::{
+    compute_password_hash, verify_password_hash, AuthError, DUMMY_PASSWORD_HASH,
+  };
+
+  #[test]
+  fn a_computed_hash_verifies_against_its_password() {
+    let password = Secret::new("everythinghastostartsomewhere".to_string());
+    let hash = compute_password_hash(password.clone()).unwrap();
+    assert_ok!(verify_password_hash(hash, password));
+  }
+
+  #[test]
+  fn a_computed_hash_rejects_a_different_password() {
+    let password = Secret::new("everythinghastostartsomewhere".to_string());
+    let hash = compute_password_hash(password).unwrap();
+    assert_err!(verify_password_hash(
+      hash,
+      Secret::new("not-the-password".to_string())
+    ));
+  }
+
+  #[test]
+  fn the_dummy_hash_is_a_valid_phc_string_that_rejects_passwords() {
+    let outcome = verify_password_hash(
+      Secret::new(DUMMY_PASSWORD_HASH.to_string()),
+      Secret::new("everythinghastostartsomewhere".to_string()),
+    );
+    assert!(matches!(outcome, Err(AuthError::InvalidCredentials(_))));
+  }
+}
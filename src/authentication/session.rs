@@ -0,0 +1,38 @@
+use std::{future::Future, pin::Pin};
+
+use actix_web::{dev::Payload, error::InternalError, FromRequest, HttpRequest};
+use uuid::Uuid;
+
+use crate::{
+  session_state::TypedSession,
+  utils::{e500, see_other},
+};
+
+/// An admin with a logged-in browser session.
+///
+/// Handlers taking a `LoggedInUser` argument are only reachable after going
+/// through `/login`; anonymous visitors are redirected there instead.
+#[derive(Debug, Clone, Copy)]
+pub struct LoggedInUser {
+  pub user_id: Uuid,
+}
+
+impl FromRequest for LoggedInUser {
+  type Error = actix_web::Error;
+  type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+  fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+    let session = TypedSession::from_request(req, payload);
+
+    Box::pin(async move {
+      let session = session.await?;
+      match session.get_user_id().map_err(e500)? {
+        Some(user_id) => Ok(Self { user_id }),
+        None => {
+          let e = anyhow::anyhow!("The user has not logged in.");
+          Err(InternalError::from_response(e, see_other("/login")).into())
+        }
+      }
+    })
+  }
+}
@@ -0,0 +1,37 @@
+//! src/bin/fake-provider.rs
+
+use emailer::{
+  configuration::get_configuration,
+  fake_provider::{run, Failures},
+  telementry::{get_subscriber, init_subscriber},
+};
+use reqwest::Url;
+use std::{
+  io::{stdout, Error, ErrorKind, Result},
+  net::TcpListener,
+};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+  let subscriber =
+    get_subscriber("fake-provider".into(), "info".into(), stdout);
+  init_subscriber(subscriber);
+
+  let configuration =
+    get_configuration().expect("Failed to read configuration.");
+  let failures =
+    Failures::from_env().expect("Failed to read FAKE_PROVIDER_* variables.");
+
+  // Listen wherever the app will send its emails.
+  let base_url = Url::parse(&configuration.email_client.base_url)
+    .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+  let address = format!(
+    "{}:{}",
+    base_url.host_str().unwrap_or("127.0.0.1"),
+    base_url.port_or_known_default().unwrap_or(80)
+  );
+  let listener = TcpListener::bind(&address)?;
+  tracing::info!(%address, ?failures, "Listening as the email provider");
+
+  run(listener, failures)?.await
+}
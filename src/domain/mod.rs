@@ -1,7 +1,9 @@
 mod new_subscriber;
 mod subscriber_email;
 mod subscriber_name;
+mod suppressed_address;
 
-pub use new_subscriber::NewSubscriber;
-pub use subscriber_email::SubscriberEmail;
-pub use subscriber_name::SubscriberName;
+pub use new_subscriber::{NewSubscriber, NewSubscriberError};
+pub use subscriber_email::{SubscriberEmail, SubscriberEmailError};
+pub use subscriber_name::{SubscriberName, SubscriberNameError};
+pub use suppressed_address::{SuppressedAddress, SuppressedAddressError};
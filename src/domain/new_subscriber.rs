@@ -1,8 +1,76 @@
 use crate::domain::{
-  subscriber_email::SubscriberEmail, subscriber_name::SubscriberName,
+  subscriber_email::{SubscriberEmail, SubscriberEmailError},
+  subscriber_name::{SubscriberName, SubscriberNameError},
 };
 
 pub struct NewSubscriber {
   pub email: SubscriberEmail,
   pub name: SubscriberName,
 }
+
+impl NewSubscriber {
+  /// Validate every field, reporting all the invalid ones at once.
+  pub fn parse(
+    email: String,
+    name: String,
+  ) -> Result<NewSubscriber, NewSubscriberError> {
+    match (SubscriberEmail::parse(email), SubscriberName::parse(name)) {
+      (Ok(email), Ok(name)) => Ok(Self { email, name }),
+      (email, name) => Err(NewSubscriberError {
+        email: email.err(),
+        name: name.err(),
+      }),
+    }
+  }
+}
+
+/// Why each invalid field of a `NewSubscriber` was refused.
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+#[error("{}", self.messages().join(" "))]
+pub struct NewSubscriberError {
+  pub email: Option<SubscriberEmailError>,
+  pub name: Option<SubscriberNameError>,
+}
+
+impl NewSubscriberError {
+  fn messages(&self) -> Vec<String> {
+    let email = self.email.as_ref().map(ToString::to_string);
+    let name = self.name.as_ref().map(ToString::to_string);
+    email.into_iter().chain(name).collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use claim::assert_ok;
+
+  use super::{NewSubscriber, NewSubscriberError};
+  use crate::domain::{SubscriberEmailError, SubscriberNameError};
+
+  #[test]
+  fn valid_fields_are_parsed_successfully() {
+    assert_ok!(NewSubscriber::parse(
+      "ursula_le_guin@gmail.com".into(),
+      "Ursula Le Guin".into()
+    ));
+  }
+
+  #[test]
+  fn every_invalid_field_is_reported() {
+    let error = NewSubscriber::parse("not-an-email".into(), " ".into())
+      .err()
+      .unwrap();
+
+    assert_eq!(
+      error,
+      NewSubscriberError {
+        email: Some(SubscriberEmailError),
+        name: Some(SubscriberNameError::Empty),
+      }
+    );
+    assert_eq!(
+      error.to_string(),
+      "The email address is invalid. The name is empty."
+    );
+  }
+}
@@ -4,15 +4,19 @@ use validator::validate_email;
 pub struct SubscriberEmail(String);
 
 impl SubscriberEmail {
-  pub fn parse(s: String) -> Result<SubscriberEmail, String> {
+  pub fn parse(s: String) -> Result<SubscriberEmail, SubscriberEmailError> {
     if validate_email(&s) {
       Ok(Self(s))
     } else {
-      Err(format!("{} is not a valid email.", s))
+      Err(SubscriberEmailError)
     }
   }
 }
 
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+#[error("The email address is invalid.")]
+pub struct SubscriberEmailError;
+
 impl AsRef<str> for SubscriberEmail {
   fn as_ref(&self) -> &str {
     &self.0
@@ -3,26 +3,34 @@ use unicode_segmentation::UnicodeSegmentation;
 #[derive(Debug)]
 pub struct SubscriberName(String);
 
+const FORBIDDEN_CHARACTERS: [char; 10] =
+  ['/', '(', ')', '"', '<', '>', '\\', '{', '}', '%'];
+
 impl SubscriberName {
   /// Returns a `SubscriberName` if the input satisfies all validation checks
-  pub fn parse(s: String) -> Result<SubscriberName, String> {
-    let is_empty_of_whitespace = s.trim().is_empty();
-
-    let is_too_long = s.graphemes(true).count() > 256;
-
-    let forbidden_characters =
-      ['/', '(', ')', '"', '<', '>', '\\', '{', '}', '%'];
-    let contains_forbidden_characters =
-      s.chars().any(|g| forbidden_characters.contains(&g));
-
-    if is_empty_of_whitespace || is_too_long || contains_forbidden_characters {
-      Err(format!("{} is not a valid subscriber name", s))
+  pub fn parse(s: String) -> Result<SubscriberName, SubscriberNameError> {
+    if s.trim().is_empty() {
+      Err(SubscriberNameError::Empty)
+    } else if s.graphemes(true).count() > 256 {
+      Err(SubscriberNameError::TooLong)
+    } else if s.chars().any(|c| FORBIDDEN_CHARACTERS.contains(&c)) {
+      Err(SubscriberNameError::ForbiddenCharacters)
     } else {
       Ok(Self(s))
     }
   }
 }
 
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum SubscriberNameError {
+  #[error("The name is empty.")]
+  Empty,
+  #[error("The name is longer than 256 characters.")]
+  TooLong,
+  #[error("The name contains one of / ( ) \" < > \\ {{ }} %.")]
+  ForbiddenCharacters,
+}
+
 impl AsRef<str> for SubscriberName {
   fn as_ref(&self) -> &str {
     &self.0
@@ -0,0 +1,63 @@
+use validator::validate_email;
+
+/// An address we must never email, or a domain to never email any address of.
+///
+/// Stored lowercase: addresses are compared without regard to case.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuppressedAddress(String);
+
+impl SuppressedAddress {
+  pub fn parse(s: String) -> Result<SuppressedAddress, SuppressedAddressError> {
+    let s = s.trim().to_lowercase();
+    let valid = if s.contains('@') {
+      validate_email(&s)
+    } else {
+      validate_email(format!("postmaster@{}", s))
+    };
+    if valid {
+      Ok(Self(s))
+    } else {
+      Err(SuppressedAddressError)
+    }
+  }
+
+  /// Whether this covers a whole domain rather than a single address
+  pub fn is_domain(&self) -> bool {
+    !self.0.contains('@')
+  }
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+#[error("Neither an email address nor a domain.")]
+pub struct SuppressedAddressError;
+
+impl AsRef<str> for SuppressedAddress {
+  fn as_ref(&self) -> &str {
+    &self.0
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use claim::assert_err;
+
+  use super::SuppressedAddress;
+
+  #[test]
+  fn addresses_and_domains_are_lowercased() {
+    let address =
+      SuppressedAddress::parse(" Ursula@Example.COM ".into()).unwrap();
+    assert_eq!(address.as_ref(), "ursula@example.com");
+    assert!(!address.is_domain());
+    let domain = SuppressedAddress::parse("Example.com".into()).unwrap();
+    assert_eq!(domain.as_ref(), "example.com");
+    assert!(domain.is_domain());
+  }
+
+  #[test]
+  fn invalid_addresses_and_domains_are_rejected() {
+    for invalid in ["", "ursula@", "@example.com", "exa mple.com", "a@b@c"] {
+      assert_err!(SuppressedAddress::parse(invalid.into()));
+    }
+  }
+}
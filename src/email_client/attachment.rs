@@ -0,0 +1,168 @@
+use lettre::message::header::ContentType;
+
+/// A file sent along with an email.
+///
+/// Attachments with a content id are inline: the HTML part refers to them as
+/// `<img src="cid:{content_id}">` and mail clients don't list them as files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attachment {
+  filename: String,
+  content_type: String,
+  content: Vec<u8>,
+  content_id: Option<String>,
+}
+
+impl Attachment {
+  /// A regular attachment, e.g. a PDF.
+  pub fn new(
+    filename: String,
+    content_type: &str,
+    content: Vec<u8>,
+  ) -> Result<Self, AttachmentError> {
+    if filename.trim().is_empty() {
+      return Err(AttachmentError::MissingFilename);
+    }
+    if ContentType::parse(content_type).is_err() {
+      return Err(AttachmentError::InvalidContentType(content_type.into()));
+    }
+    Ok(Self {
+      filename,
+      content_type: content_type.to_owned(),
+      content,
+      content_id: None,
+    })
+  }
+
+  /// An inline attachment, e.g. a logo embedded in the HTML part.
+  pub fn inline(
+    content_id: String,
+    filename: String,
+    content_type: &str,
+    content: Vec<u8>,
+  ) -> Result<Self, AttachmentError> {
+    // It ends up between angle brackets in the `Content-ID` header.
+    if content_id.is_empty()
+      || content_id
+        .chars()
+        .any(|c| c.is_whitespace() || c.is_control() || "<>\"".contains(c))
+    {
+      return Err(AttachmentError::InvalidContentId(content_id));
+    }
+    Ok(Self {
+      content_id: Some(content_id),
+      ..Self::new(filename, content_type, content)?
+    })
+  }
+
+  pub fn filename(&self) -> &str {
+    &self.filename
+  }
+
+  pub fn content_type(&self) -> &str {
+    &self.content_type
+  }
+
+  pub fn content(&self) -> &[u8] {
+    &self.content
+  }
+
+  pub fn content_id(&self) -> Option<&str> {
+    self.content_id.as_deref()
+  }
+
+  /// The content, base64-encoded as every provider expects it.
+  pub fn encoded_content(&self) -> String {
+    base64::encode(&self.content)
+  }
+
+  /// Size of the content once base64-encoded, which is what providers count
+  /// against their limits.
+  pub fn encoded_size(&self) -> usize {
+    self.content.len().div_ceil(3) * 4
+  }
+}
+
+/// Make sure `attachments` fit within a provider's `max_attachments_size`.
+pub(super) fn check_attachments_size(
+  attachments: &[Attachment],
+  max_size: usize,
+) -> Result<(), AttachmentError> {
+  let size = attachments.iter().map(Attachment::encoded_size).sum();
+  if size > max_size {
+    return Err(AttachmentError::TooLarge { size, max_size });
+  }
+  Ok(())
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum AttachmentError {
+  #[error("Attachments need a file name.")]
+  MissingFilename,
+  #[error("`{0}` is not a valid MIME type.")]
+  InvalidContentType(String),
+  #[error("`{0}` is not a valid content id.")]
+  InvalidContentId(String),
+  #[error(
+    "Attachments add up to {size} bytes once encoded, \
+    the email provider accepts at most {max_size}."
+  )]
+  TooLarge { size: usize, max_size: usize },
+}
+
+#[cfg(test)]
+mod tests {
+  use claim::{assert_err, assert_ok};
+
+  use super::{check_attachments_size, Attachment};
+
+  fn pdf(size: usize) -> Attachment {
+    Attachment::new("issue.pdf".into(), "application/pdf", vec![0; size])
+      .unwrap()
+  }
+
+  #[test]
+  fn attachments_need_a_filename() {
+    assert_err!(Attachment::new(" ".into(), "application/pdf", vec![]));
+  }
+
+  #[test]
+  fn attachments_need_a_valid_content_type() {
+    assert_err!(Attachment::new("issue.pdf".into(), "pdf", vec![]));
+  }
+
+  #[test]
+  fn content_ids_must_fit_in_a_header() {
+    for content_id in ["", "logo png", "<logo>", "logo\r\n"] {
+      assert_err!(Attachment::inline(
+        content_id.into(),
+        "logo.png".into(),
+        "image/png",
+        vec![]
+      ));
+    }
+    assert_ok!(Attachment::inline(
+      "logo@example.com".into(),
+      "logo.png".into(),
+      "image/png",
+      vec![]
+    ));
+  }
+
+  #[test]
+  fn encoded_size_accounts_for_base64_padding() {
+    for size in 0..10 {
+      let attachment = pdf(size);
+      assert_eq!(
+        attachment.encoded_size(),
+        attachment.encoded_content().len()
+      );
+    }
+  }
+
+  #[test]
+  fn attachments_over_the_limit_are_refused() {
+    let attachments = [pdf(3), pdf(3)];
+    assert_ok!(check_attachments_size(&attachments, 8));
+    assert_err!(check_attachments_size(&attachments, 7));
+  }
+}
@@ -0,0 +1,291 @@
+use std::collections::BTreeMap;
+
+use super::Attachment;
+use crate::domain::SubscriberEmail;
+
+/// Headers we, the sandbox or the providers set ourselves: overriding them
+/// would break the message or let it pose as something else.
+const RESERVED_HEADERS: &[&str] = &[
+  "bcc",
+  "cc",
+  "content-disposition",
+  "content-id",
+  "content-transfer-encoding",
+  "content-type",
+  "date",
+  "dkim-signature",
+  "from",
+  "list-unsubscribe",
+  "list-unsubscribe-post",
+  "message-id",
+  "mime-version",
+  "received",
+  "reply-to",
+  "return-path",
+  "sender",
+  "subject",
+  "to",
+  "x-original-cc",
+  "x-original-to",
+];
+
+/// Lines of a message can't be longer than that, header name included.
+const MAX_HEADER_LENGTH: usize = 998;
+
+/// Mandrill's limit, the strictest of our providers.
+const MAX_TAG_LENGTH: usize = 50;
+
+/// SendGrid's limit on categories.
+const MAX_TAGS: usize = 10;
+
+/// Postmark's limit on metadata fields.
+const MAX_METADATA: usize = 10;
+
+/// An email, apart from its recipients, built with `Message::builder`.
+#[derive(Debug, Clone)]
+pub struct Message {
+  pub(super) subject: String,
+  pub(super) html_content: String,
+  pub(super) text_content: String,
+  pub(super) attachments: Vec<Attachment>,
+  pub(super) sender_name: Option<String>,
+  pub(super) reply_to: Option<SubscriberEmail>,
+  pub(super) cc: Vec<SubscriberEmail>,
+  pub(super) bcc: Vec<SubscriberEmail>,
+  pub(super) headers: Vec<(String, String)>,
+  pub(super) tags: Vec<String>,
+  pub(super) metadata: BTreeMap<String, String>,
+}
+
+impl Message {
+  pub fn builder(
+    subject: impl Into<String>,
+    html_content: impl Into<String>,
+    text_content: impl Into<String>,
+  ) -> MessageBuilder {
+    MessageBuilder(Self::new(subject, html_content, text_content))
+  }
+
+  /// A message with nothing but its contents, which needs no validation.
+  pub(super) fn new(
+    subject: impl Into<String>,
+    html_content: impl Into<String>,
+    text_content: impl Into<String>,
+  ) -> Self {
+    Self {
+      subject: subject.into(),
+      html_content: html_content.into(),
+      text_content: text_content.into(),
+      attachments: vec![],
+      sender_name: None,
+      reply_to: None,
+      cc: vec![],
+      bcc: vec![],
+      headers: vec![],
+      tags: vec![],
+      metadata: BTreeMap::new(),
+    }
+  }
+}
+
+/// Validates everything at once in `build`, so calls can be chained.
+pub struct MessageBuilder(Message);
+
+impl MessageBuilder {
+  pub fn attachment(mut self, attachment: Attachment) -> Self {
+    self.0.attachments.push(attachment);
+    self
+  }
+
+  /// Name shown next to the sender's address, e.g. `Our newsletter`.
+  pub fn sender_name(mut self, name: impl Into<String>) -> Self {
+    self.0.sender_name = Some(name.into());
+    self
+  }
+
+  pub fn reply_to(mut self, address: SubscriberEmail) -> Self {
+    self.0.reply_to = Some(address);
+    self
+  }
+
+  pub fn cc(mut self, address: SubscriberEmail) -> Self {
+    self.0.cc.push(address);
+    self
+  }
+
+  pub fn bcc(mut self, address: SubscriberEmail) -> Self {
+    self.0.bcc.push(address);
+    self
+  }
+
+  /// A custom header, e.g. `X-Campaign`. Headers describing the message's
+  /// structure or its participants are refused, use the dedicated methods.
+  pub fn header(
+    mut self,
+    name: impl Into<String>,
+    value: impl Into<String>,
+  ) -> Self {
+    self.0.headers.push((name.into(), value.into()));
+    self
+  }
+
+  /// A label providers group statistics and webhooks by.
+  pub fn tag(mut self, tag: impl Into<String>) -> Self {
+    self.0.tags.push(tag.into());
+    self
+  }
+
+  /// A key/value pair providers hand back in their webhooks.
+  pub fn metadata(
+    mut self,
+    key: impl Into<String>,
+    value: impl Into<String>,
+  ) -> Self {
+    self.0.metadata.insert(key.into(), value.into());
+    self
+  }
+
+  pub fn build(self) -> Result<Message, MessageError> {
+    let message = self.0;
+    if let Some(name) = &message.sender_name {
+      if name.trim().is_empty() || name.chars().any(char::is_control) {
+        return Err(MessageError::InvalidSenderName(name.clone()));
+      }
+    }
+    for (name, value) in &message.headers {
+      check_header(name, value)?;
+    }
+    if message.tags.len() > MAX_TAGS {
+      return Err(MessageError::TooManyTags(MAX_TAGS));
+    }
+    for tag in &message.tags {
+      // Mandrill reserves tags starting with an underscore.
+      if tag.is_empty()
+        || tag.len() > MAX_TAG_LENGTH
+        || tag.starts_with('_')
+        || !tag.chars().all(|c| c.is_ascii_graphic() || c == ' ')
+      {
+        return Err(MessageError::InvalidTag(tag.clone()));
+      }
+    }
+    if message.metadata.len() > MAX_METADATA {
+      return Err(MessageError::TooMuchMetadata(MAX_METADATA));
+    }
+    for key in message.metadata.keys() {
+      if key.is_empty()
+        || !key
+          .chars()
+          .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+      {
+        return Err(MessageError::InvalidMetadataKey(key.clone()));
+      }
+    }
+    Ok(message)
+  }
+}
+
+/// Refuse anything that would let a value spill into other headers or the
+/// body, or override a header we control.
+fn check_header(name: &str, value: &str) -> Result<(), MessageError> {
+  // Printable ASCII but the colon, as per RFC 5322.
+  let valid_name = !name.is_empty()
+    && name.bytes().all(|b| (33..=126).contains(&b) && b != b':');
+  if !valid_name {
+    return Err(MessageError::InvalidHeaderName(name.to_owned()));
+  }
+  if RESERVED_HEADERS.contains(&name.to_ascii_lowercase().as_str()) {
+    return Err(MessageError::ReservedHeader(name.to_owned()));
+  }
+  if value.chars().any(|c| c.is_control() && c != '\t')
+    || name.len() + 2 + value.len() > MAX_HEADER_LENGTH
+  {
+    return Err(MessageError::InvalidHeaderValue(name.to_owned()));
+  }
+  Ok(())
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum MessageError {
+  #[error("`{0}` is not a valid sender name.")]
+  InvalidSenderName(String),
+  #[error("`{0}` is not a valid header name.")]
+  InvalidHeaderName(String),
+  #[error("The `{0}` header is set by the email client, it can't be custom.")]
+  ReservedHeader(String),
+  #[error("The value of the `{0}` header is too long or spans several lines.")]
+  InvalidHeaderValue(String),
+  #[error("Emails can have at most {0} tags.")]
+  TooManyTags(usize),
+  #[error("`{0}` is not a valid tag.")]
+  InvalidTag(String),
+  #[error("Emails can have at most {0} metadata fields.")]
+  TooMuchMetadata(usize),
+  #[error("`{0}` is not a valid metadata key.")]
+  InvalidMetadataKey(String),
+}
+
+#[cfg(test)]
+mod tests {
+  use claim::{assert_err, assert_ok};
+
+  use super::Message;
+
+  fn builder() -> super::MessageBuilder {
+    Message::builder("Subject", "<p>Hi!</p>", "Hi!")
+  }
+
+  #[test]
+  fn custom_headers_are_accepted() {
+    assert_ok!(builder()
+      .header("X-Campaign", "spring\tsale")
+      .header("Precedence", "bulk")
+      .build());
+  }
+
+  #[test]
+  fn header_injection_is_refused() {
+    for value in ["a\r\nBcc: victim@example.com", "a\nb", "a\rb", "a\0b"] {
+      assert_err!(builder().header("X-Campaign", value).build());
+    }
+  }
+
+  #[test]
+  fn invalid_header_names_are_refused() {
+    for name in ["", "X Campaign", "X-Campaign:", "X-Cämpaign", "X\r\nTo"] {
+      assert_err!(builder().header(name, "value").build());
+    }
+  }
+
+  #[test]
+  fn headers_set_by_the_client_are_refused() {
+    for name in ["bcc", "From", "REPLY-TO", "List-Unsubscribe"] {
+      assert_err!(builder().header(name, "value").build());
+    }
+  }
+
+  #[test]
+  fn headers_longer_than_a_line_are_refused() {
+    let value = "a".repeat(1000);
+    assert_err!(builder().header("X-Campaign", value).build());
+  }
+
+  #[test]
+  fn sender_names_must_fit_on_one_line() {
+    assert_ok!(builder().sender_name("Our \"newsletter\"").build());
+    assert_err!(builder().sender_name("Our\r\nnewsletter").build());
+    assert_err!(builder().sender_name(" ").build());
+  }
+
+  #[test]
+  fn tags_and_metadata_are_validated() {
+    assert_ok!(builder()
+      .tag("welcome")
+      .metadata("subscriber_id", "42")
+      .build());
+    assert_err!(builder().tag("_internal").build());
+    assert_err!(builder().tag("a".repeat(51)).build());
+    assert_err!(builder().metadata("subscriber id", "42").build());
+    let too_many = (0..11).fold(builder(), |b, i| b.tag(format!("tag{}", i)));
+    assert_err!(too_many.build());
+  }
+}
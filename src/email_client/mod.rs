@@ -0,0 +1,947 @@
+//! Outgoing email.
+//!
+//! Routes and the delivery worker go through `EmailClient`, which adds
+//! retries, unsubscribe links and the suppression list on top of whichever
+//! `EmailSender` the configuration picked.
+mod attachment;
+mod message;
+mod outcomes;
+mod providers;
+mod retry;
+mod sandbox;
+mod sender;
+
+use std::{
+  collections::{BTreeMap, HashSet},
+  future::Future,
+  sync::Arc,
+};
+
+use crate::{
+  domain::SubscriberEmail, suppressions::SuppressionList,
+  unsubscribe::UnsubscribeLinks,
+};
+use attachment::check_attachments_size;
+pub(crate) use sender::merge;
+use sender::merge_headers;
+use tracing::Instrument;
+
+pub use attachment::{Attachment, AttachmentError};
+pub use message::{Message, MessageBuilder, MessageError};
+pub use outcomes::store_send_outcomes;
+pub use providers::{
+  written_emails, FileSender, MandrillSender, PostmarkSender, SendGridSender,
+  SmtpSender, WrittenEmail,
+};
+pub use retry::RetryPolicy;
+pub use sandbox::Sandbox;
+pub use sender::{
+  BatchEmail, BatchRecipient, Email, EmailSender, RecipientStatus, SendError,
+  SendOutcome,
+};
+
+/// Merge variable holding each recipient's unsubscribe link, e.g. for the
+/// templates' footer.
+pub const UNSUBSCRIBE_URL: &str = "unsubscribe_url";
+
+#[derive(Clone)]
+pub struct EmailClient {
+  email_sender: Arc<dyn EmailSender>,
+  sender: SubscriberEmail,
+  retry_policy: RetryPolicy,
+  unsubscribe_links: UnsubscribeLinks,
+  sandbox: Option<Sandbox>,
+  suppressions: Option<SuppressionList>,
+}
+
+impl EmailClient {
+  pub fn new(
+    email_sender: impl EmailSender + 'static,
+    sender: SubscriberEmail,
+    retry_policy: RetryPolicy,
+    unsubscribe_links: UnsubscribeLinks,
+  ) -> Self {
+    Self {
+      email_sender: Arc::new(email_sender),
+      sender,
+      retry_policy,
+      unsubscribe_links,
+      sandbox: None,
+      suppressions: None,
+    }
+  }
+
+  /// Route every email through `sandbox` first.
+  pub fn with_sandbox(self, sandbox: Sandbox) -> Self {
+    Self {
+      sandbox: Some(sandbox),
+      ..self
+    }
+  }
+
+  /// Never send anything to addresses on `suppressions`.
+  pub fn with_suppressions(self, suppressions: SuppressionList) -> Self {
+    Self {
+      suppressions: Some(suppressions),
+      ..self
+    }
+  }
+
+  /// Make sure `attachments` fit within what the provider accepts, before
+  /// they are stored to be sent later.
+  pub fn check_attachments(
+    &self,
+    attachments: &[Attachment],
+  ) -> Result<(), AttachmentError> {
+    check_attachments_size(
+      attachments,
+      self.email_sender.max_attachments_size(),
+    )
+  }
+
+  /// Send an email with nothing but contents and attachments, see
+  /// `send_message`.
+  pub async fn send_email(
+    &self,
+    recipient: &SubscriberEmail,
+    subject: &str,
+    html_content: &str,
+    text_content: &str,
+    attachments: &[Attachment],
+  ) -> Result<SendOutcome, SendError> {
+    let message = Message {
+      attachments: attachments.to_vec(),
+      ..Message::new(subject, html_content, text_content)
+    };
+    self.send_message(recipient, &message).await
+  }
+
+  /// Send `message`, retrying transient failures according to the client's
+  /// `RetryPolicy`.
+  ///
+  /// `{{unsubscribe_url}}` in the contents is replaced with the recipient's
+  /// unsubscribe link, which the `List-Unsubscribe` headers point to as well.
+  ///
+  /// A provider refusing the recipient is not an error: check the returned
+  /// outcome's `status`. Attachments too large for the provider are, without
+  /// calling it.
+  ///
+  /// A suppressed recipient gets nothing, as `RecipientStatus::Suppressed`;
+  /// suppressed copies are left out.
+  ///
+  /// In the sandbox, the email may go to other addresses than requested or
+  /// be dropped, as `RecipientStatus::Dropped`; the outcome is still the
+  /// requested recipient's.
+  #[tracing::instrument(
+    name = "Send an email",
+    skip_all,
+    fields(attempts = tracing::field::Empty)
+  )]
+  pub async fn send_message(
+    &self,
+    recipient: &SubscriberEmail,
+    message: &Message,
+  ) -> Result<SendOutcome, SendError> {
+    check_attachments_size(
+      &message.attachments,
+      self.email_sender.max_attachments_size(),
+    )
+    .map_err(|e| SendError::Permanent(e.into()))?;
+    let suppressed = self
+      .suppressed(
+        std::iter::once(recipient)
+          .chain(&message.cc)
+          .chain(&message.bcc),
+      )
+      .await
+      .map_err(|e| SendError::Transient {
+        source: anyhow::Error::new(e)
+          .context("Failed to check the suppression list."),
+        retry_after: None,
+      })?;
+    if suppressed.contains(recipient.as_ref()) {
+      tracing::info!(
+        recipient = %recipient.as_ref(),
+        "Not sending an email to a suppressed address",
+      );
+      return Ok(SendOutcome::new(recipient, RecipientStatus::Suppressed));
+    }
+    let unsuppressed = |copies: &[SubscriberEmail]| {
+      copies
+        .iter()
+        .filter(|copy| !suppressed.contains(copy.as_ref()))
+        .cloned()
+        .collect::<Vec<_>>()
+    };
+    let (cc, bcc) = (unsuppressed(&message.cc), unsuppressed(&message.bcc));
+    let merge_vars = BTreeMap::from([(
+      UNSUBSCRIBE_URL.to_owned(),
+      self.unsubscribe_links.link(recipient),
+    )]);
+    let html_content = merge(&message.html_content, &merge_vars, true);
+    let text_content = merge(&message.text_content, &merge_vars, false);
+    let mut headers =
+      with_unsubscribe_headers(&message.headers, &merge_vars[UNSUBSCRIBE_URL]);
+    let rerouted = match &self.sandbox {
+      None => None,
+      Some(sandbox) => {
+        let original_recipients = std::iter::once(recipient)
+          .chain(&cc)
+          .chain(&bcc)
+          .map(AsRef::as_ref)
+          .collect::<Vec<_>>()
+          .join(", ");
+        match sandbox.reroute(recipient, &cc, &bcc) {
+          Some(rerouted) => {
+            tracing::info!(
+              %original_recipients,
+              sandbox_recipient = %rerouted.recipient.as_ref(),
+              "The sandbox redirected an email",
+            );
+            headers.extend(rerouted.headers.iter().cloned());
+            Some(rerouted)
+          }
+          None => {
+            tracing::info!(
+              %original_recipients,
+              "The sandbox dropped an email",
+            );
+            return Ok(SendOutcome::new(recipient, RecipientStatus::Dropped));
+          }
+        }
+      }
+    };
+    let email = Email {
+      sender: &self.sender,
+      sender_name: message.sender_name.as_deref(),
+      recipient: rerouted.as_ref().map_or(recipient, |r| &r.recipient),
+      reply_to: message.reply_to.as_ref(),
+      cc: rerouted.as_ref().map_or(&cc, |r| &r.cc),
+      bcc: rerouted.as_ref().map_or(&bcc, |r| &r.bcc),
+      subject: &message.subject,
+      html_content: &html_content,
+      text_content: &text_content,
+      attachments: &message.attachments,
+      headers: &headers,
+      tags: &message.tags,
+      metadata: &message.metadata,
+    };
+
+    let mut outcome =
+      self.with_retries(|| self.email_sender.send(&email)).await?;
+    outcome.recipient = recipient.as_ref().to_owned();
+    log_refusal(&outcome);
+    Ok(outcome)
+  }
+
+  /// Send a batch with nothing but contents and attachments, see
+  /// `send_batch_message`.
+  pub async fn send_batch(
+    &self,
+    recipients: &[BatchRecipient],
+    subject: &str,
+    html_content: &str,
+    text_content: &str,
+    attachments: &[Attachment],
+  ) -> Vec<SendOutcome> {
+    let message = Message {
+      attachments: attachments.to_vec(),
+      ..Message::new(subject, html_content, text_content)
+    };
+    self.send_batch_message(recipients, &message).await
+  }
+
+  /// Send `message` to every recipient, personalised with their merge
+  /// variables, in as few provider calls as the provider allows.
+  ///
+  /// Every recipient gets an `unsubscribe_url` merge variable, also used by
+  /// the `List-Unsubscribe` headers.
+  ///
+  /// Recipients of a chunk the provider could not be reached for, even after
+  /// retrying, are reported as `RecipientStatus::Failed`, as are all of them
+  /// when the attachments are too large for the provider or when `message`
+  /// has cc or bcc recipients, which would get a copy per recipient.
+  ///
+  /// Suppressed recipients get nothing, as `RecipientStatus::Suppressed`.
+  ///
+  /// In the sandbox, recipients get their emails one at a time through
+  /// `send_message`, as several of them may be redirected to the same
+  /// address.
+  #[tracing::instrument(
+    name = "Send a batch of emails",
+    skip_all,
+    fields(recipients = recipients.len(), attempts = tracing::field::Empty)
+  )]
+  pub async fn send_batch_message(
+    &self,
+    recipients: &[BatchRecipient],
+    message: &Message,
+  ) -> Vec<SendOutcome> {
+    let checked = check_attachments_size(
+      &message.attachments,
+      self.email_sender.max_attachments_size(),
+    )
+    .map_err(anyhow::Error::from)
+    .and_then(|_| {
+      if message.cc.is_empty() && message.bcc.is_empty() {
+        Ok(())
+      } else {
+        Err(anyhow::anyhow!("Batches can't have cc or bcc recipients."))
+      }
+    });
+    if let Err(e) = checked {
+      tracing::error!(error.message = %e, "Failed to send the batch");
+      return recipients
+        .iter()
+        .map(|recipient| {
+          SendOutcome::new(&recipient.email, RecipientStatus::Failed)
+        })
+        .collect();
+    }
+    let suppressed = match self
+      .suppressed(recipients.iter().map(|recipient| &recipient.email))
+      .await
+    {
+      Ok(suppressed) => suppressed,
+      Err(e) => {
+        tracing::error!(
+          error.cause_chain = ?e,
+          error.message = %e,
+          "Failed to check the suppression list",
+        );
+        return recipients
+          .iter()
+          .map(|recipient| {
+            SendOutcome::new(&recipient.email, RecipientStatus::Failed)
+          })
+          .collect();
+      }
+    };
+    if suppressed.is_empty() {
+      return self.deliver_batch(recipients, message).await;
+    }
+    tracing::info!(
+      n_suppressed = suppressed.len(),
+      "Not sending emails of the batch to suppressed addresses",
+    );
+    let unsuppressed: Vec<_> = recipients
+      .iter()
+      .filter(|recipient| !suppressed.contains(recipient.email.as_ref()))
+      .cloned()
+      .collect();
+    let mut outcomes =
+      self.deliver_batch(&unsuppressed, message).await.into_iter();
+    recipients
+      .iter()
+      .map(|recipient| {
+        if suppressed.contains(recipient.email.as_ref()) {
+          SendOutcome::new(&recipient.email, RecipientStatus::Suppressed)
+        } else {
+          outcomes.next().unwrap_or_else(|| {
+            SendOutcome::new(&recipient.email, RecipientStatus::Failed)
+          })
+        }
+      })
+      .collect()
+  }
+
+  /// Hand `recipients` over to the provider, one outcome per recipient, in
+  /// order.
+  async fn deliver_batch(
+    &self,
+    recipients: &[BatchRecipient],
+    message: &Message,
+  ) -> Vec<SendOutcome> {
+    if self.sandbox.is_some() {
+      let mut outcomes = Vec::with_capacity(recipients.len());
+      for recipient in recipients {
+        let message = personalise(message, &recipient.merge_vars);
+        let outcome = self
+          .send_message(&recipient.email, &message)
+          .await
+          .unwrap_or_else(|e| {
+            tracing::error!(
+              error.cause_chain = ?e,
+              error.message = %e,
+              "Failed to send an email of the batch",
+            );
+            SendOutcome::new(&recipient.email, RecipientStatus::Failed)
+          });
+        outcomes.push(outcome);
+      }
+      return outcomes;
+    }
+    let headers = with_unsubscribe_headers(
+      &message.headers,
+      &format!("{{{{{}}}}}", UNSUBSCRIBE_URL),
+    );
+    let mut outcomes = Vec::with_capacity(recipients.len());
+    for chunk in recipients.chunks(self.email_sender.max_batch_size().max(1)) {
+      let chunk: Vec<_> = chunk
+        .iter()
+        .map(|recipient| {
+          let mut recipient = recipient.clone();
+          recipient.merge_vars.insert(
+            UNSUBSCRIBE_URL.to_owned(),
+            self.unsubscribe_links.link(&recipient.email),
+          );
+          recipient
+        })
+        .collect();
+      let batch = BatchEmail {
+        sender: &self.sender,
+        sender_name: message.sender_name.as_deref(),
+        recipients: &chunk,
+        reply_to: message.reply_to.as_ref(),
+        subject: &message.subject,
+        html_content: &message.html_content,
+        text_content: &message.text_content,
+        attachments: &message.attachments,
+        headers: &headers,
+        tags: &message.tags,
+        metadata: &message.metadata,
+      };
+      match self
+        .with_retries(|| self.email_sender.send_batch(&batch))
+        .await
+      {
+        Ok(chunk_outcomes) => {
+          chunk_outcomes.iter().for_each(log_refusal);
+          outcomes.extend(chunk_outcomes);
+        }
+        Err(e) => {
+          tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to send a chunk of the batch",
+          );
+          outcomes.extend(chunk.iter().map(|recipient| {
+            SendOutcome::new(&recipient.email, RecipientStatus::Failed)
+          }));
+        }
+      }
+    }
+    outcomes
+  }
+
+  /// Which of `emails` are suppressed, none without a suppression list.
+  async fn suppressed<'a>(
+    &self,
+    emails: impl IntoIterator<Item = &'a SubscriberEmail>,
+  ) -> Result<HashSet<String>, sqlx::Error> {
+    match &self.suppressions {
+      None => Ok(HashSet::new()),
+      Some(suppressions) => suppressions.suppressed(emails).await,
+    }
+  }
+
+  /// Run `operation` until it succeeds, fails permanently or the
+  /// `RetryPolicy` gives up, recording the number of attempts on the
+  /// current span.
+  async fn with_retries<T, F, Fut>(
+    &self,
+    mut operation: F,
+  ) -> Result<T, SendError>
+  where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, SendError>>,
+  {
+    let mut attempt = 1;
+    loop {
+      tracing::Span::current().record("attempts", attempt);
+      let error = match operation()
+        .instrument(tracing::info_span!("Email API request", attempt))
+        .await
+      {
+        Ok(outcome) => return Ok(outcome),
+        Err(e) => e,
+      };
+      let retry_after = match &error {
+        SendError::Transient { retry_after, .. } => *retry_after,
+        SendError::Permanent(_) => return Err(error),
+      };
+      let delay =
+        match self.retry_policy.delay_before_retry(attempt, retry_after) {
+          Some(delay) => delay,
+          None => return Err(error),
+        };
+      tracing::warn!(
+        error.cause_chain = ?error,
+        attempt,
+        retry_in_milliseconds = delay.as_millis() as u64,
+        "Email API request failed, retrying",
+      );
+      tokio::time::sleep(delay).await;
+      attempt += 1;
+    }
+  }
+}
+
+/// `message` with `merge_vars` filled in, as providers do for batches.
+fn personalise(
+  message: &Message,
+  merge_vars: &BTreeMap<String, String>,
+) -> Message {
+  Message {
+    subject: merge(&message.subject, merge_vars, false),
+    html_content: merge(&message.html_content, merge_vars, true),
+    text_content: merge(&message.text_content, merge_vars, false),
+    headers: merge_headers(&message.headers, merge_vars),
+    ..message.clone()
+  }
+}
+
+/// `headers` followed by RFC 8058 one-click unsubscribe, which bulk senders
+/// must support.
+fn with_unsubscribe_headers(
+  headers: &[(String, String)],
+  unsubscribe_url: &str,
+) -> Vec<(String, String)> {
+  let mut headers = headers.to_vec();
+  headers.push(("List-Unsubscribe".into(), format!("<{}>", unsubscribe_url)));
+  headers.push((
+    "List-Unsubscribe-Post".into(),
+    "List-Unsubscribe=One-Click".into(),
+  ));
+  headers
+}
+
+/// Providers answer 200 for recipients they refuse, make sure it shows.
+fn log_refusal(outcome: &SendOutcome) {
+  if !outcome.is_accepted() {
+    tracing::warn!(
+      recipient = %outcome.recipient,
+      status = outcome.status.as_str(),
+      reject_reason = ?outcome.reject_reason,
+      "The email provider did not accept the email",
+    );
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use async_trait::async_trait;
+  use claim::{assert_err, assert_ok};
+  use fake::{
+    faker::{
+      internet::en::SafeEmail,
+      lorem::{en::Paragraph, en::Sentence},
+    },
+    Fake, Faker,
+  };
+  use reqwest::Client;
+  use secrecy::Secret;
+  use std::sync::Mutex;
+  use wiremock::{
+    matchers::{any, body_partial_json},
+    Mock, MockServer, Request, Respond, ResponseTemplate,
+  };
+
+  use crate::{domain::SubscriberEmail, unsubscribe::UnsubscribeLinks};
+
+  use super::{
+    Attachment, BatchEmail, BatchRecipient, Email, EmailClient, EmailSender,
+    MandrillSender, Message, RecipientStatus, RetryPolicy, Sandbox, SendError,
+    SendOutcome,
+  };
+
+  /// Generate a random email subject
+  fn subject() -> String {
+    Sentence(1..2).fake()
+  }
+
+  /// Generate some random email content
+  fn content() -> String {
+    Paragraph(1..10).fake()
+  }
+
+  /// Generate a random subscriber email
+  fn email() -> SubscriberEmail {
+    SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+  }
+
+  fn unsubscribe_links() -> UnsubscribeLinks {
+    UnsubscribeLinks::new(
+      "https://example.com".into(),
+      Secret::new(Faker.fake()),
+    )
+  }
+
+  /// Mandrill's answer when it accepted the email, for every recipient
+  fn sent() -> impl Respond {
+    |request: &Request| {
+      let body: serde_json::Value = serde_json::from_slice(&request.body)
+        .expect("Mandrill requests are JSON");
+      let statuses: Vec<_> = body["message"]["to"]
+        .as_array()
+        .expect("Mandrill requests have recipients")
+        .iter()
+        .map(|to| {
+          serde_json::json!({
+            "email": to["email"],
+            "status": "sent",
+            "_id": "abc123abc123abc123abc123abc123"
+          })
+        })
+        .collect();
+      ResponseTemplate::new(200).set_body_json(statuses)
+    }
+  }
+
+  /// Get a test instance of `EmailClient`
+  fn email_client(base_url: String) -> EmailClient {
+    let http_client = Client::builder()
+      .timeout(Duration::from_millis(200))
+      .build()
+      .unwrap();
+    EmailClient::new(
+      MandrillSender::new(http_client, base_url, Secret::new(Faker.fake())),
+      email(),
+      RetryPolicy {
+        max_attempts: 3,
+        base_delay: Duration::from_millis(10),
+        max_delay: Duration::from_secs(2),
+        jitter: 0.0,
+      },
+      unsubscribe_links(),
+    )
+  }
+
+  #[tokio::test]
+  async fn send_email_retries_server_errors_until_it_succeeds() {
+    let mock_server = MockServer::start().await;
+    let email_client = email_client(mock_server.uri());
+
+    Mock::given(any())
+      .respond_with(ResponseTemplate::new(500))
+      .up_to_n_times(2)
+      .expect(2)
+      .mount(&mock_server)
+      .await;
+    Mock::given(any())
+      .respond_with(sent())
+      .expect(1)
+      .mount(&mock_server)
+      .await;
+
+    let outcome = email_client
+      .send_email(&email(), &subject(), &content(), &content(), &[])
+      .await;
+
+    assert_ok!(outcome);
+  }
+
+  #[tokio::test]
+  async fn send_email_gives_up_after_max_attempts() {
+    let mock_server = MockServer::start().await;
+    let email_client = email_client(mock_server.uri());
+
+    Mock::given(any())
+      .respond_with(ResponseTemplate::new(503))
+      .expect(3)
+      .mount(&mock_server)
+      .await;
+
+    let outcome = email_client
+      .send_email(&email(), &subject(), &content(), &content(), &[])
+      .await;
+
+    assert_err!(outcome);
+  }
+
+  #[tokio::test]
+  async fn send_email_does_not_retry_client_errors() {
+    let mock_server = MockServer::start().await;
+    let email_client = email_client(mock_server.uri());
+
+    Mock::given(any())
+      .respond_with(ResponseTemplate::new(400))
+      .expect(1)
+      .mount(&mock_server)
+      .await;
+
+    let outcome = email_client
+      .send_email(&email(), &subject(), &content(), &content(), &[])
+      .await;
+
+    assert_err!(outcome);
+  }
+
+  #[tokio::test]
+  async fn send_email_retries_timeouts() {
+    let mock_server = MockServer::start().await;
+    let email_client = email_client(mock_server.uri());
+
+    Mock::given(any())
+      .respond_with(
+        ResponseTemplate::new(200).set_delay(Duration::from_secs(180)),
+      )
+      .up_to_n_times(1)
+      .expect(1)
+      .mount(&mock_server)
+      .await;
+    Mock::given(any())
+      .respond_with(sent())
+      .expect(1)
+      .mount(&mock_server)
+      .await;
+
+    let outcome = email_client
+      .send_email(&email(), &subject(), &content(), &content(), &[])
+      .await;
+
+    assert_ok!(outcome);
+  }
+
+  #[tokio::test]
+  async fn send_email_honors_retry_after() {
+    let mock_server = MockServer::start().await;
+    let email_client = email_client(mock_server.uri());
+
+    Mock::given(any())
+      .respond_with(
+        ResponseTemplate::new(429).insert_header("Retry-After", "1"),
+      )
+      .up_to_n_times(1)
+      .expect(1)
+      .mount(&mock_server)
+      .await;
+    Mock::given(any())
+      .respond_with(sent())
+      .expect(1)
+      .mount(&mock_server)
+      .await;
+
+    let started = std::time::Instant::now();
+    let outcome = email_client
+      .send_email(&email(), &subject(), &content(), &content(), &[])
+      .await;
+
+    assert_ok!(outcome);
+    assert!(started.elapsed() >= Duration::from_secs(1));
+  }
+
+  #[tokio::test]
+  async fn send_email_links_to_the_recipients_unsubscribe_page() {
+    let mock_server = MockServer::start().await;
+    let email_client = email_client(mock_server.uri());
+    let recipient = email();
+    let link = email_client.unsubscribe_links.link(&recipient);
+
+    Mock::given(body_partial_json(serde_json::json!({
+      "message": {
+        "text": format!("Unsubscribe: {}", link),
+        "headers": {
+          "List-Unsubscribe": format!("<{}>", link),
+          "List-Unsubscribe-Post": "List-Unsubscribe=One-Click"
+        }
+      }
+    })))
+    .respond_with(sent())
+    .expect(1)
+    .mount(&mock_server)
+    .await;
+
+    let outcome = email_client
+      .send_email(
+        &recipient,
+        &subject(),
+        &content(),
+        "Unsubscribe: {{unsubscribe_url}}",
+        &[],
+      )
+      .await;
+
+    assert_ok!(outcome);
+  }
+
+  #[tokio::test]
+  async fn send_email_in_the_sandbox_goes_to_the_catch_all_address() {
+    let mock_server = MockServer::start().await;
+    let catch_all = email();
+    let email_client = email_client(mock_server.uri())
+      .with_sandbox(Sandbox::new(vec![], Some(catch_all.clone())));
+    let recipient = email();
+
+    Mock::given(body_partial_json(serde_json::json!({
+      "message": {
+        "to": [{ "email": catch_all.as_ref() }],
+        "headers": { "X-Original-To": recipient.as_ref() }
+      }
+    })))
+    .respond_with(sent())
+    .expect(1)
+    .mount(&mock_server)
+    .await;
+
+    let outcome = email_client
+      .send_email(&recipient, &subject(), &content(), &content(), &[])
+      .await
+      .unwrap();
+
+    assert_eq!(outcome.recipient, recipient.as_ref());
+  }
+
+  #[tokio::test]
+  async fn send_batch_in_the_sandbox_drops_emails_outside_the_allowlist() {
+    let mock_server = MockServer::start().await;
+    let allowed = email();
+    let email_client = email_client(mock_server.uri())
+      .with_sandbox(Sandbox::new(vec![allowed.clone()], None));
+    let recipients =
+      vec![BatchRecipient::new(allowed), BatchRecipient::new(email())];
+
+    Mock::given(any())
+      .respond_with(sent())
+      .expect(1)
+      .mount(&mock_server)
+      .await;
+
+    let outcomes = email_client
+      .send_batch(&recipients, &subject(), &content(), &content(), &[])
+      .await;
+
+    let statuses: Vec<_> = outcomes.iter().map(|o| o.status).collect();
+    assert_eq!(
+      statuses,
+      vec![RecipientStatus::Sent, RecipientStatus::Dropped]
+    );
+  }
+
+  #[tokio::test]
+  async fn send_email_refuses_attachments_too_large_for_the_provider() {
+    let mock_server = MockServer::start().await;
+    let email_client = email_client(mock_server.uri());
+    let attachment = Attachment::new(
+      "issue.pdf".into(),
+      "application/pdf",
+      vec![0; 20 * 1024 * 1024],
+    )
+    .unwrap();
+
+    Mock::given(any())
+      .respond_with(sent())
+      .expect(0)
+      .mount(&mock_server)
+      .await;
+
+    let outcome = email_client
+      .send_email(&email(), &subject(), &content(), &content(), &[attachment])
+      .await;
+
+    assert!(matches!(outcome, Err(SendError::Permanent(_))));
+  }
+
+  /// Records the size of every batch, queuing every recipient.
+  #[derive(Default)]
+  struct ChunkRecorder {
+    batch_sizes: std::sync::Arc<Mutex<Vec<usize>>>,
+  }
+
+  #[async_trait]
+  impl EmailSender for ChunkRecorder {
+    async fn send(&self, _: &Email<'_>) -> Result<SendOutcome, SendError> {
+      Err(SendError::Permanent(anyhow::anyhow!(
+        "ChunkRecorder only records batches"
+      )))
+    }
+
+    fn max_batch_size(&self) -> usize {
+      2
+    }
+
+    async fn send_batch(
+      &self,
+      batch: &BatchEmail<'_>,
+    ) -> Result<Vec<SendOutcome>, SendError> {
+      self
+        .batch_sizes
+        .lock()
+        .unwrap()
+        .push(batch.recipients.len());
+      Ok(
+        batch
+          .recipients
+          .iter()
+          .map(|recipient| {
+            SendOutcome::new(&recipient.email, RecipientStatus::Queued)
+          })
+          .collect(),
+      )
+    }
+  }
+
+  #[tokio::test]
+  async fn send_batch_chunks_recipients_to_the_provider_limit() {
+    let recorder = ChunkRecorder::default();
+    let batch_sizes = recorder.batch_sizes.clone();
+    let email_client = EmailClient::new(
+      recorder,
+      email(),
+      RetryPolicy::no_retries(),
+      unsubscribe_links(),
+    );
+    let recipients: Vec<_> =
+      (0..5).map(|_| BatchRecipient::new(email())).collect();
+
+    let outcomes = email_client
+      .send_batch(&recipients, &subject(), &content(), &content(), &[])
+      .await;
+
+    assert_eq!(*batch_sizes.lock().unwrap(), vec![2, 2, 1]);
+    assert_eq!(outcomes.len(), 5);
+    for (recipient, outcome) in recipients.iter().zip(&outcomes) {
+      assert_eq!(recipient.email.as_ref(), outcome.recipient);
+    }
+  }
+
+  #[tokio::test]
+  async fn send_batch_reports_failed_chunks_per_recipient() {
+    let mock_server = MockServer::start().await;
+    let email_client = email_client(mock_server.uri());
+    let recipients: Vec<_> =
+      (0..2).map(|_| BatchRecipient::new(email())).collect();
+
+    Mock::given(any())
+      .respond_with(ResponseTemplate::new(500))
+      .expect(3)
+      .mount(&mock_server)
+      .await;
+
+    let outcomes = email_client
+      .send_batch(&recipients, &subject(), &content(), &content(), &[])
+      .await;
+
+    assert_eq!(outcomes.len(), 2);
+    assert!(outcomes
+      .iter()
+      .all(|outcome| outcome.status == RecipientStatus::Failed));
+  }
+
+  #[tokio::test]
+  async fn send_batch_message_refuses_copies() {
+    let recorder = ChunkRecorder::default();
+    let batch_sizes = recorder.batch_sizes.clone();
+    let email_client = EmailClient::new(
+      recorder,
+      email(),
+      RetryPolicy::no_retries(),
+      unsubscribe_links(),
+    );
+    let recipients: Vec<_> =
+      (0..2).map(|_| BatchRecipient::new(email())).collect();
+    let message = Message::builder(subject(), content(), content())
+      .cc(email())
+      .build()
+      .unwrap();
+
+    let outcomes = email_client.send_batch_message(&recipients, &message).await;
+
+    assert!(batch_sizes.lock().unwrap().is_empty());
+    assert!(outcomes
+      .iter()
+      .all(|outcome| outcome.status == RecipientStatus::Failed));
+  }
+}
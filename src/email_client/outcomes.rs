@@ -0,0 +1,48 @@
+use chrono::Utc;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::SendOutcome;
+
+/// Record what the provider answered for each recipient.
+///
+/// `newsletter_issue_id` is `None` for emails that aren't part of an issue,
+/// e.g. subscription confirmations.
+#[tracing::instrument(
+  skip(pool, outcomes),
+  fields(n_outcomes = outcomes.len())
+)]
+pub async fn store_send_outcomes(
+  pool: &PgPool,
+  newsletter_issue_id: Option<Uuid>,
+  outcomes: &[SendOutcome],
+) -> Result<(), sqlx::Error> {
+  let mut transaction = pool.begin().await?;
+  let recorded_at = Utc::now();
+  for outcome in outcomes {
+    sqlx::query!(
+      r#"
+      INSERT INTO send_outcomes (
+        send_outcome_id,
+        recipient,
+        status,
+        reject_reason,
+        provider_message_id,
+        newsletter_issue_id,
+        recorded_at
+      )
+      VALUES ($1, $2, $3, $4, $5, $6, $7)
+      "#,
+      Uuid::new_v4(),
+      outcome.recipient,
+      outcome.status.as_str(),
+      outcome.reject_reason,
+      outcome.message_id,
+      newsletter_issue_id,
+      recorded_at
+    )
+    .execute(&mut transaction)
+    .await?;
+  }
+  transaction.commit().await
+}
@@ -0,0 +1,168 @@
+use std::{
+  io,
+  path::{Path, PathBuf},
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use lettre::message::header::MessageId;
+use uuid::Uuid;
+
+use super::mime::build_message;
+use crate::email_client::{
+  Email, EmailSender, RecipientStatus, SendError, SendOutcome,
+};
+
+/// Writes emails to a directory instead of delivering them, to inspect them
+/// during local development and in tests, see `written_emails`.
+///
+/// Emails are the very messages `SmtpSender` would relay, one per file.
+pub struct FileSender {
+  directory: PathBuf,
+  maildir: bool,
+}
+
+impl FileSender {
+  /// Write `{directory}/{name}.eml` files, names sorting in sending order.
+  pub fn eml(directory: PathBuf) -> Self {
+    Self {
+      directory,
+      maildir: false,
+    }
+  }
+
+  /// Deliver to the Maildir at `directory`, for mail clients to read.
+  pub fn maildir(directory: PathBuf) -> Self {
+    Self {
+      directory,
+      maildir: true,
+    }
+  }
+
+  async fn write(&self, name: &str, content: &[u8]) -> io::Result<()> {
+    if !self.maildir {
+      tokio::fs::create_dir_all(&self.directory).await?;
+      return tokio::fs::write(
+        self.directory.join(format!("{}.eml", name)),
+        content,
+      )
+      .await;
+    }
+    for subdirectory in ["tmp", "new", "cur"] {
+      tokio::fs::create_dir_all(self.directory.join(subdirectory)).await?;
+    }
+    // Written in `tmp/` first, so readers never see half an email in `new/`.
+    let tmp = self.directory.join("tmp").join(name);
+    tokio::fs::write(&tmp, content).await?;
+    tokio::fs::rename(&tmp, self.directory.join("new").join(name)).await
+  }
+}
+
+#[async_trait]
+impl EmailSender for FileSender {
+  async fn send(&self, email: &Email<'_>) -> Result<SendOutcome, SendError> {
+    let message = build_message(email).map_err(SendError::Permanent)?;
+    let message_id = message
+      .headers()
+      .get::<MessageId>()
+      .map(|id| id.as_ref().to_owned());
+    let timestamp = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .unwrap_or_default()
+      .as_micros();
+    let name = format!("{}.{}", timestamp, Uuid::new_v4());
+    self
+      .write(&name, &message.formatted())
+      .await
+      .map_err(|e| SendError::Permanent(e.into()))?;
+    Ok(SendOutcome {
+      message_id,
+      ..SendOutcome::new(email.recipient, RecipientStatus::Sent)
+    })
+  }
+}
+
+/// An email written by `FileSender`.
+#[derive(Debug)]
+pub struct WrittenEmail {
+  pub path: PathBuf,
+  /// The whole message, headers and body
+  pub content: String,
+}
+
+impl WrittenEmail {
+  /// Value of the first `name` header, unfolded.
+  pub fn header(&self, name: &str) -> Option<String> {
+    let headers = self.content.split("\r\n\r\n").next()?;
+    let mut value: Option<String> = None;
+    for line in headers.split("\r\n") {
+      match (&mut value, line.starts_with([' ', '\t'])) {
+        (Some(value), true) => value.push_str(line),
+        (Some(_), false) => break,
+        (None, true) => {}
+        (None, false) => {
+          value = line
+            .split_once(':')
+            .filter(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.trim_start().to_owned());
+        }
+      }
+    }
+    value
+  }
+}
+
+/// Every email `FileSender` wrote to `directory`, oldest first, whether as
+/// `.eml` files or in a Maildir.
+///
+/// Meant for tests, to check what would have been sent.
+pub fn written_emails(directory: &Path) -> io::Result<Vec<WrittenEmail>> {
+  let mut paths = vec![];
+  for directory in [
+    directory.to_owned(),
+    directory.join("new"),
+    directory.join("cur"),
+  ] {
+    if !directory.is_dir() {
+      continue;
+    }
+    for entry in std::fs::read_dir(directory)? {
+      let path = entry?.path();
+      if path.is_file() {
+        paths.push(path);
+      }
+    }
+  }
+  paths.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+  paths
+    .into_iter()
+    .map(|path| {
+      let content = std::fs::read_to_string(&path)?;
+      Ok(WrittenEmail { path, content })
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::PathBuf;
+
+  use super::WrittenEmail;
+
+  #[test]
+  fn headers_are_unfolded_and_case_insensitive() {
+    let email = WrittenEmail {
+      path: PathBuf::new(),
+      content: "From: a@example.com\r\nSubject: A very\r\n long subject\r\n\
+        To: b@example.com\r\n\r\nSubject: not a header\r\n"
+        .into(),
+    };
+
+    assert_eq!(
+      email.header("subject").as_deref(),
+      Some("A very long subject")
+    );
+    assert_eq!(email.header("To").as_deref(), Some("b@example.com"));
+    assert_eq!(email.header("Cc"), None);
+  }
+}
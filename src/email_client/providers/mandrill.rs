@@ -0,0 +1,695 @@
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use reqwest::{Client, Response};
+use secrecy::{ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+
+use super::send_request;
+use crate::domain::SubscriberEmail;
+use crate::email_client::{
+  Attachment, BatchEmail, Email, EmailSender, RecipientStatus, SendError,
+  SendOutcome,
+};
+
+/// Delivers through Mandrill's `/messages` endpoint.
+pub struct MandrillSender {
+  http_client: Client,
+  base_url: String,
+  api_key: Secret<String>,
+}
+
+impl MandrillSender {
+  pub fn new(
+    http_client: Client,
+    base_url: String,
+    api_key: Secret<String>,
+  ) -> Self {
+    Self {
+      http_client,
+      base_url,
+      api_key,
+    }
+  }
+}
+
+#[async_trait]
+impl EmailSender for MandrillSender {
+  async fn send(&self, email: &Email<'_>) -> Result<SendOutcome, SendError> {
+    let url = format!("{}/messages", self.base_url);
+    let to = std::iter::once(SendEmailMessageRecipient {
+      email: email.recipient.as_ref(),
+      r#type: None,
+    })
+    .chain(SendEmailMessageRecipient::copies(email.cc, "cc"))
+    .chain(SendEmailMessageRecipient::copies(email.bcc, "bcc"))
+    .collect();
+    let request_body = SendEmailMessageRequest {
+      key: self.api_key.expose_secret(),
+      message: SendEmailMessage {
+        from_email: email.sender.as_ref(),
+        from_name: email.sender_name,
+        to,
+        subject: email.subject,
+        html: email.html_content,
+        text: email.text_content,
+        // Otherwise cc recipients would not show up in the `Cc` header.
+        preserve_recipients: (!email.cc.is_empty()).then_some(true),
+        merge_language: None,
+        merge_vars: vec![],
+        attachments: MandrillFile::attachments(email.attachments),
+        images: MandrillFile::images(email.attachments),
+        headers: headers(email.headers, email.reply_to),
+        tags: email.tags,
+        metadata: email.metadata,
+      },
+    };
+    let response =
+      send_request(self.http_client.post(&url).json(&request_body)).await?;
+    // The cc and bcc recipients get an outcome too, in no particular order.
+    let outcomes = parse_outcomes(response).await?;
+    outcomes
+      .into_iter()
+      .find(|outcome| {
+        outcome
+          .recipient
+          .eq_ignore_ascii_case(email.recipient.as_ref())
+      })
+      .ok_or_else(|| {
+        SendError::Permanent(anyhow::anyhow!(
+          "Mandrill did not report on the recipient."
+        ))
+      })
+  }
+
+  fn max_batch_size(&self) -> usize {
+    1000
+  }
+
+  /// Mandrill caps whole messages at 25MB, keep some room for the rest.
+  fn max_attachments_size(&self) -> usize {
+    20 * 1024 * 1024
+  }
+
+  async fn send_batch(
+    &self,
+    batch: &BatchEmail<'_>,
+  ) -> Result<Vec<SendOutcome>, SendError> {
+    let url = format!("{}/messages", self.base_url);
+    let request_body = SendEmailMessageRequest {
+      key: self.api_key.expose_secret(),
+      message: SendEmailMessage {
+        from_email: batch.sender.as_ref(),
+        from_name: batch.sender_name,
+        to: batch
+          .recipients
+          .iter()
+          .map(|recipient| SendEmailMessageRecipient {
+            email: recipient.email.as_ref(),
+            r#type: None,
+          })
+          .collect(),
+        subject: batch.subject,
+        html: batch.html_content,
+        text: batch.text_content,
+        // Each recipient gets their own copy, only showing their address.
+        preserve_recipients: Some(false),
+        merge_language: Some("handlebars"),
+        merge_vars: batch
+          .recipients
+          .iter()
+          .map(|recipient| RecipientMergeVars {
+            rcpt: recipient.email.as_ref(),
+            vars: recipient
+              .merge_vars
+              .iter()
+              .map(|(name, content)| MergeVar { name, content })
+              .collect(),
+          })
+          .collect(),
+        attachments: MandrillFile::attachments(batch.attachments),
+        images: MandrillFile::images(batch.attachments),
+        // Mandrill applies merge variables to headers too.
+        headers: headers(batch.headers, batch.reply_to),
+        tags: batch.tags,
+        metadata: batch.metadata,
+      },
+    };
+    let response =
+      send_request(self.http_client.post(&url).json(&request_body)).await?;
+    // Mandrill neither promises to keep the order nor to report on everybody.
+    let mut outcomes = parse_outcomes(response).await?;
+    Ok(
+      batch
+        .recipients
+        .iter()
+        .map(|recipient| {
+          match outcomes.iter().position(|outcome| {
+            outcome
+              .recipient
+              .eq_ignore_ascii_case(recipient.email.as_ref())
+          }) {
+            Some(index) => outcomes.swap_remove(index),
+            None => SendOutcome {
+              reject_reason: Some("Mandrill did not report on them".into()),
+              ..SendOutcome::new(&recipient.email, RecipientStatus::Failed)
+            },
+          }
+        })
+        .collect(),
+    )
+  }
+}
+
+/// Mandrill has no field of its own for `Reply-To`.
+fn headers<'a>(
+  headers: &'a [(String, String)],
+  reply_to: Option<&'a SubscriberEmail>,
+) -> BTreeMap<&'a str, &'a str> {
+  headers
+    .iter()
+    .map(|(name, value)| (name.as_str(), value.as_str()))
+    .chain(reply_to.map(|reply_to| ("Reply-To", reply_to.as_ref())))
+    .collect()
+}
+
+/// Decode the per-recipient statuses Mandrill answers `/messages` with.
+async fn parse_outcomes(
+  response: Response,
+) -> Result<Vec<SendOutcome>, SendError> {
+  let statuses: Vec<SendEmailMessageStatus> = response
+    .json()
+    .await
+    .context("Failed to parse the response from Mandrill.")
+    .map_err(SendError::Permanent)?;
+  Ok(
+    statuses
+      .into_iter()
+      .map(|status| SendOutcome {
+        status: match status.status.as_str() {
+          "sent" => RecipientStatus::Sent,
+          "queued" | "scheduled" => RecipientStatus::Queued,
+          "invalid" => RecipientStatus::Invalid,
+          _ => RecipientStatus::Rejected,
+        },
+        recipient: status.email,
+        reject_reason: status.reject_reason,
+        message_id: status.id,
+      })
+      .collect(),
+  )
+}
+
+#[derive(Serialize)]
+struct SendEmailMessage<'a> {
+  from_email: &'a str,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  from_name: Option<&'a str>,
+  to: Vec<SendEmailMessageRecipient<'a>>,
+  subject: &'a str,
+  html: &'a str,
+  text: &'a str,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  preserve_recipients: Option<bool>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  merge_language: Option<&'a str>,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  merge_vars: Vec<RecipientMergeVars<'a>>,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  attachments: Vec<MandrillFile<'a>>,
+  /// Inline images, referred to as `cid:{name}` in the HTML
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  images: Vec<MandrillFile<'a>>,
+  #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+  headers: BTreeMap<&'a str, &'a str>,
+  #[serde(skip_serializing_if = "<[_]>::is_empty")]
+  tags: &'a [String],
+  #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+  metadata: &'a BTreeMap<String, String>,
+}
+
+#[derive(Serialize)]
+struct MandrillFile<'a> {
+  r#type: &'a str,
+  name: &'a str,
+  /// Base64-encoded
+  content: String,
+}
+
+impl<'a> MandrillFile<'a> {
+  fn attachments(attachments: &'a [Attachment]) -> Vec<Self> {
+    attachments
+      .iter()
+      .filter(|attachment| attachment.content_id().is_none())
+      .map(|attachment| Self {
+        r#type: attachment.content_type(),
+        name: attachment.filename(),
+        content: attachment.encoded_content(),
+      })
+      .collect()
+  }
+
+  fn images(attachments: &'a [Attachment]) -> Vec<Self> {
+    attachments
+      .iter()
+      .filter_map(|attachment| {
+        Some(Self {
+          r#type: attachment.content_type(),
+          name: attachment.content_id()?,
+          content: attachment.encoded_content(),
+        })
+      })
+      .collect()
+  }
+}
+
+#[derive(Serialize)]
+struct SendEmailMessageRecipient<'a> {
+  email: &'a str,
+  /// `cc` or `bcc`, `to` when left out
+  #[serde(skip_serializing_if = "Option::is_none")]
+  r#type: Option<&'a str>,
+}
+
+impl<'a> SendEmailMessageRecipient<'a> {
+  fn copies(
+    addresses: &'a [SubscriberEmail],
+    r#type: &'a str,
+  ) -> impl Iterator<Item = Self> + 'a {
+    addresses.iter().map(move |address| Self {
+      email: address.as_ref(),
+      r#type: Some(r#type),
+    })
+  }
+}
+
+#[derive(Serialize)]
+struct RecipientMergeVars<'a> {
+  rcpt: &'a str,
+  vars: Vec<MergeVar<'a>>,
+}
+
+#[derive(Serialize)]
+struct MergeVar<'a> {
+  name: &'a str,
+  content: &'a str,
+}
+
+#[derive(Serialize)]
+struct SendEmailMessageRequest<'a> {
+  key: &'a str,
+  message: SendEmailMessage<'a>,
+}
+
+/// One entry of the array Mandrill answers `/messages` with
+#[derive(Deserialize)]
+struct SendEmailMessageStatus {
+  email: String,
+  status: String,
+  reject_reason: Option<String>,
+  #[serde(rename = "_id")]
+  id: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+  use claim::{assert_err, assert_ok};
+  use fake::{Fake, Faker};
+  use reqwest::Client;
+  use secrecy::Secret;
+  use wiremock::{
+    matchers::{body_partial_json, header, method, path},
+    Mock, MockServer, ResponseTemplate,
+  };
+
+  use super::MandrillSender;
+  use crate::email_client::{
+    providers::test_helpers::{batch_recipients, EmailParts},
+    EmailSender, RecipientStatus, SendOutcome,
+  };
+
+  struct SendEmailBodyMatcher;
+
+  impl wiremock::Match for SendEmailBodyMatcher {
+    fn matches(&self, request: &wiremock::Request) -> bool {
+      let result: Result<serde_json::Value, _> =
+        serde_json::from_slice(&request.body);
+
+      if let Ok(body) = result {
+        body.get("key").is_some()
+          && body["message"].get("from_email").is_some()
+          && body["message"]["to"][0].get("email").is_some()
+          && body["message"].get("subject").is_some()
+          && body["message"].get("html").is_some()
+          && body["message"].get("text").is_some()
+      } else {
+        false
+      }
+    }
+  }
+
+  #[tokio::test]
+  async fn send_sends_the_expected_request() {
+    let mock_server = MockServer::start().await;
+    let sender = MandrillSender::new(
+      Client::new(),
+      mock_server.uri(),
+      Secret::new(Faker.fake()),
+    );
+
+    let parts = EmailParts::generate();
+
+    Mock::given(header("Content-Type", "application/json"))
+      .and(path("/messages"))
+      .and(method("POST"))
+      .and(SendEmailBodyMatcher)
+      .respond_with(ResponseTemplate::new(200).set_body_json(
+        serde_json::json!([
+          { "email": parts.recipient.as_ref(), "status": "sent", "_id": "1" }
+        ]),
+      ))
+      .expect(1)
+      .mount(&mock_server)
+      .await;
+
+    assert_ok!(sender.send(&parts.email()).await);
+  }
+
+  #[tokio::test]
+  async fn send_splits_attachments_from_inline_images() {
+    let mock_server = MockServer::start().await;
+    let sender = MandrillSender::new(
+      Client::new(),
+      mock_server.uri(),
+      Secret::new(Faker.fake()),
+    );
+
+    let parts = EmailParts::generate().with_attachments();
+
+    Mock::given(path("/messages"))
+      .and(method("POST"))
+      .and(body_partial_json(serde_json::json!({
+        "message": {
+          "attachments": [{
+            "type": "application/pdf",
+            "name": "issue.pdf",
+            "content": base64::encode("%PDF-1.4")
+          }],
+          "images": [{
+            "type": "image/png",
+            "name": "logo",
+            "content": base64::encode(b"\x89PNG")
+          }]
+        }
+      })))
+      .respond_with(ResponseTemplate::new(200).set_body_json(
+        serde_json::json!([
+          { "email": parts.recipient.as_ref(), "status": "sent", "_id": "1" }
+        ]),
+      ))
+      .expect(1)
+      .mount(&mock_server)
+      .await;
+
+    assert_ok!(sender.send(&parts.email()).await);
+  }
+
+  #[tokio::test]
+  async fn send_includes_the_envelope() {
+    let mock_server = MockServer::start().await;
+    let sender = MandrillSender::new(
+      Client::new(),
+      mock_server.uri(),
+      Secret::new(Faker.fake()),
+    );
+    let parts = EmailParts::generate().with_envelope();
+
+    Mock::given(path("/messages"))
+      .and(method("POST"))
+      .and(body_partial_json(serde_json::json!({
+        "message": {
+          "from_name": "Our newsletter",
+          "to": [
+            { "email": parts.recipient.as_ref() },
+            { "email": "cc@example.com", "type": "cc" },
+            { "email": "bcc@example.com", "type": "bcc" }
+          ],
+          "preserve_recipients": true,
+          "headers": { "Reply-To": "editor@example.com" },
+          "tags": ["issue"],
+          "metadata": { "issue_id": "42" }
+        }
+      })))
+      .respond_with(ResponseTemplate::new(200).set_body_json(
+        serde_json::json!([
+          { "email": parts.recipient.as_ref(), "status": "sent", "_id": "1" },
+          { "email": "cc@example.com", "status": "sent", "_id": "2" },
+          { "email": "bcc@example.com", "status": "sent", "_id": "3" }
+        ]),
+      ))
+      .expect(1)
+      .mount(&mock_server)
+      .await;
+
+    let outcome = sender.send(&parts.email()).await.unwrap();
+
+    assert_eq!(outcome.message_id.as_deref(), Some("1"));
+  }
+
+  #[tokio::test]
+  async fn send_picks_the_recipient_among_the_copies() {
+    let mock_server = MockServer::start().await;
+    let sender = MandrillSender::new(
+      Client::new(),
+      mock_server.uri(),
+      Secret::new(Faker.fake()),
+    );
+    let parts = EmailParts::generate().with_envelope();
+
+    Mock::given(path("/messages"))
+      .respond_with(ResponseTemplate::new(200).set_body_json(
+        serde_json::json!([
+          { "email": "bcc@example.com", "status": "sent", "_id": "3" },
+          {
+            "email": parts.recipient.as_ref().to_uppercase(),
+            "status": "queued",
+            "_id": "1"
+          },
+          { "email": "cc@example.com", "status": "sent", "_id": "2" }
+        ]),
+      ))
+      .expect(1)
+      .mount(&mock_server)
+      .await;
+
+    let outcome = sender.send(&parts.email()).await.unwrap();
+
+    assert_eq!(outcome.status, RecipientStatus::Queued);
+    assert_eq!(outcome.message_id.as_deref(), Some("1"));
+  }
+
+  #[tokio::test]
+  async fn send_fails_when_the_recipient_is_not_reported_on() {
+    let mock_server = MockServer::start().await;
+    let sender = MandrillSender::new(
+      Client::new(),
+      mock_server.uri(),
+      Secret::new(Faker.fake()),
+    );
+    let parts = EmailParts::generate().with_envelope();
+
+    Mock::given(path("/messages"))
+      .respond_with(ResponseTemplate::new(200).set_body_json(
+        serde_json::json!([
+          { "email": "cc@example.com", "status": "sent", "_id": "2" }
+        ]),
+      ))
+      .expect(1)
+      .mount(&mock_server)
+      .await;
+
+    assert_err!(sender.send(&parts.email()).await);
+  }
+
+  #[tokio::test]
+  async fn send_reports_rejections_with_their_reason() {
+    let mock_server = MockServer::start().await;
+    let sender = MandrillSender::new(
+      Client::new(),
+      mock_server.uri(),
+      Secret::new(Faker.fake()),
+    );
+
+    let parts = EmailParts::generate();
+
+    Mock::given(path("/messages"))
+      .respond_with(ResponseTemplate::new(200).set_body_json(
+        serde_json::json!([{
+          "email": parts.recipient.as_ref(),
+          "status": "rejected",
+          "reject_reason": "hard-bounce",
+          "_id": "abc123"
+        }]),
+      ))
+      .expect(1)
+      .mount(&mock_server)
+      .await;
+
+    let outcome = sender.send(&parts.email()).await.unwrap();
+
+    assert_eq!(
+      outcome,
+      SendOutcome {
+        recipient: parts.recipient.as_ref().to_owned(),
+        status: RecipientStatus::Rejected,
+        reject_reason: Some("hard-bounce".into()),
+        message_id: Some("abc123".into()),
+      }
+    );
+  }
+
+  #[tokio::test]
+  async fn send_fails_on_an_unexpected_response() {
+    let mock_server = MockServer::start().await;
+    let sender = MandrillSender::new(
+      Client::new(),
+      mock_server.uri(),
+      Secret::new(Faker.fake()),
+    );
+
+    Mock::given(path("/messages"))
+      .respond_with(ResponseTemplate::new(200).set_body_string("OK"))
+      .expect(1)
+      .mount(&mock_server)
+      .await;
+
+    let parts = EmailParts::generate();
+    assert_err!(sender.send(&parts.email()).await);
+  }
+
+  #[tokio::test]
+  async fn send_batch_hides_recipients_and_parses_their_status() {
+    let mock_server = MockServer::start().await;
+    let sender = MandrillSender::new(
+      Client::new(),
+      mock_server.uri(),
+      Secret::new(Faker.fake()),
+    );
+    let parts = EmailParts::generate().with_headers();
+    let recipients = batch_recipients(&["a@example.com", "b@example.com"]);
+
+    Mock::given(path("/messages"))
+      .and(method("POST"))
+      .and(body_partial_json(serde_json::json!({
+        "message": {
+          "to": [{ "email": "a@example.com" }, { "email": "b@example.com" }],
+          "preserve_recipients": false,
+          "merge_language": "handlebars",
+          "headers": {
+            "List-Unsubscribe": "<https://example.com/unsubscribe/{{email}}>"
+          },
+          "merge_vars": [
+            {
+              "rcpt": "a@example.com",
+              "vars": [{ "name": "email", "content": "a@example.com" }]
+            },
+            {
+              "rcpt": "b@example.com",
+              "vars": [{ "name": "email", "content": "b@example.com" }]
+            }
+          ]
+        }
+      })))
+      .respond_with(ResponseTemplate::new(200).set_body_json(
+        serde_json::json!([
+          { "email": "a@example.com", "status": "sent", "_id": "1" },
+          {
+            "email": "b@example.com",
+            "status": "rejected",
+            "reject_reason": "hard-bounce",
+            "_id": "2"
+          }
+        ]),
+      ))
+      .expect(1)
+      .mount(&mock_server)
+      .await;
+
+    let outcomes = sender.send_batch(&parts.batch(&recipients)).await.unwrap();
+
+    assert_eq!(
+      outcomes,
+      vec![
+        SendOutcome {
+          recipient: "a@example.com".into(),
+          status: RecipientStatus::Sent,
+          reject_reason: None,
+          message_id: Some("1".into()),
+        },
+        SendOutcome {
+          recipient: "b@example.com".into(),
+          status: RecipientStatus::Rejected,
+          reject_reason: Some("hard-bounce".into()),
+          message_id: Some("2".into()),
+        },
+      ]
+    );
+
+    // Mandrill's order is not the recipients'.
+    mock_server.reset().await;
+    Mock::given(path("/messages"))
+      .respond_with(ResponseTemplate::new(200).set_body_json(
+        serde_json::json!([
+          {
+            "email": "B@example.com",
+            "status": "rejected",
+            "reject_reason": "hard-bounce",
+            "_id": "2"
+          },
+          { "email": "a@example.com", "status": "sent", "_id": "1" }
+        ]),
+      ))
+      .expect(1)
+      .mount(&mock_server)
+      .await;
+
+    let shuffled = sender.send_batch(&parts.batch(&recipients)).await.unwrap();
+
+    assert_eq!(
+      shuffled
+        .iter()
+        .map(|outcome| (outcome.status, outcome.message_id.as_deref()))
+        .collect::<Vec<_>>(),
+      vec![
+        (RecipientStatus::Sent, Some("1")),
+        (RecipientStatus::Rejected, Some("2")),
+      ]
+    );
+
+    // Nor does it report on every recipient.
+    mock_server.reset().await;
+    Mock::given(path("/messages"))
+      .respond_with(ResponseTemplate::new(200).set_body_json(
+        serde_json::json!([
+          { "email": "b@example.com", "status": "sent", "_id": "2" }
+        ]),
+      ))
+      .expect(1)
+      .mount(&mock_server)
+      .await;
+
+    let partial = sender.send_batch(&parts.batch(&recipients)).await.unwrap();
+
+    assert_eq!(
+      partial
+        .iter()
+        .map(|outcome| (outcome.recipient.as_str(), outcome.status))
+        .collect::<Vec<_>>(),
+      vec![
+        ("a@example.com", RecipientStatus::Failed),
+        ("b@example.com", RecipientStatus::Sent),
+      ]
+    );
+  }
+}
@@ -0,0 +1,83 @@
+//! RFC 5322 messages, for transports handing over the email as a whole.
+use lettre::message::{
+  header::{ContentType, HeaderName, HeaderValue},
+  Attachment as MimeAttachment, Mailbox, Message, MultiPart,
+};
+
+use crate::email_client::{Attachment, Email};
+
+/// `multipart/alternative` for the text and HTML parts, wrapped in
+/// `multipart/related` with any inline images, itself wrapped in
+/// `multipart/mixed` with any other attachments.
+///
+/// Tags and metadata have no equivalent in the message itself, they are
+/// left out, as are bcc recipients, which only appear in the envelope.
+pub(super) fn build_message(
+  email: &Email<'_>,
+) -> Result<Message, anyhow::Error> {
+  let from = Mailbox::new(
+    email.sender_name.map(str::to_owned),
+    email.sender.as_ref().parse()?,
+  );
+  let to: Mailbox = email.recipient.as_ref().parse()?;
+  let mut body = MultiPart::alternative_plain_html(
+    email.text_content.to_owned(),
+    email.html_content.to_owned(),
+  );
+  let (inline, attached): (Vec<_>, Vec<_>) = email
+    .attachments
+    .iter()
+    .partition(|attachment| attachment.content_id().is_some());
+  if !inline.is_empty() {
+    body = inline.into_iter().try_fold(
+      MultiPart::related().multipart(body),
+      |related, image| {
+        let part = MimeAttachment::new_inline_with_name(
+          image.content_id().unwrap_or_default().to_owned(),
+          image.filename().to_owned(),
+        )
+        .body(image.content().to_vec(), content_type(image)?);
+        Ok::<_, anyhow::Error>(related.singlepart(part))
+      },
+    )?;
+  }
+  if !attached.is_empty() {
+    body = attached.into_iter().try_fold(
+      MultiPart::mixed().multipart(body),
+      |mixed, file| {
+        let part = MimeAttachment::new(file.filename().to_owned())
+          .body(file.content().to_vec(), content_type(file)?);
+        Ok::<_, anyhow::Error>(mixed.singlepart(part))
+      },
+    )?;
+  }
+  let mut builder = Message::builder()
+    .from(from)
+    .to(to)
+    .subject(email.subject)
+    .message_id(None);
+  if let Some(reply_to) = email.reply_to {
+    builder = builder.reply_to(reply_to.as_ref().parse()?);
+  }
+  for cc in email.cc {
+    builder = builder.cc(cc.as_ref().parse()?);
+  }
+  for bcc in email.bcc {
+    builder = builder.bcc(bcc.as_ref().parse()?);
+  }
+  let builder =
+    email
+      .headers
+      .iter()
+      .try_fold(builder, |builder, (name, value)| {
+        let name = HeaderName::new_from_ascii(name.clone())?;
+        Ok::<_, anyhow::Error>(
+          builder.raw_header(HeaderValue::new(name, value.clone())),
+        )
+      })?;
+  Ok(builder.multipart(body)?)
+}
+
+fn content_type(attachment: &Attachment) -> Result<ContentType, anyhow::Error> {
+  Ok(ContentType::parse(attachment.content_type())?)
+}
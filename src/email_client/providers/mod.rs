@@ -0,0 +1,229 @@
+//! `EmailSender` implementations for the APIs and relays we can deliver through.
+mod file;
+mod mandrill;
+mod mime;
+mod postmark;
+mod sendgrid;
+mod smtp;
+
+pub use file::{written_emails, FileSender, WrittenEmail};
+pub use mandrill::MandrillSender;
+pub use postmark::PostmarkSender;
+pub use sendgrid::SendGridSender;
+pub use smtp::SmtpSender;
+
+use std::time::{Duration, SystemTime};
+
+use anyhow::Context;
+use lettre::message::Mailbox;
+use reqwest::{header::RETRY_AFTER, RequestBuilder, Response, StatusCode};
+
+use super::SendError;
+use crate::domain::SubscriberEmail;
+
+/// Send `request`, classifying failures as transient or permanent.
+async fn send_request(request: RequestBuilder) -> Result<Response, SendError> {
+  let response = request.send().await.map_err(|e| {
+    if e.is_timeout() || e.is_connect() {
+      SendError::Transient {
+        source: e.into(),
+        retry_after: None,
+      }
+    } else {
+      SendError::Permanent(e.into())
+    }
+  })?;
+  let status = response.status();
+  let retry_after = retry_after(&response);
+  match response.error_for_status() {
+    Ok(response) => Ok(response),
+    Err(e)
+      if status == StatusCode::TOO_MANY_REQUESTS
+        || status.is_server_error() =>
+    {
+      Err(SendError::Transient {
+        source: e.into(),
+        retry_after,
+      })
+    }
+    Err(e) => Err(SendError::Permanent(e.into())),
+  }
+}
+
+/// `address` preceded by `name`, quoted as needed, for APIs taking a single
+/// `From` string.
+fn mailbox(
+  name: Option<&str>,
+  address: &SubscriberEmail,
+) -> Result<String, SendError> {
+  let address = address
+    .as_ref()
+    .parse()
+    .context("Failed to parse the sender's address.")
+    .map_err(SendError::Permanent)?;
+  Ok(Mailbox::new(name.map(str::to_owned), address).to_string())
+}
+
+/// Parse `Retry-After`, given either as delay-seconds or as an HTTP date.
+fn retry_after(response: &Response) -> Option<Duration> {
+  let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+  if let Ok(seconds) = value.parse::<u64>() {
+    return Some(Duration::from_secs(seconds));
+  }
+  let date = httpdate::parse_http_date(value).ok()?;
+  Some(
+    date
+      .duration_since(SystemTime::now())
+      .unwrap_or(Duration::ZERO),
+  )
+}
+
+#[cfg(test)]
+mod test_helpers {
+  use std::collections::BTreeMap;
+
+  use fake::{
+    faker::{
+      internet::en::SafeEmail,
+      lorem::{en::Paragraph, en::Sentence},
+    },
+    Fake,
+  };
+
+  use crate::{
+    domain::SubscriberEmail,
+    email_client::{Attachment, BatchEmail, BatchRecipient, Email},
+  };
+
+  /// Owned parts of a random email, to borrow an `Email` from.
+  pub struct EmailParts {
+    pub sender: SubscriberEmail,
+    pub recipient: SubscriberEmail,
+    pub subject: String,
+    pub html_content: String,
+    pub text_content: String,
+    pub attachments: Vec<Attachment>,
+    pub headers: Vec<(String, String)>,
+    pub sender_name: Option<String>,
+    pub reply_to: Option<SubscriberEmail>,
+    pub cc: Vec<SubscriberEmail>,
+    pub bcc: Vec<SubscriberEmail>,
+    pub tags: Vec<String>,
+    pub metadata: BTreeMap<String, String>,
+  }
+
+  impl EmailParts {
+    pub fn generate() -> Self {
+      Self {
+        sender: SubscriberEmail::parse(SafeEmail().fake()).unwrap(),
+        recipient: SubscriberEmail::parse(SafeEmail().fake()).unwrap(),
+        subject: Sentence(1..2).fake(),
+        html_content: Paragraph(1..10).fake(),
+        text_content: Paragraph(1..10).fake(),
+        attachments: vec![],
+        headers: vec![],
+        sender_name: None,
+        reply_to: None,
+        cc: vec![],
+        bcc: vec![],
+        tags: vec![],
+        metadata: BTreeMap::new(),
+      }
+    }
+
+    pub fn email(&self) -> Email<'_> {
+      Email {
+        sender: &self.sender,
+        sender_name: self.sender_name.as_deref(),
+        recipient: &self.recipient,
+        reply_to: self.reply_to.as_ref(),
+        cc: &self.cc,
+        bcc: &self.bcc,
+        subject: &self.subject,
+        html_content: &self.html_content,
+        text_content: &self.text_content,
+        attachments: &self.attachments,
+        headers: &self.headers,
+        tags: &self.tags,
+        metadata: &self.metadata,
+      }
+    }
+
+    pub fn batch<'a>(
+      &'a self,
+      recipients: &'a [BatchRecipient],
+    ) -> BatchEmail<'a> {
+      BatchEmail {
+        sender: &self.sender,
+        sender_name: self.sender_name.as_deref(),
+        recipients,
+        reply_to: self.reply_to.as_ref(),
+        subject: &self.subject,
+        html_content: &self.html_content,
+        text_content: &self.text_content,
+        attachments: &self.attachments,
+        headers: &self.headers,
+        tags: &self.tags,
+        metadata: &self.metadata,
+      }
+    }
+
+    /// A `List-Unsubscribe` header, personalised with the `email` merge
+    /// variable in batches.
+    pub fn with_headers(mut self) -> Self {
+      self.headers = vec![(
+        "List-Unsubscribe".into(),
+        "<https://example.com/unsubscribe/{{email}}>".into(),
+      )];
+      self
+    }
+
+    /// A sender name, a reply-to address, copies, a tag and metadata.
+    pub fn with_envelope(mut self) -> Self {
+      let address = |email: &str| SubscriberEmail::parse(email.into()).unwrap();
+      self.sender_name = Some("Our newsletter".into());
+      self.reply_to = Some(address("editor@example.com"));
+      self.cc = vec![address("cc@example.com")];
+      self.bcc = vec![address("bcc@example.com")];
+      self.tags = vec!["issue".into()];
+      self.metadata = BTreeMap::from([("issue_id".into(), "42".into())]);
+      self
+    }
+
+    /// A PDF and an inline logo.
+    pub fn with_attachments(mut self) -> Self {
+      self.attachments = vec![
+        Attachment::new(
+          "issue.pdf".into(),
+          "application/pdf",
+          b"%PDF-1.4".to_vec(),
+        )
+        .unwrap(),
+        Attachment::inline(
+          "logo".into(),
+          "logo.png".into(),
+          "image/png",
+          b"\x89PNG".to_vec(),
+        )
+        .unwrap(),
+      ];
+      self
+    }
+  }
+
+  /// Recipients with their own address as their only merge variable.
+  pub fn batch_recipients(emails: &[&str]) -> Vec<BatchRecipient> {
+    emails
+      .iter()
+      .map(|email| {
+        let mut recipient = BatchRecipient::new(
+          SubscriberEmail::parse(email.to_string()).unwrap(),
+        );
+        recipient
+          .merge_vars
+          .insert("email".into(), email.to_string());
+        recipient
+      })
+      .collect()
+  }
+}
@@ -0,0 +1,453 @@
+use anyhow::Context;
+use async_trait::async_trait;
+use reqwest::Client;
+use secrecy::{ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+
+use super::{mailbox, send_request};
+use crate::domain::SubscriberEmail;
+use crate::email_client::{
+  sender::{merge, merge_headers},
+  Attachment, BatchEmail, Email, EmailSender, RecipientStatus, SendError,
+  SendOutcome,
+};
+
+/// Delivers through Postmark's `/email` endpoint.
+pub struct PostmarkSender {
+  http_client: Client,
+  base_url: String,
+  server_token: Secret<String>,
+}
+
+impl PostmarkSender {
+  pub fn new(
+    http_client: Client,
+    base_url: String,
+    server_token: Secret<String>,
+  ) -> Self {
+    Self {
+      http_client,
+      base_url,
+      server_token,
+    }
+  }
+}
+
+#[async_trait]
+impl EmailSender for PostmarkSender {
+  async fn send(&self, email: &Email<'_>) -> Result<SendOutcome, SendError> {
+    let url = format!("{}/email", self.base_url);
+    let attachments = PostmarkAttachment::all(email.attachments);
+    let request_body = SendEmailRequest {
+      from: mailbox(email.sender_name, email.sender)?,
+      to: email.recipient.as_ref(),
+      reply_to: email.reply_to.map(AsRef::as_ref),
+      cc: addresses(email.cc),
+      bcc: addresses(email.bcc),
+      subject: email.subject,
+      html_body: email.html_content,
+      text_body: email.text_content,
+      attachments: &attachments,
+      headers: PostmarkHeader::all(email.headers),
+      tag: email.tags.first().map(String::as_str),
+      metadata: email.metadata,
+    };
+    let request = self
+      .http_client
+      .post(&url)
+      .header("Accept", "application/json")
+      .header("X-Postmark-Server-Token", self.server_token.expose_secret())
+      .json(&request_body);
+    let response = send_request(request).await?;
+    let response: SendEmailResponse = response
+      .json()
+      .await
+      .context("Failed to parse the response from Postmark.")
+      .map_err(SendError::Permanent)?;
+    Ok(response.into_outcome(email.recipient))
+  }
+
+  fn max_batch_size(&self) -> usize {
+    500
+  }
+
+  /// Postmark's 10MB limit covers the whole message, attachments included.
+  fn max_attachments_size(&self) -> usize {
+    9 * 1024 * 1024
+  }
+
+  /// Postmark has no merge variables outside of its own templates, so every
+  /// recipient gets a separate message, merged on our side.
+  async fn send_batch(
+    &self,
+    batch: &BatchEmail<'_>,
+  ) -> Result<Vec<SendOutcome>, SendError> {
+    let url = format!("{}/email/batch", self.base_url);
+    let merged: Vec<_> = batch
+      .recipients
+      .iter()
+      .map(|recipient| {
+        (
+          merge(batch.subject, &recipient.merge_vars, false),
+          merge(batch.html_content, &recipient.merge_vars, true),
+          merge(batch.text_content, &recipient.merge_vars, false),
+          merge_headers(batch.headers, &recipient.merge_vars),
+        )
+      })
+      .collect();
+    // Encoded once, but repeated in every message of the batch.
+    let attachments = PostmarkAttachment::all(batch.attachments);
+    let from = mailbox(batch.sender_name, batch.sender)?;
+    let request_body: Vec<_> = batch
+      .recipients
+      .iter()
+      .zip(&merged)
+      .map(|(recipient, (subject, html_body, text_body, headers))| {
+        SendEmailRequest {
+          from: from.clone(),
+          to: recipient.email.as_ref(),
+          reply_to: batch.reply_to.map(AsRef::as_ref),
+          cc: None,
+          bcc: None,
+          subject,
+          html_body,
+          text_body,
+          attachments: &attachments,
+          headers: PostmarkHeader::all(headers),
+          tag: batch.tags.first().map(String::as_str),
+          metadata: batch.metadata,
+        }
+      })
+      .collect();
+    let request = self
+      .http_client
+      .post(&url)
+      .header("Accept", "application/json")
+      .header("X-Postmark-Server-Token", self.server_token.expose_secret())
+      .json(&request_body);
+    let response = send_request(request).await?;
+    let responses: Vec<SendEmailResponse> = response
+      .json()
+      .await
+      .context("Failed to parse the response from Postmark.")
+      .map_err(SendError::Permanent)?;
+    // Postmark answers in request order, but leaves `To` out of errors.
+    Ok(
+      batch
+        .recipients
+        .iter()
+        .zip(responses)
+        .map(|(recipient, response)| response.into_outcome(&recipient.email))
+        .collect(),
+    )
+  }
+}
+
+/// Postmark's API error code for malformed addresses
+const INVALID_EMAIL_REQUEST: u32 = 300;
+
+/// Postmark takes several addresses as a comma-separated list.
+fn addresses(addresses: &[SubscriberEmail]) -> Option<String> {
+  let addresses: Vec<_> = addresses.iter().map(AsRef::as_ref).collect();
+  (!addresses.is_empty()).then(|| addresses.join(","))
+}
+
+/// Postmark takes a single tag, only the first one is sent.
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailRequest<'a> {
+  from: String,
+  to: &'a str,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  reply_to: Option<&'a str>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  cc: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  bcc: Option<String>,
+  subject: &'a str,
+  html_body: &'a str,
+  text_body: &'a str,
+  #[serde(skip_serializing_if = "<[_]>::is_empty")]
+  attachments: &'a [PostmarkAttachment<'a>],
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  headers: Vec<PostmarkHeader<'a>>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  tag: Option<&'a str>,
+  #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+  metadata: &'a BTreeMap<String, String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct PostmarkHeader<'a> {
+  name: &'a str,
+  value: &'a str,
+}
+
+impl<'a> PostmarkHeader<'a> {
+  fn all(headers: &'a [(String, String)]) -> Vec<Self> {
+    headers
+      .iter()
+      .map(|(name, value)| Self { name, value })
+      .collect()
+  }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct PostmarkAttachment<'a> {
+  name: &'a str,
+  /// Base64-encoded
+  content: String,
+  content_type: &'a str,
+  /// `cid:{content_id}` for inline attachments
+  #[serde(rename = "ContentID", skip_serializing_if = "Option::is_none")]
+  content_id: Option<String>,
+}
+
+impl<'a> PostmarkAttachment<'a> {
+  fn all(attachments: &'a [Attachment]) -> Vec<Self> {
+    attachments
+      .iter()
+      .map(|attachment| Self {
+        name: attachment.filename(),
+        content: attachment.encoded_content(),
+        content_type: attachment.content_type(),
+        content_id: attachment
+          .content_id()
+          .map(|content_id| format!("cid:{}", content_id)),
+      })
+      .collect()
+  }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailResponse {
+  error_code: u32,
+  message: String,
+  #[serde(rename = "MessageID")]
+  message_id: Option<String>,
+}
+
+impl SendEmailResponse {
+  fn into_outcome(self, recipient: &SubscriberEmail) -> SendOutcome {
+    let status = match self.error_code {
+      0 => RecipientStatus::Sent,
+      INVALID_EMAIL_REQUEST => RecipientStatus::Invalid,
+      _ => RecipientStatus::Rejected,
+    };
+    SendOutcome {
+      reject_reason: (status != RecipientStatus::Sent).then_some(self.message),
+      message_id: self.message_id,
+      ..SendOutcome::new(recipient, status)
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use claim::assert_ok;
+  use fake::{Fake, Faker};
+  use reqwest::Client;
+  use secrecy::Secret;
+  use wiremock::{
+    matchers::{body_partial_json, header, header_exists, method, path},
+    Mock, MockServer, ResponseTemplate,
+  };
+
+  use super::PostmarkSender;
+  use crate::email_client::{
+    providers::test_helpers::{batch_recipients, EmailParts},
+    EmailSender, RecipientStatus,
+  };
+
+  struct SendEmailBodyMatcher;
+
+  impl wiremock::Match for SendEmailBodyMatcher {
+    fn matches(&self, request: &wiremock::Request) -> bool {
+      let result: Result<serde_json::Value, _> =
+        serde_json::from_slice(&request.body);
+
+      if let Ok(body) = result {
+        body.get("From").is_some()
+          && body.get("To").is_some()
+          && body.get("Subject").is_some()
+          && body.get("HtmlBody").is_some()
+          && body.get("TextBody").is_some()
+      } else {
+        false
+      }
+    }
+  }
+
+  #[tokio::test]
+  async fn send_sends_the_expected_request() {
+    let mock_server = MockServer::start().await;
+    let sender = PostmarkSender::new(
+      Client::new(),
+      mock_server.uri(),
+      Secret::new(Faker.fake()),
+    );
+
+    Mock::given(header_exists("X-Postmark-Server-Token"))
+      .and(header("Content-Type", "application/json"))
+      .and(path("/email"))
+      .and(method("POST"))
+      .and(SendEmailBodyMatcher)
+      .respond_with(ResponseTemplate::new(200).set_body_json(
+        serde_json::json!({
+          "To": "a@example.com",
+          "SubmittedAt": "2022-06-30T10:04:21.1435314-04:00",
+          "MessageID": "0a129aee-e1cd-480d-b08d-4f48548ff48d",
+          "ErrorCode": 0,
+          "Message": "OK"
+        }),
+      ))
+      .expect(1)
+      .mount(&mock_server)
+      .await;
+
+    let parts = EmailParts::generate();
+    assert_ok!(sender.send(&parts.email()).await);
+  }
+
+  #[tokio::test]
+  async fn send_includes_attachments() {
+    let mock_server = MockServer::start().await;
+    let sender = PostmarkSender::new(
+      Client::new(),
+      mock_server.uri(),
+      Secret::new(Faker.fake()),
+    );
+
+    Mock::given(path("/email"))
+      .and(method("POST"))
+      .and(body_partial_json(serde_json::json!({
+        "Attachments": [
+          {
+            "Name": "issue.pdf",
+            "Content": base64::encode("%PDF-1.4"),
+            "ContentType": "application/pdf"
+          },
+          {
+            "Name": "logo.png",
+            "Content": base64::encode(b"\x89PNG"),
+            "ContentType": "image/png",
+            "ContentID": "cid:logo"
+          }
+        ]
+      })))
+      .respond_with(ResponseTemplate::new(200).set_body_json(
+        serde_json::json!({
+          "MessageID": "0a129aee-e1cd-480d-b08d-4f48548ff48d",
+          "ErrorCode": 0,
+          "Message": "OK"
+        }),
+      ))
+      .expect(1)
+      .mount(&mock_server)
+      .await;
+
+    let parts = EmailParts::generate().with_attachments();
+    assert_ok!(sender.send(&parts.email()).await);
+  }
+
+  #[tokio::test]
+  async fn send_includes_the_envelope() {
+    let mock_server = MockServer::start().await;
+    let sender = PostmarkSender::new(
+      Client::new(),
+      mock_server.uri(),
+      Secret::new(Faker.fake()),
+    );
+
+    Mock::given(path("/email"))
+      .and(method("POST"))
+      .and(body_partial_json(serde_json::json!({
+        "ReplyTo": "editor@example.com",
+        "Cc": "cc@example.com",
+        "Bcc": "bcc@example.com",
+        "Tag": "issue",
+        "Metadata": { "issue_id": "42" }
+      })))
+      .respond_with(ResponseTemplate::new(200).set_body_json(
+        serde_json::json!({
+          "MessageID": "0a129aee-e1cd-480d-b08d-4f48548ff48d",
+          "ErrorCode": 0,
+          "Message": "OK"
+        }),
+      ))
+      .expect(1)
+      .mount(&mock_server)
+      .await;
+
+    let parts = EmailParts::generate().with_envelope();
+    assert_ok!(sender.send(&parts.email()).await);
+  }
+
+  #[tokio::test]
+  async fn send_batch_sends_one_merged_message_per_recipient() {
+    let mock_server = MockServer::start().await;
+    let sender = PostmarkSender::new(
+      Client::new(),
+      mock_server.uri(),
+      Secret::new(Faker.fake()),
+    );
+    let mut parts = EmailParts::generate().with_headers();
+    parts.text_content = "Sent to {{email}}".into();
+    let recipients = batch_recipients(&["a@example.com", "b@example.com"]);
+
+    Mock::given(path("/email/batch"))
+      .and(method("POST"))
+      .and(body_partial_json(serde_json::json!([
+        {
+          "To": "a@example.com",
+          "TextBody": "Sent to a@example.com",
+          "Headers": [{
+            "Name": "List-Unsubscribe",
+            "Value": "<https://example.com/unsubscribe/a@example.com>"
+          }]
+        },
+        {
+          "To": "b@example.com",
+          "TextBody": "Sent to b@example.com",
+          "Headers": [{
+            "Name": "List-Unsubscribe",
+            "Value": "<https://example.com/unsubscribe/b@example.com>"
+          }]
+        }
+      ])))
+      .respond_with(ResponseTemplate::new(200).set_body_json(
+        serde_json::json!([
+          {
+            "ErrorCode": 0,
+            "Message": "OK",
+            "MessageID": "b7bc2f4a-e38e-4336-af7d-e6c392c2f817",
+            "To": "a@example.com"
+          },
+          {
+            "ErrorCode": 406,
+            "Message": "You tried to send to a recipient that has been marked as inactive."
+          }
+        ]),
+      ))
+      .expect(1)
+      .mount(&mock_server)
+      .await;
+
+    let outcomes = sender.send_batch(&parts.batch(&recipients)).await.unwrap();
+
+    assert_eq!(outcomes[0].recipient, "a@example.com");
+    assert_eq!(outcomes[0].status, RecipientStatus::Sent);
+    assert_eq!(
+      outcomes[0].message_id.as_deref(),
+      Some("b7bc2f4a-e38e-4336-af7d-e6c392c2f817")
+    );
+    assert_eq!(outcomes[1].recipient, "b@example.com");
+    assert_eq!(outcomes[1].status, RecipientStatus::Rejected);
+    assert!(outcomes[1].reject_reason.is_some());
+  }
+}
@@ -0,0 +1,436 @@
+use async_trait::async_trait;
+use reqwest::Client;
+use secrecy::{ExposeSecret, Secret};
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use super::send_request;
+use crate::domain::SubscriberEmail;
+use crate::email_client::{
+  sender::merge_headers, Attachment, BatchEmail, Email, EmailSender,
+  RecipientStatus, SendError, SendOutcome,
+};
+
+/// Delivers through SendGrid's v3 `/mail/send` endpoint.
+pub struct SendGridSender {
+  http_client: Client,
+  base_url: String,
+  api_key: Secret<String>,
+}
+
+impl SendGridSender {
+  pub fn new(
+    http_client: Client,
+    base_url: String,
+    api_key: Secret<String>,
+  ) -> Self {
+    Self {
+      http_client,
+      base_url,
+      api_key,
+    }
+  }
+}
+
+#[async_trait]
+impl EmailSender for SendGridSender {
+  async fn send(&self, email: &Email<'_>) -> Result<SendOutcome, SendError> {
+    let url = format!("{}/v3/mail/send", self.base_url);
+    let request_body = SendMailRequest {
+      personalizations: vec![Personalization {
+        to: vec![Address::new(email.recipient)],
+        cc: email.cc.iter().map(Address::new).collect(),
+        bcc: email.bcc.iter().map(Address::new).collect(),
+        substitutions: BTreeMap::new(),
+        headers: email.headers.to_vec(),
+      }],
+      from: Address {
+        name: email.sender_name,
+        ..Address::new(email.sender)
+      },
+      reply_to: email.reply_to.map(Address::new),
+      subject: email.subject,
+      // SendGrid requires the plain text part to come first.
+      content: vec![
+        Content {
+          r#type: "text/plain",
+          value: email.text_content,
+        },
+        Content {
+          r#type: "text/html",
+          value: email.html_content,
+        },
+      ],
+      attachments: SendGridAttachment::all(email.attachments),
+      categories: email.tags,
+      custom_args: email.metadata,
+    };
+    let request = self
+      .http_client
+      .post(&url)
+      .bearer_auth(self.api_key.expose_secret())
+      .json(&request_body);
+    let response = send_request(request).await?;
+    Ok(SendOutcome {
+      message_id: message_id(&response),
+      ..SendOutcome::new(email.recipient, RecipientStatus::Queued)
+    })
+  }
+
+  fn max_batch_size(&self) -> usize {
+    1000
+  }
+
+  /// SendGrid accepts requests of up to 30MB, whole message included.
+  fn max_attachments_size(&self) -> usize {
+    25 * 1024 * 1024
+  }
+
+  /// One personalization per recipient, so nobody sees the others' address.
+  ///
+  /// SendGrid substitutes the same value in the HTML and in the plain text
+  /// part, so merge variables are not escaped for HTML here.
+  async fn send_batch(
+    &self,
+    batch: &BatchEmail<'_>,
+  ) -> Result<Vec<SendOutcome>, SendError> {
+    let url = format!("{}/v3/mail/send", self.base_url);
+    let request_body = SendMailRequest {
+      personalizations: batch
+        .recipients
+        .iter()
+        .map(|recipient| Personalization {
+          to: vec![Address::new(&recipient.email)],
+          cc: vec![],
+          bcc: vec![],
+          substitutions: recipient
+            .merge_vars
+            .iter()
+            .map(|(name, value)| (format!("{{{{{}}}}}", name), value.as_str()))
+            .collect(),
+          headers: merge_headers(batch.headers, &recipient.merge_vars),
+        })
+        .collect(),
+      from: Address {
+        name: batch.sender_name,
+        ..Address::new(batch.sender)
+      },
+      reply_to: batch.reply_to.map(Address::new),
+      subject: batch.subject,
+      content: vec![
+        Content {
+          r#type: "text/plain",
+          value: batch.text_content,
+        },
+        Content {
+          r#type: "text/html",
+          value: batch.html_content,
+        },
+      ],
+      attachments: SendGridAttachment::all(batch.attachments),
+      categories: batch.tags,
+      custom_args: batch.metadata,
+    };
+    let request = self
+      .http_client
+      .post(&url)
+      .bearer_auth(self.api_key.expose_secret())
+      .json(&request_body);
+    // A 202 with an empty body: SendGrid only tells us it queued the batch.
+    let response = send_request(request).await?;
+    let message_id = message_id(&response);
+    Ok(
+      batch
+        .recipients
+        .iter()
+        .map(|recipient| SendOutcome {
+          message_id: message_id.clone(),
+          ..SendOutcome::new(&recipient.email, RecipientStatus::Queued)
+        })
+        .collect(),
+    )
+  }
+}
+
+/// SendGrid identifies the whole request with `X-Message-Id`, its webhooks
+/// report it as the prefix of each recipient's `sg_message_id`.
+fn message_id(response: &reqwest::Response) -> Option<String> {
+  response
+    .headers()
+    .get("X-Message-Id")
+    .and_then(|value| value.to_str().ok())
+    .map(str::to_owned)
+}
+
+#[derive(Serialize)]
+struct SendMailRequest<'a> {
+  personalizations: Vec<Personalization<'a>>,
+  from: Address<'a>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  reply_to: Option<Address<'a>>,
+  subject: &'a str,
+  content: Vec<Content<'a>>,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  attachments: Vec<SendGridAttachment<'a>>,
+  /// SendGrid's name for tags
+  #[serde(skip_serializing_if = "<[_]>::is_empty")]
+  categories: &'a [String],
+  /// SendGrid's name for metadata
+  #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+  custom_args: &'a BTreeMap<String, String>,
+}
+
+#[derive(Serialize)]
+struct Personalization<'a> {
+  to: Vec<Address<'a>>,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  cc: Vec<Address<'a>>,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  bcc: Vec<Address<'a>>,
+  #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+  substitutions: BTreeMap<String, &'a str>,
+  /// Headers differ per recipient, e.g. `List-Unsubscribe`
+  #[serde(
+    skip_serializing_if = "Vec::is_empty",
+    serialize_with = "serialize_headers"
+  )]
+  headers: Vec<(String, String)>,
+}
+
+/// SendGrid expects headers as a JSON object.
+fn serialize_headers<S: serde::Serializer>(
+  headers: &[(String, String)],
+  serializer: S,
+) -> Result<S::Ok, S::Error> {
+  serializer.collect_map(headers.iter().map(|(name, value)| (name, value)))
+}
+
+#[derive(Serialize)]
+struct Address<'a> {
+  email: &'a str,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  name: Option<&'a str>,
+}
+
+impl<'a> Address<'a> {
+  fn new(email: &'a SubscriberEmail) -> Self {
+    Self {
+      email: email.as_ref(),
+      name: None,
+    }
+  }
+}
+
+#[derive(Serialize)]
+struct Content<'a> {
+  r#type: &'a str,
+  value: &'a str,
+}
+
+#[derive(Serialize)]
+struct SendGridAttachment<'a> {
+  /// Base64-encoded
+  content: String,
+  r#type: &'a str,
+  filename: &'a str,
+  disposition: &'a str,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  content_id: Option<&'a str>,
+}
+
+impl<'a> SendGridAttachment<'a> {
+  fn all(attachments: &'a [Attachment]) -> Vec<Self> {
+    attachments
+      .iter()
+      .map(|attachment| Self {
+        content: attachment.encoded_content(),
+        r#type: attachment.content_type(),
+        filename: attachment.filename(),
+        disposition: match attachment.content_id() {
+          Some(_) => "inline",
+          None => "attachment",
+        },
+        content_id: attachment.content_id(),
+      })
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use claim::assert_ok;
+  use reqwest::Client;
+  use secrecy::Secret;
+  use wiremock::{
+    matchers::{body_partial_json, header, method, path},
+    Mock, MockServer, ResponseTemplate,
+  };
+
+  use super::SendGridSender;
+  use crate::email_client::{
+    providers::test_helpers::{batch_recipients, EmailParts},
+    EmailSender, RecipientStatus,
+  };
+
+  struct SendEmailBodyMatcher;
+
+  impl wiremock::Match for SendEmailBodyMatcher {
+    fn matches(&self, request: &wiremock::Request) -> bool {
+      let result: Result<serde_json::Value, _> =
+        serde_json::from_slice(&request.body);
+
+      if let Ok(body) = result {
+        body["personalizations"][0]["to"][0].get("email").is_some()
+          && body["from"].get("email").is_some()
+          && body.get("subject").is_some()
+          && body["content"][0]["type"] == "text/plain"
+          && body["content"][1]["type"] == "text/html"
+      } else {
+        false
+      }
+    }
+  }
+
+  #[tokio::test]
+  async fn send_sends_the_expected_request() {
+    let mock_server = MockServer::start().await;
+    let sender = SendGridSender::new(
+      Client::new(),
+      mock_server.uri(),
+      Secret::new("api-key".to_string()),
+    );
+
+    Mock::given(header("Authorization", "Bearer api-key"))
+      .and(header("Content-Type", "application/json"))
+      .and(path("/v3/mail/send"))
+      .and(method("POST"))
+      .and(SendEmailBodyMatcher)
+      .respond_with(
+        ResponseTemplate::new(202)
+          .insert_header("X-Message-Id", "W1PyUpmfQeWbRcu3GgUdvA"),
+      )
+      .expect(1)
+      .mount(&mock_server)
+      .await;
+
+    let parts = EmailParts::generate();
+    let outcome = assert_ok!(sender.send(&parts.email()).await);
+    assert_eq!(outcome.status, RecipientStatus::Queued);
+    assert_eq!(
+      outcome.message_id.as_deref(),
+      Some("W1PyUpmfQeWbRcu3GgUdvA")
+    );
+  }
+
+  #[tokio::test]
+  async fn send_includes_attachments() {
+    let mock_server = MockServer::start().await;
+    let sender = SendGridSender::new(
+      Client::new(),
+      mock_server.uri(),
+      Secret::new("api-key".to_string()),
+    );
+
+    Mock::given(path("/v3/mail/send"))
+      .and(method("POST"))
+      .and(body_partial_json(serde_json::json!({
+        "attachments": [
+          {
+            "content": base64::encode("%PDF-1.4"),
+            "type": "application/pdf",
+            "filename": "issue.pdf",
+            "disposition": "attachment"
+          },
+          {
+            "content": base64::encode(b"\x89PNG"),
+            "type": "image/png",
+            "filename": "logo.png",
+            "disposition": "inline",
+            "content_id": "logo"
+          }
+        ]
+      })))
+      .respond_with(ResponseTemplate::new(202))
+      .expect(1)
+      .mount(&mock_server)
+      .await;
+
+    let parts = EmailParts::generate().with_attachments();
+    assert_ok!(sender.send(&parts.email()).await);
+  }
+
+  #[tokio::test]
+  async fn send_includes_the_envelope() {
+    let mock_server = MockServer::start().await;
+    let sender = SendGridSender::new(
+      Client::new(),
+      mock_server.uri(),
+      Secret::new("api-key".to_string()),
+    );
+    let parts = EmailParts::generate().with_envelope();
+
+    Mock::given(path("/v3/mail/send"))
+      .and(method("POST"))
+      .and(body_partial_json(serde_json::json!({
+        "personalizations": [{
+          "cc": [{ "email": "cc@example.com" }],
+          "bcc": [{ "email": "bcc@example.com" }]
+        }],
+        "from": { "email": parts.sender.as_ref(), "name": "Our newsletter" },
+        "reply_to": { "email": "editor@example.com" },
+        "categories": ["issue"],
+        "custom_args": { "issue_id": "42" }
+      })))
+      .respond_with(ResponseTemplate::new(202))
+      .expect(1)
+      .mount(&mock_server)
+      .await;
+
+    assert_ok!(sender.send(&parts.email()).await);
+  }
+
+  #[tokio::test]
+  async fn send_batch_uses_one_personalization_per_recipient() {
+    let mock_server = MockServer::start().await;
+    let sender = SendGridSender::new(
+      Client::new(),
+      mock_server.uri(),
+      Secret::new("api-key".to_string()),
+    );
+    let parts = EmailParts::generate().with_headers();
+    let recipients = batch_recipients(&["a@example.com", "b@example.com"]);
+
+    Mock::given(path("/v3/mail/send"))
+      .and(method("POST"))
+      .and(body_partial_json(serde_json::json!({
+        "personalizations": [
+          {
+            "to": [{ "email": "a@example.com" }],
+            "substitutions": { "{{email}}": "a@example.com" },
+            "headers": {
+              "List-Unsubscribe": "<https://example.com/unsubscribe/a@example.com>"
+            }
+          },
+          {
+            "to": [{ "email": "b@example.com" }],
+            "substitutions": { "{{email}}": "b@example.com" },
+            "headers": {
+              "List-Unsubscribe": "<https://example.com/unsubscribe/b@example.com>"
+            }
+          }
+        ]
+      })))
+      .respond_with(ResponseTemplate::new(202))
+      .expect(1)
+      .mount(&mock_server)
+      .await;
+
+    let outcomes = sender.send_batch(&parts.batch(&recipients)).await.unwrap();
+
+    assert_eq!(outcomes.len(), 2);
+    assert!(outcomes
+      .iter()
+      .all(|outcome| outcome.status == RecipientStatus::Queued));
+  }
+}
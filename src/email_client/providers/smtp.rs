@@ -0,0 +1,53 @@
+use async_trait::async_trait;
+use lettre::{
+  message::header::MessageId, AsyncSmtpTransport, AsyncTransport,
+  Tokio1Executor,
+};
+
+use super::mime::build_message;
+use crate::email_client::{
+  Email, EmailSender, RecipientStatus, SendError, SendOutcome,
+};
+
+/// Delivers through an SMTP relay.
+///
+/// The transport keeps a pool of authenticated connections around, so
+/// consecutive sends don't pay for a new TLS handshake and `AUTH` every time.
+pub struct SmtpSender {
+  transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpSender {
+  pub fn new(transport: AsyncSmtpTransport<Tokio1Executor>) -> Self {
+    Self { transport }
+  }
+}
+
+#[async_trait]
+impl EmailSender for SmtpSender {
+  async fn send(&self, email: &Email<'_>) -> Result<SendOutcome, SendError> {
+    let message = build_message(email).map_err(SendError::Permanent)?;
+    // The relay's reply carries no id of its own, but bounces and webhooks
+    // quote the Message-ID we generated.
+    let message_id = message
+      .headers()
+      .get::<MessageId>()
+      .map(|id| id.as_ref().to_owned());
+    self.transport.send(message).await.map_err(|e| {
+      // 5xx replies and errors on our side won't go away by themselves,
+      // anything else (4xx replies, I/O, TLS, timeouts) might.
+      if e.is_permanent() || e.is_client() {
+        SendError::Permanent(e.into())
+      } else {
+        SendError::Transient {
+          source: e.into(),
+          retry_after: None,
+        }
+      }
+    })?;
+    Ok(SendOutcome {
+      message_id,
+      ..SendOutcome::new(email.recipient, RecipientStatus::Sent)
+    })
+  }
+}
@@ -0,0 +1,133 @@
+use std::time::Duration;
+
+use rand::{thread_rng, Rng};
+
+/// How `EmailClient` retries requests the email API failed to handle.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+  /// Total number of attempts, including the first one
+  pub max_attempts: u32,
+  /// Delay before the first retry, doubled for every following one
+  pub base_delay: Duration,
+  /// Upper bound for any delay, including one requested with `Retry-After`
+  pub max_delay: Duration,
+  /// Fraction of each backoff delay, between 0 and 1, that is randomized
+  pub jitter: f64,
+}
+
+impl RetryPolicy {
+  /// A policy giving up on the first failure.
+  pub fn no_retries() -> Self {
+    Self {
+      max_attempts: 1,
+      base_delay: Duration::ZERO,
+      max_delay: Duration::ZERO,
+      jitter: 0.0,
+    }
+  }
+
+  /// How long to wait after failed attempt number `attempt` (starting at 1),
+  /// or `None` if we should give up.
+  ///
+  /// A delay requested by the API through `Retry-After` wins over our own
+  /// backoff; if it exceeds `max_delay` we give up rather than wait less than
+  /// we were asked to.
+  pub fn delay_before_retry(
+    &self,
+    attempt: u32,
+    retry_after: Option<Duration>,
+  ) -> Option<Duration> {
+    if attempt >= self.max_attempts {
+      return None;
+    }
+    match retry_after {
+      Some(delay) if delay > self.max_delay => None,
+      Some(delay) => Some(delay),
+      None => Some(self.backoff(attempt)),
+    }
+  }
+
+  /// Exponential backoff, capped at `max_delay`, with jitter applied.
+  fn backoff(&self, attempt: u32) -> Duration {
+    let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+    let delay = self
+      .base_delay
+      .checked_mul(factor)
+      .unwrap_or(self.max_delay)
+      .min(self.max_delay);
+    let jitter = self.jitter.clamp(0.0, 1.0);
+    if jitter == 0.0 {
+      delay
+    } else {
+      delay.mul_f64(1.0 - jitter * thread_rng().gen::<f64>())
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use claim::{assert_none, assert_some_eq};
+
+  use super::RetryPolicy;
+
+  fn policy() -> RetryPolicy {
+    RetryPolicy {
+      max_attempts: 5,
+      base_delay: Duration::from_millis(100),
+      max_delay: Duration::from_millis(500),
+      jitter: 0.0,
+    }
+  }
+
+  #[test]
+  fn backoff_doubles_until_max_delay() {
+    let policy = policy();
+    let delays: Vec<_> = (1..5)
+      .map(|attempt| policy.delay_before_retry(attempt, None).unwrap())
+      .collect();
+    assert_eq!(
+      delays,
+      vec![
+        Duration::from_millis(100),
+        Duration::from_millis(200),
+        Duration::from_millis(400),
+        Duration::from_millis(500),
+      ]
+    );
+  }
+
+  #[test]
+  fn gives_up_after_max_attempts() {
+    assert_none!(policy().delay_before_retry(5, None));
+    assert_none!(RetryPolicy::no_retries().delay_before_retry(1, None));
+  }
+
+  #[test]
+  fn retry_after_overrides_backoff() {
+    let retry_after = Duration::from_millis(300);
+    assert_some_eq!(
+      policy().delay_before_retry(1, Some(retry_after)),
+      retry_after
+    );
+  }
+
+  #[test]
+  fn retry_after_longer_than_max_delay_gives_up() {
+    assert_none!(policy().delay_before_retry(1, Some(Duration::from_secs(60))));
+  }
+
+  #[test]
+  fn jitter_only_ever_shortens_the_delay() {
+    let policy = RetryPolicy {
+      jitter: 0.5,
+      ..policy()
+    };
+    for _ in 0..100 {
+      let delay = policy.delay_before_retry(2, None).unwrap();
+      assert!(delay <= Duration::from_millis(200));
+      assert!(delay >= Duration::from_millis(100));
+    }
+  }
+}
@@ -0,0 +1,179 @@
+use crate::domain::SubscriberEmail;
+
+/// Keeps emails sent outside of production away from real inboxes, e.g. when
+/// running against a copy of the production database.
+///
+/// Addresses on the allowlist get their emails as usual, anybody else's go
+/// to the catch-all address instead, or nowhere when there is none.
+#[derive(Clone, Debug)]
+pub struct Sandbox {
+  allowlist: Vec<SubscriberEmail>,
+  catch_all: Option<SubscriberEmail>,
+}
+
+/// Where the sandbox sends an email, along with headers recording where it
+/// should have gone.
+#[derive(Debug)]
+pub(super) struct Rerouted {
+  pub recipient: SubscriberEmail,
+  pub cc: Vec<SubscriberEmail>,
+  pub bcc: Vec<SubscriberEmail>,
+  pub headers: Vec<(String, String)>,
+}
+
+impl Sandbox {
+  pub fn new(
+    allowlist: Vec<SubscriberEmail>,
+    catch_all: Option<SubscriberEmail>,
+  ) -> Self {
+    Self {
+      allowlist,
+      catch_all,
+    }
+  }
+
+  /// Where emails for `address` go, `None` if they are dropped.
+  fn route<'a>(
+    &'a self,
+    address: &'a SubscriberEmail,
+  ) -> Option<&'a SubscriberEmail> {
+    let allowed = self
+      .allowlist
+      .iter()
+      .any(|allowed| allowed.as_ref().eq_ignore_ascii_case(address.as_ref()));
+    if allowed {
+      Some(address)
+    } else {
+      self.catch_all.as_ref()
+    }
+  }
+
+  /// Route every recipient of an email, `None` if it is dropped altogether,
+  /// i.e. when its main recipient's copy would be.
+  ///
+  /// Copies redirected to an address already receiving the email are left
+  /// out, so the catch-all address gets a single one.
+  pub(super) fn reroute(
+    &self,
+    recipient: &SubscriberEmail,
+    cc: &[SubscriberEmail],
+    bcc: &[SubscriberEmail],
+  ) -> Option<Rerouted> {
+    let mut seen = vec![self.route(recipient)?];
+    let rerouted_cc = self.route_copies(cc, &mut seen);
+    let rerouted_bcc = self.route_copies(bcc, &mut seen);
+    // Bcc recipients stay hidden, they only show up in the logs.
+    let mut headers =
+      vec![("X-Original-To".to_owned(), recipient.as_ref().to_owned())];
+    if !cc.is_empty() {
+      headers.push(("X-Original-Cc".to_owned(), join(cc)));
+    }
+    Some(Rerouted {
+      recipient: seen[0].clone(),
+      cc: rerouted_cc,
+      bcc: rerouted_bcc,
+      headers,
+    })
+  }
+
+  /// Route copies to `addresses`, skipping those already in `seen`.
+  fn route_copies<'a>(
+    &'a self,
+    addresses: &'a [SubscriberEmail],
+    seen: &mut Vec<&'a SubscriberEmail>,
+  ) -> Vec<SubscriberEmail> {
+    let mut routed = vec![];
+    for address in addresses.iter().filter_map(|address| self.route(address)) {
+      let new = !seen
+        .iter()
+        .any(|seen| seen.as_ref().eq_ignore_ascii_case(address.as_ref()));
+      if new {
+        seen.push(address);
+        routed.push(address.clone());
+      }
+    }
+    routed
+  }
+}
+
+/// `addresses`, comma-separated as in `To` or `Cc` headers.
+fn join(addresses: &[SubscriberEmail]) -> String {
+  addresses
+    .iter()
+    .map(AsRef::as_ref)
+    .collect::<Vec<_>>()
+    .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+  use claim::assert_none;
+
+  use super::Sandbox;
+  use crate::domain::SubscriberEmail;
+
+  fn address(email: &str) -> SubscriberEmail {
+    SubscriberEmail::parse(email.into()).unwrap()
+  }
+
+  fn sandbox(catch_all: Option<&str>) -> Sandbox {
+    Sandbox::new(vec![address("team@example.com")], catch_all.map(address))
+  }
+
+  fn addresses(addresses: &[SubscriberEmail]) -> Vec<&str> {
+    addresses.iter().map(AsRef::as_ref).collect()
+  }
+
+  #[test]
+  fn allowlisted_addresses_are_left_alone() {
+    let rerouted = sandbox(Some("catch-all@example.com"))
+      .reroute(&address("Team@example.com"), &[], &[])
+      .unwrap();
+
+    assert_eq!(rerouted.recipient.as_ref(), "Team@example.com");
+  }
+
+  #[test]
+  fn other_addresses_go_to_the_catch_all_address_once() {
+    let rerouted = sandbox(Some("catch-all@example.com"))
+      .reroute(
+        &address("ursula_le_guin@gmail.com"),
+        &[address("team@example.com"), address("cc@gmail.com")],
+        &[address("bcc@gmail.com")],
+      )
+      .unwrap();
+
+    assert_eq!(rerouted.recipient.as_ref(), "catch-all@example.com");
+    assert_eq!(addresses(&rerouted.cc), vec!["team@example.com"]);
+    assert!(rerouted.bcc.is_empty());
+    assert_eq!(
+      rerouted.headers,
+      vec![
+        ("X-Original-To".into(), "ursula_le_guin@gmail.com".into()),
+        (
+          "X-Original-Cc".into(),
+          "team@example.com, cc@gmail.com".into()
+        ),
+      ]
+    );
+  }
+
+  #[test]
+  fn emails_for_other_addresses_are_dropped_without_a_catch_all() {
+    let sandbox = sandbox(None);
+
+    assert_none!(sandbox.reroute(
+      &address("ursula_le_guin@gmail.com"),
+      &[],
+      &[]
+    ));
+    let rerouted = sandbox
+      .reroute(
+        &address("team@example.com"),
+        &[address("cc@gmail.com")],
+        &[],
+      )
+      .unwrap();
+    assert!(rerouted.cc.is_empty());
+  }
+}
@@ -0,0 +1,276 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use async_trait::async_trait;
+
+use super::Attachment;
+use crate::domain::SubscriberEmail;
+
+/// A single email, ready to be handed over to a provider.
+pub struct Email<'a> {
+  pub sender: &'a SubscriberEmail,
+  pub sender_name: Option<&'a str>,
+  pub recipient: &'a SubscriberEmail,
+  pub reply_to: Option<&'a SubscriberEmail>,
+  pub cc: &'a [SubscriberEmail],
+  pub bcc: &'a [SubscriberEmail],
+  pub subject: &'a str,
+  pub html_content: &'a str,
+  pub text_content: &'a str,
+  pub attachments: &'a [Attachment],
+  /// Extra headers, e.g. `List-Unsubscribe`, as `(name, value)` pairs
+  pub headers: &'a [(String, String)],
+  pub tags: &'a [String],
+  pub metadata: &'a BTreeMap<String, String>,
+}
+
+/// The same email for many recipients, personalised with merge variables.
+///
+/// `{{name}}` placeholders in the subject, contents and header values are
+/// replaced with each recipient's value for `name`.
+///
+/// There is no cc or bcc: each copy would go to them too.
+pub struct BatchEmail<'a> {
+  pub sender: &'a SubscriberEmail,
+  pub sender_name: Option<&'a str>,
+  pub recipients: &'a [BatchRecipient],
+  pub reply_to: Option<&'a SubscriberEmail>,
+  pub subject: &'a str,
+  pub html_content: &'a str,
+  pub text_content: &'a str,
+  pub attachments: &'a [Attachment],
+  pub headers: &'a [(String, String)],
+  pub tags: &'a [String],
+  pub metadata: &'a BTreeMap<String, String>,
+}
+
+#[derive(Clone)]
+pub struct BatchRecipient {
+  pub email: SubscriberEmail,
+  pub merge_vars: BTreeMap<String, String>,
+}
+
+impl BatchRecipient {
+  pub fn new(email: SubscriberEmail) -> Self {
+    Self {
+      email,
+      merge_vars: BTreeMap::new(),
+    }
+  }
+}
+
+/// What the provider did with an email, for one recipient.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SendOutcome {
+  pub recipient: String,
+  pub status: RecipientStatus,
+  /// Why the provider refused the email, in its own words
+  pub reject_reason: Option<String>,
+  /// Id the provider refers to the email by, e.g. in its webhooks
+  pub message_id: Option<String>,
+}
+
+impl SendOutcome {
+  /// An outcome the provider told us nothing more about than `status`.
+  pub fn new(recipient: &SubscriberEmail, status: RecipientStatus) -> Self {
+    Self {
+      recipient: recipient.as_ref().to_owned(),
+      status,
+      reject_reason: None,
+      message_id: None,
+    }
+  }
+
+  /// Whether the provider accepted the email for delivery, or we kept it
+  /// from the recipient on purpose.
+  pub fn is_accepted(&self) -> bool {
+    matches!(
+      self.status,
+      RecipientStatus::Sent
+        | RecipientStatus::Queued
+        | RecipientStatus::Dropped
+        | RecipientStatus::Suppressed
+    )
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecipientStatus {
+  /// Handed over to the recipient's mail server
+  Sent,
+  /// Accepted by the provider, to be sent later
+  Queued,
+  /// Refused by the provider, e.g. because of a previous bounce
+  Rejected,
+  /// Not a deliverable address according to the provider
+  Invalid,
+  /// We could not get an answer from the provider for this recipient
+  Failed,
+  /// Never handed over to the provider, as the sandbox keeps it from anybody
+  /// outside its allowlist
+  Dropped,
+  /// Never handed over to the provider, as the address is on the suppression
+  /// list
+  Suppressed,
+}
+
+impl RecipientStatus {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      RecipientStatus::Sent => "sent",
+      RecipientStatus::Queued => "queued",
+      RecipientStatus::Rejected => "rejected",
+      RecipientStatus::Invalid => "invalid",
+      RecipientStatus::Failed => "failed",
+      RecipientStatus::Dropped => "dropped",
+      RecipientStatus::Suppressed => "suppressed",
+    }
+  }
+}
+
+/// A backend able to deliver an `Email`, usually a provider's HTTP API.
+///
+/// Implementations make a single attempt: retries are `EmailClient`'s job,
+/// driven by whether the returned `SendError` is transient.
+#[async_trait]
+pub trait EmailSender: Send + Sync {
+  async fn send(&self, email: &Email<'_>) -> Result<SendOutcome, SendError>;
+
+  /// Largest number of recipients `send_batch` accepts at once.
+  fn max_batch_size(&self) -> usize {
+    1
+  }
+
+  /// Largest total size of an email's attachments, once base64-encoded.
+  fn max_attachments_size(&self) -> usize {
+    10 * 1024 * 1024
+  }
+
+  /// Send `batch` without disclosing recipients to each other, returning one
+  /// result per recipient, in order.
+  ///
+  /// The default implementation calls `send` for each recipient in turn,
+  /// which is only safe to retry because `max_batch_size` defaults to 1.
+  async fn send_batch(
+    &self,
+    batch: &BatchEmail<'_>,
+  ) -> Result<Vec<SendOutcome>, SendError> {
+    let mut outcomes = Vec::with_capacity(batch.recipients.len());
+    for recipient in batch.recipients {
+      let subject = merge(batch.subject, &recipient.merge_vars, false);
+      let html_content = merge(batch.html_content, &recipient.merge_vars, true);
+      let text_content =
+        merge(batch.text_content, &recipient.merge_vars, false);
+      let headers = merge_headers(batch.headers, &recipient.merge_vars);
+      let email = Email {
+        sender: batch.sender,
+        sender_name: batch.sender_name,
+        recipient: &recipient.email,
+        reply_to: batch.reply_to,
+        cc: &[],
+        bcc: &[],
+        subject: &subject,
+        html_content: &html_content,
+        text_content: &text_content,
+        attachments: batch.attachments,
+        headers: &headers,
+        tags: batch.tags,
+        metadata: batch.metadata,
+      };
+      let outcome = match self.send(&email).await {
+        Ok(outcome) => outcome,
+        Err(SendError::Permanent(e)) => SendOutcome {
+          reject_reason: Some(format!("{:#}", e)),
+          ..SendOutcome::new(&recipient.email, RecipientStatus::Rejected)
+        },
+        Err(e) => return Err(e),
+      };
+      outcomes.push(outcome);
+    }
+    Ok(outcomes)
+  }
+}
+
+/// Replace `{{name}}` placeholders in `template`, escaping values for HTML
+/// when `html` is set.
+///
+/// Mandrill does the same with handlebars, which the fake provider mimics.
+pub(crate) fn merge(
+  template: &str,
+  merge_vars: &BTreeMap<String, String>,
+  html: bool,
+) -> String {
+  merge_vars
+    .iter()
+    .fold(template.to_owned(), |merged, (name, value)| {
+      let value = if html {
+        htmlescape::encode_minimal(value)
+      } else {
+        value.clone()
+      };
+      merged.replace(&format!("{{{{{}}}}}", name), &value)
+    })
+}
+
+/// Replace `{{name}}` placeholders in the values of `headers`.
+pub(super) fn merge_headers(
+  headers: &[(String, String)],
+  merge_vars: &BTreeMap<String, String>,
+) -> Vec<(String, String)> {
+  headers
+    .iter()
+    .map(|(name, value)| (name.clone(), merge(value, merge_vars, false)))
+    .collect()
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum SendError {
+  /// Worth another try: a timeout, a connection error, a 429 or a 5xx
+  #[error("The email provider is temporarily unable to accept the email.")]
+  Transient {
+    #[source]
+    source: anyhow::Error,
+    /// Delay the provider asked us to wait for before retrying, if any
+    retry_after: Option<Duration>,
+  },
+  /// Retrying would fail the same way, e.g. after a 4xx
+  #[error("The email provider refused the email.")]
+  Permanent(#[source] anyhow::Error),
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::BTreeMap;
+
+  use super::merge;
+
+  #[test]
+  fn merge_replaces_every_placeholder() {
+    let merge_vars = BTreeMap::from([
+      ("name".to_string(), "Ursula".to_string()),
+      ("issue".to_string(), "42".to_string()),
+    ]);
+    assert_eq!(
+      merge(
+        "Hi {{name}}, issue {{issue}} is out {{name}}!",
+        &merge_vars,
+        false
+      ),
+      "Hi Ursula, issue 42 is out Ursula!"
+    );
+  }
+
+  #[test]
+  fn merge_escapes_html_values() {
+    let merge_vars = BTreeMap::from([("name".to_string(), "<b>".to_string())]);
+    assert_eq!(
+      merge("<p>{{name}}</p>", &merge_vars, true),
+      "<p>&lt;b&gt;</p>"
+    );
+    assert_eq!(merge("{{name}}", &merge_vars, false), "<b>");
+  }
+
+  #[test]
+  fn merge_leaves_unknown_placeholders_alone() {
+    assert_eq!(merge("{{name}}", &BTreeMap::new(), false), "{{name}}");
+  }
+}
@@ -0,0 +1,160 @@
+//! Emails rendered from the templates directory.
+//!
+//! Every email has an HTML and a plain text template, `{name}.html` and
+//! `{name}.txt`, usually extending `layout.html` and `layout.txt`, which pull
+//! in the shared header and footer from `partials/`.
+use std::path::Path;
+
+use serde::Serialize;
+use tera::{Context, Tera};
+
+/// A context struct, rendered by the templates named after it.
+pub trait EmailTemplate: Serialize {
+  /// Name of the templates, without their extension
+  const NAME: &'static str;
+
+  /// A plausible context, used to check the templates at startup.
+  fn example() -> Self;
+}
+
+/// Sent to new subscribers, to confirm their address.
+#[derive(Serialize)]
+pub struct ConfirmationEmail<'a> {
+  pub confirmation_link: &'a str,
+}
+
+impl EmailTemplate for ConfirmationEmail<'_> {
+  const NAME: &'static str = "confirmation";
+
+  fn example() -> Self {
+    Self {
+      confirmation_link: "https://example.com/subscriptions/confirm",
+    }
+  }
+}
+
+/// A newsletter issue, as published by an admin.
+#[derive(Serialize)]
+pub struct IssueEmail<'a> {
+  pub title: &'a str,
+  pub html_content: &'a str,
+  pub text_content: &'a str,
+}
+
+impl EmailTemplate for IssueEmail<'_> {
+  const NAME: &'static str = "issue";
+
+  fn example() -> Self {
+    Self {
+      title: "Issue #1",
+      html_content: "<p>Hello, world!</p>",
+      text_content: "Hello, world!",
+    }
+  }
+}
+
+pub struct RenderedEmail {
+  pub html: String,
+  pub text: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct EmailTemplates {
+  tera: Tera,
+}
+
+impl EmailTemplates {
+  /// Load every template in `directory`, failing if any of the emails we send
+  /// can't be rendered.
+  pub fn load(directory: &Path) -> Result<Self, tera::Error> {
+    let glob = directory.join("**").join("*");
+    let tera = Tera::new(&glob.to_string_lossy())?;
+    Self::new(tera)
+  }
+
+  fn new(mut tera: Tera) -> Result<Self, tera::Error> {
+    // Tera's default also escapes `/`, which mangles links.
+    tera.set_escape_fn(htmlescape::encode_minimal);
+    let templates = Self { tera };
+    templates.render(&ConfirmationEmail::example())?;
+    templates.render(&IssueEmail::example())?;
+    Ok(templates)
+  }
+
+  pub fn render<T: EmailTemplate>(
+    &self,
+    email: &T,
+  ) -> Result<RenderedEmail, tera::Error> {
+    let context = Context::from_serialize(email)?;
+    Ok(RenderedEmail {
+      html: self.tera.render(&format!("{}.html", T::NAME), &context)?,
+      text: self.tera.render(&format!("{}.txt", T::NAME), &context)?,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::Path;
+
+  use claim::assert_err;
+  use tera::Tera;
+
+  use super::{ConfirmationEmail, EmailTemplates, IssueEmail};
+
+  fn templates() -> EmailTemplates {
+    EmailTemplates::load(Path::new("templates")).unwrap()
+  }
+
+  /// The bundled templates, with `overrides` replacing some of them.
+  fn templates_with(overrides: &[(&str, &str)]) -> Tera {
+    let mut tera = Tera::new("templates/**/*").unwrap();
+    tera.add_raw_templates(overrides.to_vec()).unwrap();
+    tera
+  }
+
+  #[test]
+  fn confirmation_emails_link_to_the_confirmation_page() {
+    let link = "https://example.com/subscriptions/confirm?token=a&b";
+    let email = templates()
+      .render(&ConfirmationEmail {
+        confirmation_link: link,
+      })
+      .unwrap();
+
+    assert!(email
+      .html
+      .contains("https://example.com/subscriptions/confirm?token=a&amp;b"));
+    assert!(email.text.contains(link));
+  }
+
+  #[test]
+  fn issues_are_wrapped_in_the_layout() {
+    let email = templates()
+      .render(&IssueEmail {
+        title: "<Issue>",
+        html_content: "<p>Hi {{email}}</p>",
+        text_content: "Hi {{email}} & co",
+      })
+      .unwrap();
+
+    assert!(email.html.contains("<title>&lt;Issue&gt;</title>"));
+    assert!(email.html.contains("<p>Hi {{email}}</p>"));
+    assert!(email.html.contains("subscribed to our newsletter"));
+    assert!(email.text.contains("Hi {{email}} & co"));
+    assert!(email.text.contains("subscribed to our newsletter"));
+  }
+
+  #[test]
+  fn templates_using_unknown_variables_are_refused() {
+    let tera = templates_with(&[("confirmation.txt", "{{ unknown }}")]);
+    assert_err!(EmailTemplates::new(tera));
+  }
+
+  #[test]
+  fn missing_templates_are_refused() {
+    let mut tera = templates_with(&[]);
+    tera.templates.remove("issue.txt");
+    assert_err!(EmailTemplates::new(tera));
+  }
+}
@@ -0,0 +1,360 @@
+//! A stand-in for Mandrill, to run the app locally without sending anything.
+//!
+//! It answers `POST /messages` like Mandrill does, keeping every message in
+//! memory to browse at `/` (HTML) or `GET /messages` (JSON). Failures can be
+//! injected to see how the app copes, see `Failures`.
+//!
+//! Run it with `cargo run --bin fake-provider`: it listens wherever
+//! `email_client.base_url` points.
+use std::{
+  collections::BTreeMap, net::TcpListener, sync::Mutex, time::Duration,
+};
+
+use actix_web::{
+  dev::Server,
+  http::header::ContentType,
+  web::{self, Data, Json, Path},
+  App, HttpResponse, HttpServer,
+};
+use chrono::{SecondsFormat, Utc};
+use config::{Config, ConfigError, Environment};
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use tracing_actix_web::TracingLogger;
+use uuid::Uuid;
+
+use crate::email_client::merge;
+
+/// Trouble to cause on purpose, e.g. to exercise retries by hand.
+///
+/// Read from `FAKE_PROVIDER_*` environment variables at startup, e.g.
+/// `FAKE_PROVIDER_ERROR_RATE=0.5`, and changed with `PUT /failures`.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct Failures {
+  /// Delay before answering every request
+  pub latency_milliseconds: u64,
+  /// Share of requests, between 0 and 1, answered with a 500
+  pub error_rate: f64,
+  /// Share of recipients, between 0 and 1, rejected as bounced
+  pub reject_rate: f64,
+}
+
+impl Failures {
+  pub fn from_env() -> Result<Self, ConfigError> {
+    Config::builder()
+      .add_source(Environment::with_prefix("fake_provider").try_parsing(true))
+      .build()?
+      .try_deserialize()
+  }
+}
+
+/// A message as Mandrill's `/messages` takes it, as far as we send them.
+#[derive(Deserialize)]
+struct SendRequest {
+  message: SentMessage,
+}
+
+#[derive(Deserialize)]
+struct SentMessage {
+  from_email: String,
+  from_name: Option<String>,
+  to: Vec<SentRecipient>,
+  subject: String,
+  html: String,
+  text: String,
+  #[serde(default)]
+  merge_vars: Vec<SentMergeVars>,
+  #[serde(default)]
+  attachments: Vec<SentFile>,
+  #[serde(default)]
+  images: Vec<SentFile>,
+  #[serde(default)]
+  headers: BTreeMap<String, String>,
+  #[serde(default)]
+  tags: Vec<String>,
+  #[serde(default)]
+  metadata: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct SentRecipient {
+  email: String,
+  /// `cc` or `bcc`, `to` when left out
+  r#type: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SentMergeVars {
+  rcpt: String,
+  vars: Vec<SentMergeVar>,
+}
+
+#[derive(Deserialize)]
+struct SentMergeVar {
+  name: String,
+  content: String,
+}
+
+#[derive(Deserialize)]
+struct SentFile {
+  name: String,
+}
+
+/// What one recipient received, merge variables filled in.
+#[derive(Serialize, Clone, Debug)]
+pub struct StoredMessage {
+  pub id: String,
+  /// RFC 3339
+  pub received_at: String,
+  pub from: String,
+  pub to: String,
+  pub cc: Vec<String>,
+  pub bcc: Vec<String>,
+  pub subject: String,
+  pub html: String,
+  pub text: String,
+  pub headers: BTreeMap<String, String>,
+  pub attachments: Vec<String>,
+  pub tags: Vec<String>,
+  pub metadata: BTreeMap<String, String>,
+  /// `sent`, or `rejected` when a failure was injected
+  pub status: &'static str,
+}
+
+/// One entry of the array Mandrill answers `/messages` with
+#[derive(Serialize)]
+struct RecipientStatus {
+  email: String,
+  status: &'static str,
+  reject_reason: Option<&'static str>,
+  #[serde(rename = "_id")]
+  id: String,
+}
+
+#[derive(Default)]
+struct Inbox {
+  messages: Mutex<Vec<StoredMessage>>,
+  failures: Mutex<Failures>,
+}
+
+pub fn run(
+  listener: TcpListener,
+  failures: Failures,
+) -> std::io::Result<Server> {
+  let inbox = Data::new(Inbox {
+    failures: Mutex::new(failures),
+    ..Inbox::default()
+  });
+  let server = HttpServer::new(move || {
+    App::new()
+      .wrap(TracingLogger::default())
+      .route("/", web::get().to(inbox_page))
+      .route("/messages", web::post().to(send))
+      .route("/messages", web::get().to(list_messages))
+      .route("/messages", web::delete().to(clear_messages))
+      .route("/messages/{id}", web::get().to(message_page))
+      .route("/failures", web::get().to(get_failures))
+      .route("/failures", web::put().to(set_failures))
+      .app_data(inbox.clone())
+  })
+  .listen(listener)?
+  .run();
+  Ok(server)
+}
+
+#[tracing::instrument(name = "Receive a message", skip_all)]
+async fn send(request: Json<SendRequest>, inbox: Data<Inbox>) -> HttpResponse {
+  let failures = inbox.failures.lock().unwrap().clone();
+  if failures.latency_milliseconds > 0 {
+    tokio::time::sleep(Duration::from_millis(failures.latency_milliseconds))
+      .await;
+  }
+  if thread_rng().gen_bool(failures.error_rate.clamp(0.0, 1.0)) {
+    return HttpResponse::InternalServerError().json(serde_json::json!({
+      "status": "error",
+      "code": -1,
+      "name": "GeneralError",
+      "message": "Injected failure"
+    }));
+  }
+  let message = request.into_inner().message;
+  let copies = |r#type: &str| {
+    message
+      .to
+      .iter()
+      .filter(|recipient| recipient.r#type.as_deref() == Some(r#type))
+      .map(|recipient| recipient.email.clone())
+      .collect::<Vec<_>>()
+  };
+  let (cc, bcc) = (copies("cc"), copies("bcc"));
+  let from = match &message.from_name {
+    Some(name) => format!("{} <{}>", name, message.from_email),
+    None => message.from_email.clone(),
+  };
+  let mut statuses = vec![];
+  let mut messages = inbox.messages.lock().unwrap();
+  for recipient in &message.to {
+    let rejected = thread_rng().gen_bool(failures.reject_rate.clamp(0.0, 1.0));
+    let status = RecipientStatus {
+      email: recipient.email.clone(),
+      status: if rejected { "rejected" } else { "sent" },
+      reject_reason: rejected.then_some("hard-bounce"),
+      id: Uuid::new_v4().to_simple().to_string(),
+    };
+    // Copies show up in the message of the recipient they were sent with.
+    if recipient.r#type.is_none() {
+      let merge_vars: BTreeMap<_, _> = message
+        .merge_vars
+        .iter()
+        .filter(|vars| vars.rcpt.eq_ignore_ascii_case(&recipient.email))
+        .flat_map(|vars| &vars.vars)
+        .map(|var| (var.name.clone(), var.content.clone()))
+        .collect();
+      messages.push(StoredMessage {
+        id: status.id.clone(),
+        received_at: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        from: from.clone(),
+        to: recipient.email.clone(),
+        cc: cc.clone(),
+        bcc: bcc.clone(),
+        subject: merge(&message.subject, &merge_vars, false),
+        html: merge(&message.html, &merge_vars, true),
+        text: merge(&message.text, &merge_vars, false),
+        headers: message
+          .headers
+          .iter()
+          .map(|(name, value)| (name.clone(), merge(value, &merge_vars, false)))
+          .collect(),
+        attachments: message
+          .attachments
+          .iter()
+          .chain(&message.images)
+          .map(|file| file.name.clone())
+          .collect(),
+        tags: message.tags.clone(),
+        metadata: message.metadata.clone(),
+        status: status.status,
+      });
+    }
+    statuses.push(status);
+  }
+  HttpResponse::Ok().json(statuses)
+}
+
+async fn list_messages(inbox: Data<Inbox>) -> HttpResponse {
+  HttpResponse::Ok().json(&*inbox.messages.lock().unwrap())
+}
+
+async fn clear_messages(inbox: Data<Inbox>) -> HttpResponse {
+  inbox.messages.lock().unwrap().clear();
+  HttpResponse::NoContent().finish()
+}
+
+async fn get_failures(inbox: Data<Inbox>) -> HttpResponse {
+  HttpResponse::Ok().json(&*inbox.failures.lock().unwrap())
+}
+
+async fn set_failures(
+  failures: Json<Failures>,
+  inbox: Data<Inbox>,
+) -> HttpResponse {
+  tracing::info!(failures = ?failures, "Injecting failures");
+  *inbox.failures.lock().unwrap() = failures.into_inner();
+  HttpResponse::NoContent().finish()
+}
+
+async fn inbox_page(inbox: Data<Inbox>) -> HttpResponse {
+  let rows: String = inbox
+    .messages
+    .lock()
+    .unwrap()
+    .iter()
+    .rev()
+    .map(|message| {
+      format!(
+        r#"<tr><td>{}</td><td>{}</td><td><a href="/messages/{}">{}</a></td><td>{}</td></tr>"#,
+        message.received_at,
+        htmlescape::encode_minimal(&message.to),
+        htmlescape::encode_attribute(&message.id),
+        htmlescape::encode_minimal(&message.subject),
+        message.status,
+      )
+    })
+    .collect();
+  page(
+    "Inbox",
+    &format!(
+      r#"<h1>Inbox</h1>
+  <table>
+    <tr><th>Received</th><th>To</th><th>Subject</th><th>Status</th></tr>
+    {}
+  </table>"#,
+      rows
+    ),
+  )
+}
+
+async fn message_page(id: Path<String>, inbox: Data<Inbox>) -> HttpResponse {
+  let messages = inbox.messages.lock().unwrap();
+  let message = match messages.iter().find(|message| message.id == *id) {
+    Some(message) => message,
+    None => return HttpResponse::NotFound().finish(),
+  };
+  let headers: String = [
+    ("From", message.from.clone()),
+    ("To", message.to.clone()),
+    ("Cc", message.cc.join(", ")),
+    ("Bcc", message.bcc.join(", ")),
+    ("Subject", message.subject.clone()),
+    ("Attachments", message.attachments.join(", ")),
+    ("Tags", message.tags.join(", ")),
+  ]
+  .into_iter()
+  .chain(
+    message
+      .headers
+      .iter()
+      .map(|(name, value)| (name.as_str(), value.clone())),
+  )
+  .filter(|(_, value)| !value.is_empty())
+  .map(|(name, value)| {
+    format!(
+      "<tr><th>{}</th><td>{}</td></tr>",
+      htmlescape::encode_minimal(name),
+      htmlescape::encode_minimal(&value)
+    )
+  })
+  .collect();
+  page(
+    &message.subject,
+    &format!(
+      r#"<p><a href="/">&larr; Inbox</a></p>
+  <table>{}</table>
+  <iframe sandbox srcdoc="{}" style="width: 100%; height: 480px;"></iframe>
+  <pre>{}</pre>"#,
+      headers,
+      htmlescape::encode_attribute(&message.html),
+      htmlescape::encode_minimal(&message.text),
+    ),
+  )
+}
+
+fn page(title: &str, body: &str) -> HttpResponse {
+  HttpResponse::Ok()
+    .content_type(ContentType::html())
+    .body(format!(
+      r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta http-equiv="content-type" content="text/html; charset=utf-8">
+  <title>{}</title>
+</head>
+<body>
+  {}
+</body>
+</html>"#,
+      htmlescape::encode_minimal(title),
+      body
+    ))
+}
@@ -0,0 +1,56 @@
+/// Client-provided key identifying retries of the same logical request.
+#[derive(Debug)]
+pub struct IdempotencyKey(String);
+
+impl TryFrom<String> for IdempotencyKey {
+  type Error = String;
+
+  fn try_from(s: String) -> Result<Self, Self::Error> {
+    if s.is_empty() {
+      return Err("The idempotency key cannot be empty".into());
+    }
+    let max_length = 50;
+    if s.len() >= max_length {
+      return Err(format!(
+        "The idempotency key must be shorter than {} characters",
+        max_length
+      ));
+    }
+    Ok(Self(s))
+  }
+}
+
+impl From<IdempotencyKey> for String {
+  fn from(k: IdempotencyKey) -> Self {
+    k.0
+  }
+}
+
+impl AsRef<str> for IdempotencyKey {
+  fn as_ref(&self) -> &str {
+    &self.0
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use claim::{assert_err, assert_ok};
+
+  use super::IdempotencyKey;
+
+  #[test]
+  fn an_empty_key_is_rejected() {
+    assert_err!(IdempotencyKey::try_from("".to_string()));
+  }
+
+  #[test]
+  fn a_key_of_50_characters_is_rejected() {
+    assert_err!(IdempotencyKey::try_from("a".repeat(50)));
+  }
+
+  #[test]
+  fn a_uuid_is_a_valid_key() {
+    let key = "9f9c2b4e-3e1e-4d4f-8a4e-2f4b1a6b7c8d".to_string();
+    assert_ok!(IdempotencyKey::try_from(key));
+  }
+}
@@ -0,0 +1,152 @@
+use actix_web::{
+  body::to_bytes,
+  http::{header::HeaderName, header::HeaderValue, StatusCode},
+  HttpResponse,
+};
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use super::IdempotencyKey;
+
+/// What the caller should do with a request carrying an idempotency key.
+// The transaction is large, but a `NextAction` never outlives the request
+// that created it, so boxing it would buy nothing.
+#[allow(clippy::large_enum_variant)]
+pub enum NextAction {
+  /// First time we see this key: process the request, then hand the
+  /// transaction back to `save_response`.
+  StartProcessing(Transaction<'static, Postgres>),
+  /// The request was already processed: replay the saved response.
+  ReturnSavedResponse(HttpResponse),
+}
+
+/// Claim `idempotency_key` for `user_id`, or fetch the response saved for it.
+///
+/// # Implementation Notes
+///
+/// The claiming `INSERT` keeps its row locked until the transaction handed
+/// out with `NextAction::StartProcessing` commits. A concurrent duplicate
+/// blocks on that lock and, once it is released, finds the saved response.
+pub async fn try_processing(
+  pool: &PgPool,
+  idempotency_key: &IdempotencyKey,
+  user_id: Uuid,
+) -> Result<NextAction, anyhow::Error> {
+  let mut transaction = pool.begin().await?;
+  let n_inserted_rows = sqlx::query!(
+    r#"
+    INSERT INTO idempotency (user_id, idempotency_key, created_at)
+    VALUES ($1, $2, $3)
+    ON CONFLICT DO NOTHING
+    "#,
+    user_id,
+    idempotency_key.as_ref(),
+    Utc::now()
+  )
+  .execute(&mut transaction)
+  .await?
+  .rows_affected();
+
+  if n_inserted_rows > 0 {
+    Ok(NextAction::StartProcessing(transaction))
+  } else {
+    let saved_response = get_saved_response(pool, idempotency_key, user_id)
+      .await?
+      .ok_or_else(|| {
+        anyhow::anyhow!("We expected a saved response, we didn't find it")
+      })?;
+    Ok(NextAction::ReturnSavedResponse(saved_response))
+  }
+}
+
+async fn get_saved_response(
+  pool: &PgPool,
+  idempotency_key: &IdempotencyKey,
+  user_id: Uuid,
+) -> Result<Option<HttpResponse>, anyhow::Error> {
+  let saved_response = sqlx::query!(
+    r#"
+    SELECT
+      response_status_code as "response_status_code!",
+      response_headers as "response_headers!",
+      response_body as "response_body!"
+    FROM idempotency
+    WHERE
+      user_id = $1 AND
+      idempotency_key = $2
+    "#,
+    user_id,
+    idempotency_key.as_ref()
+  )
+  .fetch_optional(pool)
+  .await?;
+
+  if let Some(r) = saved_response {
+    let status_code = StatusCode::from_u16(r.response_status_code.try_into()?)?;
+    let headers: Vec<(String, Vec<u8>)> =
+      serde_json::from_str(&r.response_headers)
+        .context("Failed to deserialize saved response headers.")?;
+    let mut response = HttpResponse::build(status_code);
+    for (name, value) in headers {
+      response.append_header((
+        HeaderName::try_from(name)?,
+        HeaderValue::from_bytes(&value)?,
+      ));
+    }
+    Ok(Some(response.body(r.response_body)))
+  } else {
+    Ok(None)
+  }
+}
+
+/// Persist `http_response` as the outcome for `idempotency_key`, then commit
+/// the transaction opened by `try_processing`.
+pub async fn save_response(
+  mut transaction: Transaction<'static, Postgres>,
+  idempotency_key: &IdempotencyKey,
+  user_id: Uuid,
+  http_response: HttpResponse,
+) -> Result<HttpResponse, anyhow::Error> {
+  let (response_head, body) = http_response.into_parts();
+  // `MessageBody::Error` is not `Send` + `Sync`,
+  // therefore it doesn't play nicely with `anyhow`
+  let body = to_bytes(body).await.map_err(|e| anyhow::anyhow!("{}", e))?;
+  let status_code = response_head.status().as_u16() as i16;
+  let headers: Vec<(String, Vec<u8>)> = response_head
+    .headers()
+    .iter()
+    .map(|(name, value)| {
+      (name.as_str().to_owned(), value.as_bytes().to_owned())
+    })
+    .collect();
+  let headers = serde_json::to_string(&headers)
+    .context("Failed to serialize response headers.")?;
+
+  sqlx::query!(
+    r#"
+    UPDATE idempotency
+    SET
+      response_status_code = $3,
+      response_headers = $4,
+      response_body = $5
+    WHERE
+      user_id = $1 AND
+      idempotency_key = $2
+    "#,
+    user_id,
+    idempotency_key.as_ref(),
+    status_code,
+    headers,
+    body.as_ref()
+  )
+  .execute(&mut transaction)
+  .await?;
+  transaction.commit().await?;
+
+  // We need `.map_into_boxed_body` to go from
+  // `HttpResponse<Bytes>` to `HttpResponse<BoxBody>`
+  let http_response = response_head.set_body(body).map_into_boxed_body();
+  Ok(http_response)
+}
@@ -0,0 +1,327 @@
+use std::time::Duration;
+
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+  domain::SubscriberEmail,
+  email_client::{
+    store_send_outcomes, Attachment, BatchRecipient, EmailClient,
+    RecipientStatus, SendOutcome,
+  },
+  email_templates::{EmailTemplates, IssueEmail},
+  open_tracking::{with_pixel, OpenTracking, OPEN_PIXEL_URL},
+};
+
+/// Result of a single pass over the delivery queue.
+pub enum ExecutionOutcome {
+  TaskCompleted,
+  EmptyQueue,
+}
+
+/// Deliver queued newsletter issues until the process is stopped.
+///
+/// Runs next to the HTTP server: publishing only enqueues tasks, so this loop
+/// is what actually calls the email API.
+pub async fn run_worker_until_stopped(
+  pool: PgPool,
+  email_client: EmailClient,
+  templates: EmailTemplates,
+  open_tracking: OpenTracking,
+) -> Result<(), anyhow::Error> {
+  loop {
+    match try_execute_task(&pool, &email_client, &templates, &open_tracking)
+      .await
+    {
+      Ok(ExecutionOutcome::EmptyQueue) => {
+        tokio::time::sleep(Duration::from_secs(10)).await;
+      }
+      Err(_) => {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+      }
+      Ok(ExecutionOutcome::TaskCompleted) => {}
+    }
+  }
+}
+
+/// Most delivery tasks dequeued, and sent, at once.
+const BATCH_SIZE: i64 = 100;
+
+/// Attempts at a batch before giving up on it, so that an issue that can't
+/// be delivered, e.g. because its template fails to render, doesn't block
+/// the queue forever.
+const MAX_ATTEMPTS: i16 = 5;
+
+/// Dequeue a batch of delivery tasks for one issue, if any, and attempt to
+/// deliver them.
+///
+/// The tasks are deleted once the attempt is over, whether or not the email
+/// API accepted them, so a single bad address can't block the queue. When
+/// the issue can't even be prepared for sending, they are retried up to
+/// `MAX_ATTEMPTS` times, then recorded as failed.
+pub async fn try_execute_task(
+  pool: &PgPool,
+  email_client: &EmailClient,
+  templates: &EmailTemplates,
+  open_tracking: &OpenTracking,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+  let (transaction, issue_id, emails, n_attempts) =
+    match dequeue_tasks(pool).await? {
+      Some(tasks) => tasks,
+      None => return Ok(ExecutionOutcome::EmptyQueue),
+    };
+  if let Err(e) = deliver_issue(
+    pool,
+    email_client,
+    templates,
+    open_tracking,
+    issue_id,
+    &emails,
+  )
+  .await
+  {
+    if n_attempts + 1 < MAX_ATTEMPTS {
+      record_failed_attempt(transaction, issue_id, &emails).await?;
+      return Err(e);
+    }
+    tracing::error!(
+      error.cause_chain = ?e,
+      newsletter_issue_id = %issue_id,
+      n_recipients = emails.len(),
+      "Giving up on delivering an issue",
+    );
+    let outcomes: Vec<_> = emails
+      .iter()
+      .map(|email| SendOutcome {
+        recipient: email.clone(),
+        status: RecipientStatus::Failed,
+        reject_reason: None,
+        message_id: None,
+      })
+      .collect();
+    store_send_outcomes(pool, Some(issue_id), &outcomes).await?;
+  }
+  delete_tasks(transaction, issue_id, &emails).await?;
+  Ok(ExecutionOutcome::TaskCompleted)
+}
+
+#[tracing::instrument(
+  skip(pool, email_client, templates, open_tracking, emails),
+  fields(newsletter_issue_id = %issue_id, n_recipients = emails.len()),
+  err
+)]
+async fn deliver_issue(
+  pool: &PgPool,
+  email_client: &EmailClient,
+  templates: &EmailTemplates,
+  open_tracking: &OpenTracking,
+  issue_id: Uuid,
+  emails: &[String],
+) -> Result<(), anyhow::Error> {
+  let mut recipients: Vec<_> = emails
+    .iter()
+    .filter_map(|email| match SubscriberEmail::parse(email.clone()) {
+      Ok(email) => Some(BatchRecipient::new(email)),
+      Err(e) => {
+        tracing::error!(
+          error.message = %e,
+          "Skipping a confirmed subscriber. \
+          Their stored contact details are invalid",
+        );
+        None
+      }
+    })
+    .collect();
+  if recipients.is_empty() {
+    return Ok(());
+  }
+
+  let issue = get_issue(pool, issue_id).await?;
+  let attachments = get_issue_attachments(pool, issue_id).await?;
+  let email = templates.render(&IssueEmail {
+    title: &issue.title,
+    html_content: &issue.html_content,
+    text_content: &issue.text_content,
+  })?;
+  let html = if issue.track_opens && open_tracking.is_enabled() {
+    for recipient in &mut recipients {
+      let pixel_url = open_tracking.pixel_url(issue_id, &recipient.email);
+      recipient
+        .merge_vars
+        .insert(OPEN_PIXEL_URL.to_owned(), pixel_url);
+    }
+    with_pixel(&email.html)
+  } else {
+    email.html
+  };
+  let outcomes = email_client
+    .send_batch(&recipients, &issue.title, &html, &email.text, &attachments)
+    .await;
+  for outcome in outcomes.iter().filter(|outcome| !outcome.is_accepted()) {
+    tracing::error!(
+      subscriber_email = %outcome.recipient,
+      status = outcome.status.as_str(),
+      reject_reason = ?outcome.reject_reason,
+      "Failed to deliver issue to a confirmed subscriber. \
+      Skipping.",
+    );
+  }
+  if let Err(e) = store_send_outcomes(pool, Some(issue_id), &outcomes).await {
+    // The emails are already on their way: failing the task would leave it
+    // in the queue and send them all over again.
+    tracing::error!(
+      error.cause_chain = ?e,
+      "Failed to store the outcomes of an issue's delivery",
+    );
+  }
+  Ok(())
+}
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+/// The locked tasks, their issue, recipients and most attempts so far
+type DequeuedTasks = (PgTransaction, Uuid, Vec<String>, i16);
+
+/// Lock up to `BATCH_SIZE` pending tasks, all for the same issue.
+///
+/// # Implementation Notes
+///
+/// `SKIP LOCKED` lets several workers poll the same queue: each one skips the
+/// rows already claimed by the others instead of waiting for them.
+#[tracing::instrument(skip_all)]
+async fn dequeue_tasks(
+  pool: &PgPool,
+) -> Result<Option<DequeuedTasks>, anyhow::Error> {
+  let mut transaction = pool.begin().await?;
+  let rows = sqlx::query!(
+    r#"
+    SELECT newsletter_issue_id, subscriber_email, n_attempts
+    FROM issue_delivery_queue
+    WHERE newsletter_issue_id = (
+      SELECT newsletter_issue_id
+      FROM issue_delivery_queue
+      FOR UPDATE
+      SKIP LOCKED
+      LIMIT 1
+    )
+    FOR UPDATE
+    SKIP LOCKED
+    LIMIT $1
+    "#,
+    BATCH_SIZE
+  )
+  .fetch_all(&mut transaction)
+  .await?;
+  match rows.first() {
+    Some(first) => {
+      let issue_id = first.newsletter_issue_id;
+      let n_attempts = rows.iter().map(|r| r.n_attempts).max().unwrap_or(0);
+      let emails = rows.into_iter().map(|r| r.subscriber_email).collect();
+      Ok(Some((transaction, issue_id, emails, n_attempts)))
+    }
+    None => Ok(None),
+  }
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_tasks(
+  mut transaction: PgTransaction,
+  issue_id: Uuid,
+  emails: &[String],
+) -> Result<(), anyhow::Error> {
+  sqlx::query!(
+    r#"
+    DELETE FROM issue_delivery_queue
+    WHERE
+      newsletter_issue_id = $1 AND
+      subscriber_email = ANY($2)
+    "#,
+    issue_id,
+    emails
+  )
+  .execute(&mut transaction)
+  .await?;
+  transaction.commit().await?;
+  Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn record_failed_attempt(
+  mut transaction: PgTransaction,
+  issue_id: Uuid,
+  emails: &[String],
+) -> Result<(), anyhow::Error> {
+  sqlx::query!(
+    r#"
+    UPDATE issue_delivery_queue
+    SET n_attempts = n_attempts + 1
+    WHERE
+      newsletter_issue_id = $1 AND
+      subscriber_email = ANY($2)
+    "#,
+    issue_id,
+    emails
+  )
+  .execute(&mut transaction)
+  .await?;
+  transaction.commit().await?;
+  Ok(())
+}
+
+struct NewsletterIssue {
+  title: String,
+  text_content: String,
+  html_content: String,
+  track_opens: bool,
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_issue(
+  pool: &PgPool,
+  issue_id: Uuid,
+) -> Result<NewsletterIssue, anyhow::Error> {
+  let issue = sqlx::query_as!(
+    NewsletterIssue,
+    r#"
+    SELECT title, text_content, html_content, track_opens
+    FROM newsletter_issues
+    WHERE
+      newsletter_issue_id = $1
+    "#,
+    issue_id
+  )
+  .fetch_one(pool)
+  .await?;
+  Ok(issue)
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_issue_attachments(
+  pool: &PgPool,
+  issue_id: Uuid,
+) -> Result<Vec<Attachment>, anyhow::Error> {
+  let rows = sqlx::query!(
+    r#"
+    SELECT filename, content_type, content, content_id
+    FROM newsletter_issue_attachments
+    WHERE newsletter_issue_id = $1
+    ORDER BY position
+    "#,
+    issue_id
+  )
+  .fetch_all(pool)
+  .await?;
+  let attachments = rows
+    .into_iter()
+    .map(|row| match row.content_id {
+      None => Attachment::new(row.filename, &row.content_type, row.content),
+      Some(content_id) => Attachment::inline(
+        content_id,
+        row.filename,
+        &row.content_type,
+        row.content,
+      ),
+    })
+    .collect::<Result<_, _>>()?;
+  Ok(attachments)
+}
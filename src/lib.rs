@@ -1,7 +1,20 @@
 //! src/lib.rs
+pub mod authentication;
 pub mod configuration;
 pub mod domain;
 pub mod email_client;
+pub mod email_templates;
+pub mod fake_provider;
+pub mod idempotency;
+pub mod issue_delivery_worker;
+pub mod markdown;
+pub mod open_tracking;
 pub mod routes;
+pub mod session_state;
+pub mod session_store;
 pub mod startup;
+pub mod suppressions;
 pub mod telementry;
+pub mod unsubscribe;
+pub mod utils;
+pub mod webhooks;
@@ -2,15 +2,19 @@
 
 use emailer::{
   configuration::get_configuration,
-  email_client::EmailClient,
+  issue_delivery_worker::run_worker_until_stopped,
+  session_store::PostgresSessionStore,
   startup::run,
+  suppressions::SuppressionList,
   telementry::{get_subscriber, init_subscriber},
 };
 use sqlx::postgres::PgPoolOptions;
 use std::{
+  fmt::{Debug, Display},
   io::{stdout, Result},
   net::TcpListener,
 };
+use tokio::task::JoinError;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -23,18 +27,16 @@ async fn main() -> Result<()> {
     .connect_timeout(std::time::Duration::from_secs(2))
     .connect_lazy_with(configuration.database.with_db());
 
-  let timeout = configuration.email_client.timeout();
-
-  let sender_email = configuration
+  let email_client = configuration
     .email_client
-    .sender()
-    .expect("Invalid sender email address.");
-  let email_client = EmailClient::new(
-    configuration.email_client.base_url,
-    sender_email,
-    configuration.email_client.authorization_token,
-    timeout,
-  );
+    .client(configuration.application.unsubscribe_links())
+    .with_suppressions(SuppressionList::new(connection_pool.clone()));
+  // A broken template should stop the deployment, not a send.
+  let templates = configuration
+    .templates
+    .load()
+    .expect("Failed to load email templates.");
+  let open_tracking = configuration.application.open_tracking();
 
   let address = format!(
     "{}:{}",
@@ -42,6 +44,61 @@ async fn main() -> Result<()> {
   );
   let listener = TcpListener::bind(address.clone())?;
 
-  run(listener, connection_pool, email_client)?.await?;
+  let session_store = PostgresSessionStore::new(connection_pool.clone());
+
+  let server = run(
+    listener,
+    connection_pool.clone(),
+    email_client.clone(),
+    templates.clone(),
+    configuration.application.base_url,
+    configuration.application.hmac_secret,
+    open_tracking.clone(),
+    configuration.webhooks,
+    session_store,
+  )?;
+  let server_task = tokio::spawn(server);
+  let worker_task = tokio::spawn(run_worker_until_stopped(
+    connection_pool,
+    email_client,
+    templates,
+    open_tracking,
+  ));
+
+  // Whichever stops first takes the whole process down with it.
+  tokio::select! {
+    o = server_task => report_exit("API", o),
+    o = worker_task => report_exit("Background worker", o),
+  };
   Ok(())
 }
+
+fn report_exit(
+  task_name: &str,
+  outcome: std::result::Result<
+    std::result::Result<(), impl Debug + Display>,
+    JoinError,
+  >,
+) {
+  match outcome {
+    Ok(Ok(())) => {
+      tracing::info!("{} has exited", task_name)
+    }
+    Ok(Err(e)) => {
+      tracing::error!(
+        error.cause_chain = ?e,
+        error.message = %e,
+        "{} failed",
+        task_name
+      )
+    }
+    Err(e) => {
+      tracing::error!(
+        error.cause_chain = ?e,
+        error.message = %e,
+        "{} task failed to complete",
+        task_name
+      )
+    }
+  }
+}
@@ -0,0 +1,363 @@
+//! Markdown issues, rendered for email clients.
+//!
+//! Rendering is deterministic: the same Markdown always gives byte-for-byte
+//! the same HTML and plain text, so both can be checked against snapshots.
+use pulldown_cmark::{Event, HeadingLevel, Options, Parser, Tag};
+
+pub struct RenderedMarkdown {
+  pub html: String,
+  pub text: String,
+}
+
+/// Styles inlined on each element, as most email clients ignore `<style>`.
+///
+/// # Implementation Notes
+///
+/// One attribute per element: ammonia applies them from a `HashMap`, which
+/// would make the order of several attributes vary from run to run.
+const STYLESHEET: &[(&str, &str)] = &[
+  ("a", "color: #1a73e8;"),
+  (
+    "blockquote",
+    "margin: 0 0 16px; padding-left: 12px; border-left: 4px solid #ddd; \
+    color: #555;",
+  ),
+  ("code", "font-family: monospace; background: #f4f4f4;"),
+  ("h1", "font-size: 24px; margin: 0 0 16px;"),
+  ("h2", "font-size: 20px; margin: 0 0 16px;"),
+  ("h3", "font-size: 16px; margin: 0 0 16px;"),
+  ("hr", "border: 0; border-top: 1px solid #ddd;"),
+  ("img", "max-width: 100%;"),
+  ("p", "margin: 0 0 16px; line-height: 1.5;"),
+  (
+    "pre",
+    "margin: 0 0 16px; padding: 12px; background: #f4f4f4; \
+    overflow-x: auto;",
+  ),
+  ("table", "border-collapse: collapse; margin: 0 0 16px;"),
+  ("td", "border: 1px solid #ddd; padding: 4px 8px;"),
+  ("th", "border: 1px solid #ddd; padding: 4px 8px;"),
+];
+
+fn options() -> Options {
+  Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH
+}
+
+/// Render `markdown` to sanitized HTML, with inline styles, and to a plain
+/// text alternative.
+pub fn render(markdown: &str) -> RenderedMarkdown {
+  let mut unsafe_html = String::new();
+  pulldown_cmark::html::push_html(
+    &mut unsafe_html,
+    Parser::new_ext(markdown, options()),
+  );
+  let mut sanitizer = ammonia::Builder::default();
+  // Inline images attached to the email are referred to as `cid:{id}`.
+  sanitizer.add_url_schemes(&["cid"]);
+  for (tag, style) in STYLESHEET {
+    sanitizer.set_tag_attribute_value(tag, "style", style);
+  }
+  RenderedMarkdown {
+    html: sanitizer.clean(&unsafe_html).to_string(),
+    text: PlainText::default().render(Parser::new_ext(markdown, options())),
+  }
+}
+
+/// Plain text close to what was written, minus the markup.
+#[derive(Default)]
+struct PlainText {
+  out: String,
+  /// Next number of each nested list, `None` for bullet lists
+  lists: Vec<Option<u64>>,
+  /// Where the text of each open heading, link or block quote starts
+  starts: Vec<usize>,
+  /// Link destinations, appended after the link text
+  links: Vec<String>,
+  /// A list marker was just written, the item's first block goes after it
+  in_item: bool,
+  in_code_block: bool,
+  first_cell: bool,
+}
+
+impl PlainText {
+  fn render<'a>(mut self, events: impl Iterator<Item = Event<'a>>) -> String {
+    for event in events {
+      match event {
+        Event::Start(tag) => self.start(tag),
+        Event::End(tag) => self.end(tag),
+        Event::Text(text) if self.in_code_block => {
+          for line in text.lines() {
+            self.out.push_str("    ");
+            self.out.push_str(line);
+            self.out.push('\n');
+          }
+        }
+        Event::Text(text) | Event::Code(text) => self.out.push_str(&text),
+        Event::SoftBreak => self.out.push(' '),
+        Event::HardBreak => self.out.push('\n'),
+        Event::Rule => {
+          self.block();
+          self.out.push_str("----");
+        }
+        // Raw HTML is dropped, along with anything unsafe in it.
+        Event::Html(_)
+        | Event::FootnoteReference(_)
+        | Event::TaskListMarker(_) => {}
+      }
+    }
+    let end = self.out.trim_end().len();
+    self.out.truncate(end);
+    self.out.push('\n');
+    self.out
+  }
+
+  /// Separate the next block from the previous one by a blank line.
+  fn block(&mut self) {
+    if std::mem::take(&mut self.in_item) || self.out.is_empty() {
+      return;
+    }
+    let end = self.out.trim_end_matches('\n').len();
+    self.out.truncate(end);
+    self.out.push_str("\n\n");
+  }
+
+  fn new_line(&mut self) {
+    if !self.out.is_empty() && !self.out.ends_with('\n') {
+      self.out.push('\n');
+    }
+  }
+
+  fn start(&mut self, tag: Tag<'_>) {
+    match tag {
+      Tag::Paragraph | Tag::Table(_) => self.block(),
+      Tag::Heading(..) | Tag::BlockQuote => {
+        self.block();
+        self.starts.push(self.out.len());
+      }
+      Tag::CodeBlock(_) => {
+        self.block();
+        self.in_code_block = true;
+      }
+      Tag::List(first_number) => {
+        // Nested lists go right under their parent item.
+        if self.lists.is_empty() {
+          self.block();
+        } else {
+          self.in_item = false;
+          self.new_line();
+        }
+        self.lists.push(first_number);
+      }
+      Tag::Item => {
+        self.new_line();
+        let depth = self.lists.len().saturating_sub(1);
+        self.out.push_str(&"  ".repeat(depth));
+        match self.lists.last_mut() {
+          Some(Some(number)) => {
+            self.out.push_str(&format!("{}. ", number));
+            *number += 1;
+          }
+          _ => self.out.push_str("- "),
+        }
+        self.in_item = true;
+      }
+      Tag::TableHead | Tag::TableRow => {
+        self.new_line();
+        self.first_cell = true;
+      }
+      Tag::TableCell => {
+        if !std::mem::take(&mut self.first_cell) {
+          self.out.push_str(" | ");
+        }
+      }
+      Tag::Link(_, destination, _) => {
+        self.starts.push(self.out.len());
+        self.links.push(destination.into_string());
+      }
+      Tag::Image(..) => self.out.push('['),
+      Tag::Emphasis
+      | Tag::Strong
+      | Tag::Strikethrough
+      | Tag::FootnoteDefinition(_) => {}
+    }
+  }
+
+  fn end(&mut self, tag: Tag<'_>) {
+    match tag {
+      Tag::Heading(level, ..) => {
+        let start = self.starts.pop().unwrap_or_default();
+        let underline = match level {
+          HeadingLevel::H1 => '=',
+          HeadingLevel::H2 => '-',
+          _ => return,
+        };
+        let width = self.out[start..].chars().count();
+        self.out.push('\n');
+        self.out.push_str(&underline.to_string().repeat(width));
+      }
+      Tag::BlockQuote => {
+        let start = self.starts.pop().unwrap_or_default();
+        let quoted = self.out.split_off(start);
+        let quoted = quoted
+          .trim_end()
+          .lines()
+          .map(|line| format!("> {}", line).trim_end().to_owned())
+          .collect::<Vec<_>>()
+          .join("\n");
+        self.out.push_str(&quoted);
+      }
+      Tag::CodeBlock(_) => self.in_code_block = false,
+      Tag::List(_) => {
+        self.lists.pop();
+      }
+      Tag::Item => self.in_item = false,
+      Tag::Link(..) => {
+        let start = self.starts.pop().unwrap_or_default();
+        let destination = self.links.pop().unwrap_or_default();
+        if self.out[start..] != destination {
+          self.out.push_str(&format!(" ({})", destination));
+        }
+      }
+      Tag::Image(_, destination, _) => {
+        self.out.push_str(&format!("] ({})", destination));
+      }
+      Tag::Paragraph
+      | Tag::Table(_)
+      | Tag::TableHead
+      | Tag::TableRow
+      | Tag::TableCell
+      | Tag::Emphasis
+      | Tag::Strong
+      | Tag::Strikethrough
+      | Tag::FootnoteDefinition(_) => {}
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::render;
+
+  const ISSUE: &str = "\
+# Issue #1
+
+Hello **{{email}}**, here is [our site](https://example.com) and \
+<https://example.com/about>.
+
+## What's new
+
+- First
+- Second
+  1. Nested
+  2. Again
+
+> Quoted
+> text
+
+```rust
+let answer = 42;
+```
+
+![Our logo](cid:logo)
+
+| Name | Value |
+| ---- | ----- |
+| a    | 1     |
+
+---
+";
+
+  #[test]
+  fn html_is_styled_inline() {
+    let html = render(ISSUE).html;
+
+    assert_eq!(
+      html,
+      "<h1 style=\"font-size: 24px; margin: 0 0 16px;\">Issue #1</h1>\n\
+      <p style=\"margin: 0 0 16px; line-height: 1.5;\">Hello \
+      <strong>{{email}}</strong>, here is \
+      <a href=\"https://example.com\" style=\"color: #1a73e8;\" \
+      rel=\"noopener noreferrer\">our site</a> and \
+      <a href=\"https://example.com/about\" style=\"color: #1a73e8;\" \
+      rel=\"noopener noreferrer\">https://example.com/about</a>.</p>\n\
+      <h2 style=\"font-size: 20px; margin: 0 0 16px;\">What's new</h2>\n\
+      <ul>\n<li>First</li>\n<li>Second\n<ol>\n<li>Nested</li>\n\
+      <li>Again</li>\n</ol>\n</li>\n</ul>\n\
+      <blockquote style=\"margin: 0 0 16px; padding-left: 12px; \
+      border-left: 4px solid #ddd; color: #555;\">\n\
+      <p style=\"margin: 0 0 16px; line-height: 1.5;\">Quoted\ntext</p>\n\
+      </blockquote>\n\
+      <pre style=\"margin: 0 0 16px; padding: 12px; background: #f4f4f4; \
+      overflow-x: auto;\"><code style=\"font-family: monospace; \
+      background: #f4f4f4;\">let answer = 42;\n</code></pre>\n\
+      <p style=\"margin: 0 0 16px; line-height: 1.5;\">\
+      <img src=\"cid:logo\" alt=\"Our logo\" style=\"max-width: 100%;\"></p>\n\
+      <table style=\"border-collapse: collapse; margin: 0 0 16px;\">\
+      <thead><tr>\
+      <th style=\"border: 1px solid #ddd; padding: 4px 8px;\">Name</th>\
+      <th style=\"border: 1px solid #ddd; padding: 4px 8px;\">Value</th>\
+      </tr></thead><tbody>\n<tr>\
+      <td style=\"border: 1px solid #ddd; padding: 4px 8px;\">a</td>\
+      <td style=\"border: 1px solid #ddd; padding: 4px 8px;\">1</td>\
+      </tr>\n</tbody></table>\n\
+      <hr style=\"border: 0; border-top: 1px solid #ddd;\">\n"
+    );
+  }
+
+  #[test]
+  fn text_keeps_the_structure_without_the_markup() {
+    let text = render(ISSUE).text;
+
+    assert_eq!(
+      text,
+      "Issue #1
+========
+
+Hello {{email}}, here is our site (https://example.com) and \
+https://example.com/about.
+
+What's new
+----------
+
+- First
+- Second
+  1. Nested
+  2. Again
+
+> Quoted text
+
+    let answer = 42;
+
+[Our logo] (cid:logo)
+
+Name | Value
+a | 1
+
+----
+"
+    );
+  }
+
+  #[test]
+  fn unsafe_html_is_removed() {
+    let rendered = render(
+      "<script>alert(1)</script>\n\n\
+      [click](javascript:alert(1)) <img src=x onerror=alert(1)>",
+    );
+
+    assert!(!rendered.html.contains("script"));
+    assert!(!rendered.html.contains("javascript:"));
+    assert!(!rendered.html.contains("onerror"));
+    assert!(!rendered.text.contains("<script>"));
+  }
+
+  #[test]
+  fn rendering_is_deterministic() {
+    let first = render(ISSUE);
+    for _ in 0..10 {
+      let again = render(ISSUE);
+      assert_eq!(first.html, again.html);
+      assert_eq!(first.text, again.text);
+    }
+  }
+}
@@ -0,0 +1,488 @@
+//! Open tracking pixels.
+//!
+//! Issues published with `track_opens` get an invisible image whose URL is
+//! unique to each recipient: loading it tells us they opened the email. Like
+//! unsubscribe links, the URL's token is signed, so opens can't be forged
+//! and sending needs no database round trip.
+//!
+//! Not every load is a human reading the email, see `MachineOpen`.
+use std::net::IpAddr;
+
+use actix_web::http::header::HeaderMap;
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use serde::Deserialize;
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::domain::SubscriberEmail;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Merge variable holding each recipient's pixel URL
+pub const OPEN_PIXEL_URL: &str = "open_pixel_url";
+
+#[derive(Clone)]
+pub struct OpenTracking {
+  base_url: String,
+  hmac_secret: Secret<String>,
+  enabled: bool,
+  trusted_proxies: Vec<TrustedProxy>,
+}
+
+impl OpenTracking {
+  /// Tracking nothing when `enabled` is false, whatever issues ask for.
+  pub fn new(
+    base_url: String,
+    hmac_secret: Secret<String>,
+    enabled: bool,
+  ) -> Self {
+    Self {
+      base_url,
+      hmac_secret,
+      enabled,
+      trusted_proxies: Vec::new(),
+    }
+  }
+
+  /// Believe what `proxies` say about their clients in `X-Forwarded-For`.
+  pub fn with_trusted_proxies(self, proxies: Vec<TrustedProxy>) -> Self {
+    Self {
+      trusted_proxies: proxies,
+      ..self
+    }
+  }
+
+  pub fn is_enabled(&self) -> bool {
+    self.enabled
+  }
+
+  /// Who loaded the pixel, given the address it came from.
+  ///
+  /// That is `peer` itself unless it is a trusted proxy: then it is the last
+  /// hop of `X-Forwarded-For` that isn't one. Anybody can send the header, so
+  /// nothing added before that hop can be believed.
+  pub fn client_ip(
+    &self,
+    peer: Option<IpAddr>,
+    headers: &HeaderMap,
+  ) -> Option<IpAddr> {
+    let mut client = peer?;
+    let mut forwarded_for = headers
+      .get_all("X-Forwarded-For")
+      .filter_map(|value| value.to_str().ok())
+      .flat_map(|value| value.split(','))
+      .map(str::trim)
+      .collect::<Vec<_>>()
+      .into_iter()
+      .rev();
+    while self.is_trusted(client) {
+      match forwarded_for.next() {
+        Some(hop) => client = hop.parse().ok()?,
+        None => break,
+      }
+    }
+    Some(client)
+  }
+
+  fn is_trusted(&self, ip: IpAddr) -> bool {
+    self.trusted_proxies.iter().any(|proxy| proxy.contains(ip))
+  }
+
+  /// The pixel recording `email`'s opens of `issue_id`.
+  pub fn pixel_url(&self, issue_id: Uuid, email: &SubscriberEmail) -> String {
+    format!("{}/t/o/{}", self.base_url, self.token(issue_id, email))
+  }
+
+  pub fn token(&self, issue_id: Uuid, email: &SubscriberEmail) -> String {
+    let payload = format!("{}:{}", issue_id, email.as_ref());
+    let signature = self.mac(&payload).finalize().into_bytes();
+    format!("{}.{}", encode(&payload), encode(signature))
+  }
+
+  /// The issue and recipient `token` was issued for, if we signed it.
+  pub fn verify(
+    &self,
+    token: &str,
+  ) -> Result<(Uuid, SubscriberEmail), InvalidTrackingToken> {
+    let (payload, signature) =
+      token.split_once('.').ok_or(InvalidTrackingToken)?;
+    let payload = decode(payload)
+      .and_then(|payload| String::from_utf8(payload).ok())
+      .ok_or(InvalidTrackingToken)?;
+    let signature = decode(signature).ok_or(InvalidTrackingToken)?;
+    self
+      .mac(&payload)
+      .verify_slice(&signature)
+      .map_err(|_| InvalidTrackingToken)?;
+    let (issue_id, email) =
+      payload.split_once(':').ok_or(InvalidTrackingToken)?;
+    let issue_id =
+      Uuid::parse_str(issue_id).map_err(|_| InvalidTrackingToken)?;
+    let email = SubscriberEmail::parse(email.to_owned())
+      .map_err(|_| InvalidTrackingToken)?;
+    Ok((issue_id, email))
+  }
+
+  fn mac(&self, payload: &str) -> HmacSha256 {
+    let mut mac =
+      HmacSha256::new_from_slice(self.hmac_secret.expose_secret().as_bytes())
+        .expect("HMAC accepts keys of any size.");
+    // The same secret signs unsubscribe links, keep the two apart.
+    mac.update(b"open:");
+    mac.update(payload.as_bytes());
+    mac
+  }
+}
+
+/// A proxy in front of the app, e.g. the load balancer: an address, or a
+/// network in CIDR notation such as `10.0.0.0/8`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct TrustedProxy {
+  network: IpAddr,
+  prefix_len: u8,
+}
+
+impl TryFrom<String> for TrustedProxy {
+  type Error = String;
+
+  fn try_from(value: String) -> Result<Self, Self::Error> {
+    let invalid =
+      || format!("`{}` is neither an address nor a network.", value);
+    let (network, prefix_len) = match value.split_once('/') {
+      Some((network, prefix_len)) => (
+        network.parse::<IpAddr>().map_err(|_| invalid())?,
+        Some(prefix_len.parse::<u8>().map_err(|_| invalid())?),
+      ),
+      None => (value.parse::<IpAddr>().map_err(|_| invalid())?, None),
+    };
+    let max_len = if network.is_ipv4() { 32 } else { 128 };
+    let prefix_len = prefix_len.unwrap_or(max_len);
+    if prefix_len > max_len {
+      return Err(invalid());
+    }
+    Ok(Self {
+      network,
+      prefix_len,
+    })
+  }
+}
+
+impl TrustedProxy {
+  fn contains(&self, ip: IpAddr) -> bool {
+    let ip = match ip {
+      IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+      IpAddr::V4(_) => ip,
+    };
+    match (self.network, ip) {
+      (IpAddr::V4(network), IpAddr::V4(ip)) => {
+        let mask = u32::MAX.checked_shl(32 - self.prefix_len as u32);
+        let mask = mask.unwrap_or(0);
+        u32::from(network) & mask == u32::from(ip) & mask
+      }
+      (IpAddr::V6(network), IpAddr::V6(ip)) => {
+        let mask = u128::MAX.checked_shl(128 - self.prefix_len as u32);
+        let mask = mask.unwrap_or(0);
+        u128::from(network) & mask == u128::from(ip) & mask
+      }
+      _ => false,
+    }
+  }
+}
+
+fn encode(bytes: impl AsRef<[u8]>) -> String {
+  base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+fn decode(s: &str) -> Option<Vec<u8>> {
+  base64::decode_config(s, base64::URL_SAFE_NO_PAD).ok()
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("The tracking token is invalid.")]
+pub struct InvalidTrackingToken;
+
+/// `html` with a pixel loading `{{open_pixel_url}}` at the end of its body.
+pub fn with_pixel(html: &str) -> String {
+  let pixel = format!(
+    r#"<img src="{{{{{}}}}}" width="1" height="1" alt="" style="display:block;border:0;width:1px;height:1px;">"#,
+    OPEN_PIXEL_URL
+  );
+  match html.to_ascii_lowercase().rfind("</body>") {
+    Some(end) => format!("{}{}{}", &html[..end], pixel, &html[end..]),
+    None => format!("{}{}", html, pixel),
+  }
+}
+
+/// A pixel loaded by software rather than by a human reading the email.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MachineOpen {
+  /// Apple Mail Privacy Protection loads every image as soon as the email is
+  /// received, from Apple's proxies
+  AppleMailPrivacy,
+  /// Loaded ahead of time, e.g. by a mail client warming its cache
+  Prefetch,
+  /// Security scanners, link checkers and other bots
+  Scanner,
+}
+
+impl MachineOpen {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      MachineOpen::AppleMailPrivacy => "apple_mail_privacy",
+      MachineOpen::Prefetch => "prefetch",
+      MachineOpen::Scanner => "scanner",
+    }
+  }
+}
+
+/// Words in the user agents of scanners and bots, lowercase.
+const SCANNER_USER_AGENTS: [&str; 12] = [
+  "bot",
+  "crawler",
+  "spider",
+  "preview",
+  "scanner",
+  "barracuda",
+  "mimecast",
+  "proofpoint",
+  "curl",
+  "wget",
+  "python",
+  "go-http-client",
+];
+
+/// Tell whether the pixel request with `headers`, coming from `client_ip`,
+/// was sent by software on its own.
+///
+/// Heuristics: a human open may be flagged, and a machine one go unnoticed.
+pub fn detect_machine_open(
+  headers: &HeaderMap,
+  client_ip: Option<IpAddr>,
+) -> Option<MachineOpen> {
+  let header = |name: &str| {
+    headers
+      .get(name)
+      .and_then(|value| value.to_str().ok())
+      .map(|value| value.trim().to_ascii_lowercase())
+  };
+  let prefetch = ["Purpose", "Sec-Purpose", "X-Purpose", "X-Moz"]
+    .into_iter()
+    .filter_map(header)
+    .any(|purpose| purpose.contains("prefetch") || purpose.contains("preview"));
+  if prefetch {
+    return Some(MachineOpen::Prefetch);
+  }
+  let user_agent = header("User-Agent").unwrap_or_default();
+  // Apple's proxies send a bare `Mozilla/5.0`, from Apple's own network.
+  let apple_network =
+    matches!(client_ip, Some(IpAddr::V4(ip)) if ip.octets()[0] == 17);
+  if user_agent == "mozilla/5.0" || apple_network {
+    return Some(MachineOpen::AppleMailPrivacy);
+  }
+  if user_agent.is_empty()
+    || SCANNER_USER_AGENTS
+      .iter()
+      .any(|word| user_agent.contains(word))
+  {
+    return Some(MachineOpen::Scanner);
+  }
+  None
+}
+
+#[cfg(test)]
+mod tests {
+  use std::net::IpAddr;
+
+  use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+  use claim::assert_err;
+  use secrecy::Secret;
+  use uuid::Uuid;
+
+  use super::{
+    detect_machine_open, with_pixel, MachineOpen, OpenTracking, TrustedProxy,
+  };
+  use crate::domain::SubscriberEmail;
+
+  fn tracking(secret: &str) -> OpenTracking {
+    OpenTracking::new(
+      "https://example.com".into(),
+      Secret::new(secret.into()),
+      true,
+    )
+  }
+
+  fn email() -> SubscriberEmail {
+    SubscriberEmail::parse("ursula_le_guin@gmail.com".into()).unwrap()
+  }
+
+  fn headers(headers: &[(&'static str, &'static str)]) -> HeaderMap {
+    let mut map = HeaderMap::new();
+    for (name, value) in headers {
+      map.insert(
+        HeaderName::from_static(name),
+        HeaderValue::from_static(value),
+      );
+    }
+    map
+  }
+
+  const BROWSER: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) \
+    AppleWebKit/605.1.15 (KHTML, like Gecko)";
+
+  #[test]
+  fn tokens_identify_the_issue_and_the_recipient() {
+    let tracking = tracking("secret");
+    let issue_id = Uuid::new_v4();
+    let token = tracking.token(issue_id, &email());
+
+    let (verified_issue_id, verified_email) = tracking.verify(&token).unwrap();
+
+    assert_eq!(verified_issue_id, issue_id);
+    assert_eq!(verified_email.as_ref(), email().as_ref());
+    assert_eq!(
+      tracking.pixel_url(issue_id, &email()),
+      format!("https://example.com/t/o/{}", token)
+    );
+  }
+
+  #[test]
+  fn forged_tokens_are_rejected() {
+    let issue_id = Uuid::new_v4();
+    let token = tracking("another secret").token(issue_id, &email());
+    assert_err!(tracking("secret").verify(&token));
+    assert_err!(tracking("secret").verify("not.a-token"));
+  }
+
+  #[test]
+  fn the_pixel_goes_at_the_end_of_the_body() {
+    assert_eq!(
+      with_pixel("<html><BODY><p>Hi</p></BODY></html>"),
+      "<html><BODY><p>Hi</p><img src=\"{{open_pixel_url}}\" width=\"1\" \
+      height=\"1\" alt=\"\" style=\"display:block;border:0;width:1px;\
+      height:1px;\"></BODY></html>"
+    );
+    assert!(with_pixel("<p>Hi</p>").starts_with("<p>Hi</p><img "));
+  }
+
+  #[test]
+  fn human_opens_are_not_flagged() {
+    let headers = headers(&[("user-agent", BROWSER)]);
+    assert_eq!(detect_machine_open(&headers, None), None);
+  }
+
+  #[test]
+  fn machine_opens_are_flagged() {
+    let apple_ip: IpAddr = "17.58.0.1".parse().unwrap();
+    let test_cases = [
+      (
+        headers(&[("user-agent", "Mozilla/5.0")]),
+        None,
+        MachineOpen::AppleMailPrivacy,
+      ),
+      (
+        headers(&[("user-agent", BROWSER)]),
+        Some(apple_ip),
+        MachineOpen::AppleMailPrivacy,
+      ),
+      (
+        headers(&[("user-agent", BROWSER), ("sec-purpose", "prefetch")]),
+        None,
+        MachineOpen::Prefetch,
+      ),
+      (
+        headers(&[("user-agent", "Barracuda Sentinel (EE)")]),
+        None,
+        MachineOpen::Scanner,
+      ),
+      (headers(&[]), None, MachineOpen::Scanner),
+    ];
+
+    for (headers, client_ip, expected) in test_cases {
+      assert_eq!(
+        detect_machine_open(&headers, client_ip),
+        Some(expected),
+        "{:?} from {:?}",
+        headers,
+        client_ip
+      );
+    }
+  }
+
+  fn proxy(value: &str) -> TrustedProxy {
+    TrustedProxy::try_from(value.to_string()).unwrap()
+  }
+
+  #[test]
+  fn trusted_proxies_are_addresses_or_networks() {
+    let test_cases = [
+      ("10.0.0.1", "10.0.0.1", true),
+      ("10.0.0.1", "10.0.0.2", false),
+      ("10.0.0.0/8", "10.20.30.40", true),
+      ("10.0.0.0/8", "11.0.0.1", false),
+      ("0.0.0.0/0", "17.58.0.1", true),
+      ("10.0.0.0/8", "::ffff:10.0.0.1", true),
+      ("fd00::/8", "fd12::1", true),
+      ("fd00::/8", "fe80::1", false),
+    ];
+    for (proxy_value, ip, expected) in test_cases {
+      assert_eq!(
+        proxy(proxy_value).contains(ip.parse().unwrap()),
+        expected,
+        "{} in {}",
+        ip,
+        proxy_value
+      );
+    }
+    for invalid in ["load-balancer", "10.0.0.0/33", "fd00::/129", "10.0.0.0/"] {
+      assert_err!(TrustedProxy::try_from(invalid.to_string()));
+    }
+  }
+
+  #[test]
+  fn forwarded_for_is_only_believed_from_trusted_proxies() {
+    let behind_proxies = tracking("secret")
+      .with_trusted_proxies(vec![proxy("10.0.0.0/8"), proxy("192.168.0.1")]);
+    let peer = |ip: &str| Some(ip.parse::<IpAddr>().unwrap());
+    let test_cases = [
+      // Straight from the client
+      ("17.58.0.1", "", peer("17.58.0.1")),
+      ("17.58.0.1", "1.2.3.4", peer("17.58.0.1")),
+      // Through the load balancer
+      ("10.0.0.1", "17.58.0.1", peer("17.58.0.1")),
+      ("10.0.0.1", "", peer("10.0.0.1")),
+      // Through several proxies, with a forged hop
+      (
+        "10.0.0.1",
+        "1.2.3.4, 17.58.0.1, 192.168.0.1",
+        peer("17.58.0.1"),
+      ),
+      ("10.0.0.1", "1.2.3.4, 10.0.0.2", peer("1.2.3.4")),
+      ("10.0.0.1", "garbage", None),
+    ];
+    for (peer_ip, forwarded_for, expected) in test_cases {
+      let mut headers = HeaderMap::new();
+      if !forwarded_for.is_empty() {
+        headers.insert(
+          HeaderName::from_static("x-forwarded-for"),
+          HeaderValue::from_static(forwarded_for),
+        );
+      }
+      assert_eq!(
+        behind_proxies.client_ip(peer(peer_ip), &headers),
+        expected,
+        "{} forwarding for {:?}",
+        peer_ip,
+        forwarded_for
+      );
+    }
+    assert_eq!(
+      tracking("secret").client_ip(
+        peer("10.0.0.1"),
+        &headers(&[("x-forwarded-for", "17.58.0.1")])
+      ),
+      peer("10.0.0.1")
+    );
+  }
+}
@@ -0,0 +1,52 @@
+use actix_web::{http::header::ContentType, web::Data, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{authentication::LoggedInUser, utils::e500};
+
+pub async fn admin_dashboard(
+  user: LoggedInUser,
+  pool: Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let username = get_username(user.user_id, &pool).await.map_err(e500)?;
+  Ok(
+    HttpResponse::Ok()
+      .content_type(ContentType::html())
+      .body(format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta http-equiv="content-type" content="text/html; charset=utf-8">
+  <title>Admin dashboard</title>
+</head>
+<body>
+  <p>Welcome {}!</p>
+  <form name="logoutForm" action="/admin/logout" method="post">
+    <input type="submit" value="Logout">
+  </form>
+</body>
+</html>"#,
+        htmlescape::encode_minimal(&username)
+      )),
+  )
+}
+
+#[tracing::instrument(name = "Get username", skip(pool))]
+pub async fn get_username(
+  user_id: Uuid,
+  pool: &PgPool,
+) -> Result<String, anyhow::Error> {
+  let row = sqlx::query!(
+    r#"
+    SELECT username
+    FROM users
+    WHERE user_id = $1
+    "#,
+    user_id,
+  )
+  .fetch_one(pool)
+  .await
+  .context("Failed to perform a query to retrieve a username.")?;
+  Ok(row.username)
+}
@@ -0,0 +1,18 @@
+use actix_web::HttpResponse;
+
+use crate::{
+  authentication::LoggedInUser,
+  session_state::TypedSession,
+  utils::{e500, see_other},
+};
+
+pub async fn log_out(
+  _user: LoggedInUser,
+  session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+  session.log_out();
+  session
+    .insert_flash("You have successfully logged out.")
+    .map_err(e500)?;
+  Ok(see_other("/login"))
+}
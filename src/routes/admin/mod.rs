@@ -0,0 +1,5 @@
+mod dashboard;
+mod logout;
+
+pub use dashboard::admin_dashboard;
+pub use logout::log_out;
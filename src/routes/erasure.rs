@@ -0,0 +1,106 @@
+use actix_web::{
+  web::{Data, Path},
+  HttpResponse,
+};
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
+
+use crate::{
+  authentication::AuthenticatedUser,
+  domain::SuppressedAddress,
+  routes::SuppressionError,
+  suppressions::{suppress, SuppressionSource},
+};
+
+/// Erase everything we know about an address at its owner's request, as the
+/// GDPR's right to erasure requires.
+///
+/// The address itself stays on the suppression list, the only way to make
+/// sure we never email it again.
+#[tracing::instrument(
+  name = "Erase a subscriber",
+  skip(email, pool, user),
+  fields(user_id = %user.user_id)
+)]
+pub async fn erase_subscriber(
+  user: AuthenticatedUser,
+  email: Path<String>,
+  pool: Data<PgPool>,
+) -> Result<HttpResponse, SuppressionError> {
+  let address = SuppressedAddress::parse(email.into_inner())
+    .ok()
+    .filter(|address| !address.is_domain())
+    .ok_or_else(|| {
+      SuppressionError::ValidationError("The email address is invalid.".into())
+    })?;
+  let mut transaction = pool
+    .begin()
+    .await
+    .context("Failed to acquire a Postgres connection from the pool.")?;
+  erase_personal_data(&mut transaction, &address)
+    .await
+    .context("Failed to erase a subscriber's data.")?;
+  suppress(
+    &mut transaction,
+    &address,
+    "Erased at its owner's request",
+    SuppressionSource::Erasure,
+  )
+  .await
+  .context("Failed to suppress an erased address.")?;
+  transaction
+    .commit()
+    .await
+    .context("Failed to commit the transaction erasing a subscriber.")?;
+  Ok(HttpResponse::NoContent().finish())
+}
+
+/// Delete every row mentioning `address`, whatever its case.
+#[tracing::instrument(skip_all)]
+async fn erase_personal_data(
+  transaction: &mut Transaction<'_, Postgres>,
+  address: &SuppressedAddress,
+) -> Result<(), sqlx::Error> {
+  sqlx::query!(
+    r#"
+    DELETE FROM subscription_tokens
+    WHERE subscriber_id IN (
+      SELECT id FROM subscriptions WHERE lower(email) = $1
+    )
+    "#,
+    address.as_ref()
+  )
+  .execute(&mut *transaction)
+  .await?;
+  sqlx::query!(
+    r#"DELETE FROM subscriptions WHERE lower(email) = $1"#,
+    address.as_ref()
+  )
+  .execute(&mut *transaction)
+  .await?;
+  sqlx::query!(
+    r#"DELETE FROM issue_delivery_queue WHERE lower(subscriber_email) = $1"#,
+    address.as_ref()
+  )
+  .execute(&mut *transaction)
+  .await?;
+  sqlx::query!(
+    r#"DELETE FROM send_outcomes WHERE lower(recipient) = $1"#,
+    address.as_ref()
+  )
+  .execute(&mut *transaction)
+  .await?;
+  sqlx::query!(
+    r#"DELETE FROM email_opens WHERE lower(recipient) = $1"#,
+    address.as_ref()
+  )
+  .execute(&mut *transaction)
+  .await?;
+  sqlx::query!(
+    r#"DELETE FROM delivery_events WHERE lower(recipient) = $1"#,
+    address.as_ref()
+  )
+  .execute(&mut *transaction)
+  .await?;
+  Ok(())
+}
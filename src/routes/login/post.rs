@@ -0,0 +1,73 @@
+use actix_web::{
+  error::InternalError,
+  web::{Data, Form},
+  HttpResponse,
+};
+use secrecy::Secret;
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use crate::{
+  authentication::{validate_credentials, AuthError, Credentials},
+  session_state::TypedSession,
+  utils::see_other,
+};
+
+#[derive(Deserialize)]
+pub struct FormData {
+  username: String,
+  password: Secret<String>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum LoginError {
+  #[error("Authentication failed")]
+  AuthError(#[source] anyhow::Error),
+  #[error("Something went wrong")]
+  UnexpectedError(#[from] anyhow::Error),
+}
+
+#[tracing::instrument(
+  name = "Log in an admin",
+  skip(form, pool, session),
+  fields(username = %form.username)
+)]
+pub async fn login(
+  form: Form<FormData>,
+  pool: Data<PgPool>,
+  session: TypedSession,
+) -> Result<HttpResponse, InternalError<LoginError>> {
+  let credentials = Credentials {
+    username: form.0.username,
+    password: form.0.password,
+  };
+
+  match validate_credentials(credentials, &pool).await {
+    Ok(user_id) => {
+      session.renew();
+      session.insert_user_id(user_id).map_err(|e| {
+        login_redirect(&session, LoginError::UnexpectedError(e.into()))
+      })?;
+      Ok(see_other("/admin/dashboard"))
+    }
+    Err(e) => {
+      let e = match e {
+        AuthError::InvalidCredentials(_) => LoginError::AuthError(e.into()),
+        AuthError::UnexpectedError(_) => LoginError::UnexpectedError(e.into()),
+      };
+      Err(login_redirect(&session, e))
+    }
+  }
+}
+
+/// Send the user back to the login form, telling them what went wrong through
+/// a flash message rather than the query string.
+fn login_redirect(
+  session: &TypedSession,
+  e: LoginError,
+) -> InternalError<LoginError> {
+  if let Err(flash_error) = session.insert_flash(e.to_string()) {
+    tracing::error!(error.cause_chain = ?flash_error, "Failed to queue flash message");
+  }
+  InternalError::from_response(e, see_other("/login"))
+}
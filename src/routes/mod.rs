@@ -1,7 +1,25 @@
 //! src/routes/mod.rs
 
+mod admin;
+mod erasure;
 mod health_check;
+mod login;
+mod newsletters;
+mod open_tracking;
 mod subscriptions;
+mod subscriptions_confirm;
+mod subscriptions_unsubscribe;
+mod suppressions;
+mod webhooks;
 
+pub use admin::*;
+pub use erasure::*;
 pub use health_check::*;
+pub use login::*;
+pub use newsletters::*;
+pub use open_tracking::*;
 pub use subscriptions::*;
+pub use subscriptions_confirm::*;
+pub use subscriptions_unsubscribe::*;
+pub use suppressions::*;
+pub use webhooks::*;
@@ -0,0 +1,304 @@
+use crate::{
+  authentication::AuthenticatedUser,
+  email_client::{Attachment, EmailClient},
+  idempotency::{save_response, try_processing, IdempotencyKey, NextAction},
+  markdown,
+};
+use actix_web::{
+  web::{Data, Json},
+  HttpRequest, HttpResponse,
+};
+use anyhow::Context;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+/// Largest issue accepted, attachments included: providers take up to 25MB
+/// of them, base64-encoded as they are in the body.
+pub const MAX_ISSUE_BODY_SIZE: usize = 32 * 1024 * 1024;
+
+#[derive(Deserialize)]
+pub struct BodyData {
+  title: String,
+  content: Content,
+  /// Add a tracking pixel, unless open tracking is disabled altogether
+  #[serde(default)]
+  track_opens: bool,
+  /// Sent along with every email of the issue
+  #[serde(default)]
+  attachments: Vec<AttachmentData>,
+}
+
+/// An attachment as the API receives it
+#[derive(Deserialize)]
+pub struct AttachmentData {
+  filename: String,
+  content_type: String,
+  /// Base64-encoded
+  content: String,
+  /// Makes the attachment inline, referred to as `cid:{content_id}`
+  content_id: Option<String>,
+}
+
+impl AttachmentData {
+  fn parse(self) -> Result<Attachment, anyhow::Error> {
+    let content = base64::decode(&self.content)
+      .with_context(|| format!("`{}` is not valid base64.", self.filename))?;
+    let attachment = match self.content_id {
+      None => Attachment::new(self.filename, &self.content_type, content),
+      Some(content_id) => Attachment::inline(
+        content_id,
+        self.filename,
+        &self.content_type,
+        content,
+      ),
+    }?;
+    Ok(attachment)
+  }
+}
+
+/// Either Markdown, rendered for us, or both versions written by hand.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum Content {
+  Markdown { markdown: String },
+  Rendered { html: String, text: String },
+}
+
+impl Content {
+  /// The HTML and plain text versions of the issue.
+  fn render(self) -> (String, String) {
+    match self {
+      Content::Markdown { markdown } => {
+        let rendered = markdown::render(&markdown);
+        (rendered.html, rendered.text)
+      }
+      Content::Rendered { html, text } => (html, text),
+    }
+  }
+}
+
+/// Acknowledgement that an issue was accepted for background delivery
+#[derive(Serialize)]
+pub struct PublishReceipt {
+  pub newsletter_issue_id: Uuid,
+  pub enqueued: u64,
+}
+
+#[tracing::instrument(
+  name = "Publish a newsletter issue",
+  skip(request, body, pool, email_client, user),
+  fields(title = %body.title, user_id = %user.user_id)
+)]
+pub async fn publish_newsletter(
+  user: AuthenticatedUser,
+  request: HttpRequest,
+  body: Json<BodyData>,
+  pool: Data<PgPool>,
+  email_client: Data<EmailClient>,
+) -> HttpResponse {
+  let idempotency_key = match request.headers().get("Idempotency-Key") {
+    None => None,
+    Some(value) => match value
+      .to_str()
+      .map_err(|e| e.to_string())
+      .and_then(|value| IdempotencyKey::try_from(value.to_owned()))
+    {
+      Ok(key) => Some(key),
+      Err(_) => return HttpResponse::BadRequest().finish(),
+    },
+  };
+  let BodyData {
+    title,
+    content,
+    track_opens,
+    attachments,
+  } = body.0;
+  // Checked now: once queued, the issue must be deliverable.
+  let attachments = match attachments
+    .into_iter()
+    .map(AttachmentData::parse)
+    .collect::<Result<Vec<_>, _>>()
+    .and_then(|attachments| {
+      email_client.check_attachments(&attachments)?;
+      Ok(attachments)
+    }) {
+    Ok(attachments) => attachments,
+    Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+  };
+
+  // Without a key every request is processed; with one, only the first is.
+  let transaction = match &idempotency_key {
+    None => pool.begin().await.map_err(anyhow::Error::from),
+    Some(key) => match try_processing(&pool, key, user.user_id).await {
+      Ok(NextAction::StartProcessing(transaction)) => Ok(transaction),
+      Ok(NextAction::ReturnSavedResponse(saved_response)) => {
+        return saved_response;
+      }
+      Err(e) => Err(e),
+    },
+  };
+  let mut transaction = match transaction {
+    Ok(transaction) => transaction,
+    Err(e) => {
+      tracing::error!(error.cause_chain = ?e, "Failed to start processing");
+      return HttpResponse::InternalServerError().finish();
+    }
+  };
+
+  let (html_content, text_content) = content.render();
+  let newsletter_issue_id = match insert_newsletter_issue(
+    &mut transaction,
+    &title,
+    &text_content,
+    &html_content,
+    track_opens,
+  )
+  .await
+  {
+    Ok(newsletter_issue_id) => newsletter_issue_id,
+    Err(_) => return HttpResponse::InternalServerError().finish(),
+  };
+  if insert_attachments(&mut transaction, newsletter_issue_id, &attachments)
+    .await
+    .is_err()
+  {
+    return HttpResponse::InternalServerError().finish();
+  }
+  let enqueued =
+    match enqueue_delivery_tasks(&mut transaction, newsletter_issue_id).await {
+      Ok(enqueued) => enqueued,
+      Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+  let response = HttpResponse::Accepted().json(PublishReceipt {
+    newsletter_issue_id,
+    enqueued,
+  });
+
+  // Tasks only become visible to the delivery worker once committed, together
+  // with the saved response when the request carries an idempotency key.
+  let response = match idempotency_key {
+    Some(key) => save_response(transaction, &key, user.user_id, response).await,
+    None => transaction
+      .commit()
+      .await
+      .map(|_| response)
+      .map_err(anyhow::Error::from),
+  };
+  match response {
+    Ok(response) => response,
+    Err(e) => {
+      tracing::error!(error.cause_chain = ?e, "Failed to commit newsletter issue");
+      HttpResponse::InternalServerError().finish()
+    }
+  }
+}
+
+#[tracing::instrument(
+  name = "Save newsletter issue details in the database",
+  skip_all
+)]
+async fn insert_newsletter_issue(
+  transaction: &mut Transaction<'_, Postgres>,
+  title: &str,
+  text_content: &str,
+  html_content: &str,
+  track_opens: bool,
+) -> Result<Uuid, sqlx::Error> {
+  let newsletter_issue_id = Uuid::new_v4();
+  sqlx::query!(
+    r#"
+    INSERT INTO newsletter_issues (
+      newsletter_issue_id,
+      title,
+      text_content,
+      html_content,
+      published_at,
+      track_opens
+    )
+    VALUES ($1, $2, $3, $4, $5, $6)
+    "#,
+    newsletter_issue_id,
+    title,
+    text_content,
+    html_content,
+    Utc::now(),
+    track_opens
+  )
+  .execute(transaction)
+  .await
+  .map_err(|e| {
+    tracing::error!("Failed to execute query: {:?}", e);
+    e
+  })?;
+  Ok(newsletter_issue_id)
+}
+
+#[tracing::instrument(
+  name = "Save newsletter issue attachments in the database",
+  skip(transaction, attachments),
+  fields(n_attachments = attachments.len())
+)]
+async fn insert_attachments(
+  transaction: &mut Transaction<'_, Postgres>,
+  newsletter_issue_id: Uuid,
+  attachments: &[Attachment],
+) -> Result<(), sqlx::Error> {
+  for (position, attachment) in attachments.iter().enumerate() {
+    sqlx::query!(
+      r#"
+      INSERT INTO newsletter_issue_attachments (
+        newsletter_issue_id,
+        position,
+        filename,
+        content_type,
+        content,
+        content_id
+      )
+      VALUES ($1, $2, $3, $4, $5, $6)
+      "#,
+      newsletter_issue_id,
+      position as i32,
+      attachment.filename(),
+      attachment.content_type(),
+      attachment.content(),
+      attachment.content_id(),
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| {
+      tracing::error!("Failed to execute query: {:?}", e);
+      e
+    })?;
+  }
+  Ok(())
+}
+
+/// Queue one delivery task per confirmed subscriber, returning how many were
+/// queued.
+#[tracing::instrument(name = "Enqueue delivery tasks", skip(transaction))]
+async fn enqueue_delivery_tasks(
+  transaction: &mut Transaction<'_, Postgres>,
+  newsletter_issue_id: Uuid,
+) -> Result<u64, sqlx::Error> {
+  let result = sqlx::query!(
+    r#"
+    INSERT INTO issue_delivery_queue (
+      newsletter_issue_id,
+      subscriber_email
+    )
+    SELECT $1, email
+    FROM subscriptions
+    WHERE status = 'confirmed'
+    "#,
+    newsletter_issue_id,
+  )
+  .execute(transaction)
+  .await
+  .map_err(|e| {
+    tracing::error!("Failed to execute query: {:?}", e);
+    e
+  })?;
+  Ok(result.rows_affected())
+}
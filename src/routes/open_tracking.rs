@@ -0,0 +1,101 @@
+use actix_web::{
+  http::header::{CacheControl, CacheDirective, USER_AGENT},
+  web::{Data, Path},
+  HttpRequest, HttpResponse,
+};
+use chrono::Utc;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+  domain::SubscriberEmail,
+  open_tracking::{detect_machine_open, MachineOpen, OpenTracking},
+};
+
+/// A transparent 1x1 GIF
+const PIXEL: &[u8] = b"GIF89a\x01\x00\x01\x00\x80\x00\x00\x00\x00\x00\xff\xff\xff\
+  !\xf9\x04\x01\x00\x00\x00\x00,\x00\x00\x00\x00\x01\x00\x01\x00\x00\x02\x02D\x01\x00;";
+
+/// Record that the recipient of a tracking pixel opened the issue, answering
+/// with the pixel itself.
+///
+/// With open tracking disabled, nothing is recorded: the pixel still loads in
+/// emails sent before. Neither is anything recorded for an address whose
+/// owner asked us to erase their data, see `store_open`.
+#[tracing::instrument(name = "Record an open", skip_all)]
+pub async fn track_open(
+  token: Path<String>,
+  request: HttpRequest,
+  pool: Data<PgPool>,
+  open_tracking: Data<OpenTracking>,
+) -> HttpResponse {
+  if open_tracking.is_enabled() {
+    let (issue_id, email) = match open_tracking.verify(&token) {
+      Ok(verified) => verified,
+      Err(_) => return HttpResponse::NotFound().finish(),
+    };
+    // Not `realip_remote_addr`: anyone can send a Forwarded header.
+    let client_ip = open_tracking.client_ip(
+      request.peer_addr().map(|address| address.ip()),
+      request.headers(),
+    );
+    let machine_open = detect_machine_open(request.headers(), client_ip);
+    let user_agent = request
+      .headers()
+      .get(USER_AGENT)
+      .and_then(|user_agent| user_agent.to_str().ok());
+    if let Err(e) =
+      store_open(&pool, issue_id, &email, user_agent, machine_open).await
+    {
+      // A missed open is better than a broken image.
+      tracing::error!(error.cause_chain = ?e, "Failed to record an open");
+    }
+  }
+  HttpResponse::Ok()
+    .content_type("image/gif")
+    // Every open must reach us, not a cache.
+    .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+    .body(PIXEL)
+}
+
+/// Record an open, unless the recipient's data was erased since we sent them
+/// the issue: their pixel keeps working, but we must not learn anything new
+/// about them.
+#[tracing::instrument(skip(pool, email))]
+async fn store_open(
+  pool: &PgPool,
+  issue_id: Uuid,
+  email: &SubscriberEmail,
+  user_agent: Option<&str>,
+  machine_open: Option<MachineOpen>,
+) -> Result<(), sqlx::Error> {
+  sqlx::query!(
+    r#"
+    INSERT INTO email_opens (
+      email_open_id,
+      newsletter_issue_id,
+      recipient,
+      opened_at,
+      user_agent,
+      machine_open
+    )
+    SELECT $1::uuid, $2::uuid, $3::text, $4::timestamptz, $5::text, $6::text
+    WHERE EXISTS (
+      SELECT 1 FROM subscriptions WHERE lower(email) = lower($3)
+    )
+    AND NOT EXISTS (
+      SELECT 1 FROM suppressions
+      WHERE address = lower($3) AND source = 'erasure'
+    )
+    "#,
+    Uuid::new_v4(),
+    issue_id,
+    email.as_ref(),
+    Utc::now(),
+    user_agent,
+    machine_open.as_ref().map(MachineOpen::as_str),
+  )
+  .execute(pool)
+  .await?;
+  Ok(())
+}
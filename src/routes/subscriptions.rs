@@ -1,13 +1,24 @@
-use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
+use crate::{
+  domain::{NewSubscriber, NewSubscriberError},
+  email_client::{store_send_outcomes, EmailClient, SendOutcome},
+  email_templates::{ConfirmationEmail, EmailTemplates},
+  startup::ApplicationBaseUrl,
+  utils::error_chain_fmt,
+};
 use actix_web::{
-  web::{Data, Form},
-  HttpResponse,
+  error::JsonPayloadError,
+  http::StatusCode,
+  web::{Data, Form, Json},
+  HttpRequest, HttpResponse, ResponseError,
 };
+use anyhow::Context;
 use chrono::Utc;
-use serde::Deserialize;
-use sqlx::PgPool;
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
+/// A subscription request, posted as a form or as JSON.
 #[derive(Deserialize)]
 pub struct FormData {
   email: String,
@@ -15,19 +26,122 @@ pub struct FormData {
 }
 
 impl TryFrom<FormData> for NewSubscriber {
-  type Error = String;
+  type Error = NewSubscriberError;
 
-  /// Converts wire-format data to valid domain model, or returns error string
+  /// Converts wire-format data to valid domain model
   fn try_from(form: FormData) -> Result<Self, Self::Error> {
-    let email = SubscriberEmail::parse(form.email)?;
-    let name = SubscriberName::parse(form.name)?;
-    Ok(Self { email, name })
+    NewSubscriber::parse(form.email, form.name)
+  }
+}
+
+#[derive(thiserror::Error)]
+pub enum SubscribeError {
+  #[error(transparent)]
+  ValidationError(#[from] NewSubscriberError),
+  #[error("Something went wrong.")]
+  UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SubscribeError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    error_chain_fmt(self, f)
+  }
+}
+
+/// Body of every failed `POST /subscriptions` and `POST /api/v1/subscriptions`,
+/// e.g.
+///
+/// ```json
+/// {
+///   "error": "validation_error",
+///   "message": "The name is empty.",
+///   "fields": { "name": "The name is empty." }
+/// }
+/// ```
+///
+/// `error` is one of `invalid_body` (400, the JSON is malformed or misses a
+/// field), `validation_error` (400, the fields listed in `fields` need fixing)
+/// or `unexpected_error` (500, retrying later may work). Clients should branch
+/// on it, messages are meant for humans and may change.
+#[derive(Serialize)]
+pub struct ErrorBody {
+  pub error: &'static str,
+  pub message: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub fields: Option<FieldErrors>,
+}
+
+/// Why each invalid field was refused, absent fields are valid.
+#[derive(Serialize)]
+pub struct FieldErrors {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub email: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub name: Option<String>,
+}
+
+impl From<&NewSubscriberError> for FieldErrors {
+  fn from(e: &NewSubscriberError) -> Self {
+    Self {
+      email: e.email.as_ref().map(ToString::to_string),
+      name: e.name.as_ref().map(ToString::to_string),
+    }
+  }
+}
+
+impl ResponseError for SubscribeError {
+  fn status_code(&self) -> StatusCode {
+    match self {
+      SubscribeError::ValidationError(_) => StatusCode::BAD_REQUEST,
+      SubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+  }
+
+  fn error_response(&self) -> HttpResponse {
+    let (error, fields) = match self {
+      SubscribeError::ValidationError(e) => {
+        ("validation_error", Some(FieldErrors::from(e)))
+      }
+      SubscribeError::UnexpectedError(_) => ("unexpected_error", None),
+    };
+    // The unexpected error's sources stay in the logs.
+    HttpResponse::build(self.status_code()).json(ErrorBody {
+      error,
+      message: self.to_string(),
+      fields,
+    })
   }
 }
 
+/// Answer JSON bodies we can't deserialize with an `ErrorBody` too, rather
+/// than actix's plain text.
+pub fn invalid_json_body(
+  e: JsonPayloadError,
+  _: &HttpRequest,
+) -> actix_web::Error {
+  let response = HttpResponse::BadRequest().json(ErrorBody {
+    error: "invalid_body",
+    message: e.to_string(),
+    fields: None,
+  });
+  actix_web::error::InternalError::from_response(e, response).into()
+}
+
+/// A subscriber, as returned by the JSON API.
+///
+/// `status` is always `pending_confirmation`, even for an address that was
+/// already confirmed: answers must not tell whether someone is on the list.
+#[derive(Serialize)]
+pub struct Subscriber {
+  pub id: Uuid,
+  pub email: String,
+  pub name: String,
+  pub status: &'static str,
+}
+
 #[tracing::instrument(
   name = "Adding a new subscriber.",
-  skip(form, pool),
+  skip(form, pool, email_client, templates, base_url),
   fields(
     subscriber_email = %form.email,
     subscriber_name = %form.name
@@ -36,42 +150,256 @@ impl TryFrom<FormData> for NewSubscriber {
 pub async fn subscribe(
   form: Form<FormData>,
   pool: Data<PgPool>,
-) -> HttpResponse {
-  let new_subscriber = match form.0.try_into() {
-    Ok(new) => new,
-    Err(_) => return HttpResponse::BadRequest().finish(),
+  email_client: Data<EmailClient>,
+  templates: Data<EmailTemplates>,
+  base_url: Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, SubscribeError> {
+  let new_subscriber = form.0.try_into()?;
+  register_subscriber(
+    &pool,
+    &email_client,
+    &templates,
+    &base_url.0,
+    new_subscriber,
+  )
+  .await?;
+  Ok(HttpResponse::Ok().finish())
+}
+
+#[tracing::instrument(
+  name = "Adding a new subscriber through the API.",
+  skip(body, pool, email_client, templates, base_url),
+  fields(
+    subscriber_email = %body.email,
+    subscriber_name = %body.name
+  )
+)]
+pub async fn api_subscribe(
+  body: Json<FormData>,
+  pool: Data<PgPool>,
+  email_client: Data<EmailClient>,
+  templates: Data<EmailTemplates>,
+  base_url: Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, SubscribeError> {
+  let new_subscriber: NewSubscriber = body.0.try_into()?;
+  let email = new_subscriber.email.as_ref().to_owned();
+  let name = new_subscriber.name.as_ref().to_owned();
+  let id = register_subscriber(
+    &pool,
+    &email_client,
+    &templates,
+    &base_url.0,
+    new_subscriber,
+  )
+  .await?;
+  Ok(HttpResponse::Created().json(Subscriber {
+    id,
+    email,
+    name,
+    status: "pending_confirmation",
+  }))
+}
+
+/// Store `new_subscriber` as pending and email them a confirmation link,
+/// returning their id.
+///
+/// Subscribing again is harmless: a pending subscriber gets another
+/// confirmation email, an unsubscribed one goes back to pending and gets one
+/// too, and a confirmed subscriber is left as is. So is a suppressed one: their
+/// address bounced or they reported us as spam, emailing them would only make
+/// things worse.
+async fn register_subscriber(
+  pool: &PgPool,
+  email_client: &EmailClient,
+  templates: &EmailTemplates,
+  base_url: &str,
+  new_subscriber: NewSubscriber,
+) -> Result<Uuid, SubscribeError> {
+  let mut transaction = pool
+    .begin()
+    .await
+    .context("Failed to acquire a Postgres connection from the pool.")?;
+  let subscriber_id = match insert_subscriber(&mut transaction, &new_subscriber)
+    .await
+    .context("Failed to insert a new subscriber in the database.")?
+  {
+    Some(subscriber_id) => subscriber_id,
+    None => {
+      let existing = get_subscriber_by_email(&mut transaction, &new_subscriber)
+        .await
+        .context("Failed to retrieve an existing subscriber.")?;
+      match existing.status.as_str() {
+        "confirmed" | "suppressed" => return Ok(existing.id),
+        "unsubscribed" => {
+          resubscribe(&mut transaction, existing.id)
+            .await
+            .context("Failed to mark a subscriber as pending again.")?;
+        }
+        _ => {}
+      }
+      existing.id
+    }
   };
-  match insert_subscriber(&pool, &new_subscriber).await {
-    Ok(_) => HttpResponse::Ok().finish(),
-    Err(_) => HttpResponse::InternalServerError().finish(),
+  let subscription_token = generate_subscription_token();
+  store_token(&mut transaction, subscriber_id, &subscription_token)
+    .await
+    .context("Failed to store the confirmation token for a new subscriber.")?;
+  transaction
+    .commit()
+    .await
+    .context("Failed to commit the transaction storing a new subscriber.")?;
+  let outcome = send_confirmation_email(
+    email_client,
+    templates,
+    new_subscriber,
+    base_url,
+    &subscription_token,
+  )
+  .await
+  .context("Failed to send a confirmation email.")?;
+  if let Err(e) = store_send_outcomes(pool, None, &[outcome]).await {
+    // The email is already on its way, don't make the subscriber retry.
+    tracing::error!(
+      error.cause_chain = ?e,
+      "Failed to store the outcome of the confirmation email",
+    );
   }
+  Ok(subscriber_id)
+}
+
+/// Generate a random 25-characters-long case-sensitive subscription token.
+fn generate_subscription_token() -> String {
+  let mut rng = thread_rng();
+  std::iter::repeat_with(|| rng.sample(Alphanumeric))
+    .map(char::from)
+    .take(25)
+    .collect()
 }
 
+#[tracing::instrument(
+  name = "Send a confirmation email to a new subscriber",
+  skip(email_client, templates, new_subscriber, base_url, subscription_token)
+)]
+pub async fn send_confirmation_email(
+  email_client: &EmailClient,
+  templates: &EmailTemplates,
+  new_subscriber: NewSubscriber,
+  base_url: &str,
+  subscription_token: &str,
+) -> Result<SendOutcome, anyhow::Error> {
+  let confirmation_link = format!(
+    "{}/subscriptions/confirm?subscription_token={}",
+    base_url, subscription_token
+  );
+  let email = templates.render(&ConfirmationEmail {
+    confirmation_link: &confirmation_link,
+  })?;
+  let outcome = email_client
+    .send_email(
+      &new_subscriber.email,
+      "Welcome!",
+      &email.html,
+      &email.text,
+      &[],
+    )
+    .await?;
+  Ok(outcome)
+}
+
+/// Insert `new_subscriber` as pending, returning `None` if their address is
+/// already on the list.
 #[tracing::instrument(
   name = "Saving new subscriber details in the database",
-  skip(new_subscriber, pool)
+  skip(new_subscriber, transaction)
 )]
 pub async fn insert_subscriber(
-  pool: &PgPool,
+  transaction: &mut Transaction<'_, Postgres>,
   new_subscriber: &NewSubscriber,
-) -> Result<(), sqlx::Error> {
-  sqlx::query!(
+) -> Result<Option<Uuid>, sqlx::Error> {
+  let subscriber_id = Uuid::new_v4();
+  // Concurrent requests for the same address wait on each other here, only
+  // the first one inserts.
+  let inserted = sqlx::query!(
     r#"
-    INSERT INTO subscriptions (id, email, name, subscribed_at)
-    VALUES ($1, $2, $3, $4)
+    INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+    VALUES ($1, $2, $3, $4, 'pending_confirmation')
+    ON CONFLICT ((lower(email))) DO NOTHING
     "#,
-    Uuid::new_v4(),
+    subscriber_id,
     new_subscriber.email.as_ref(),
     new_subscriber.name.as_ref(),
     Utc::now()
   )
-  .execute(pool)
+  .execute(transaction)
+  .await?
+  .rows_affected();
+  Ok((inserted > 0).then_some(subscriber_id))
+}
+
+pub struct ExistingSubscriber {
+  pub id: Uuid,
+  pub status: String,
+}
+
+#[tracing::instrument(
+  name = "Get an existing subscriber by email",
+  skip(new_subscriber, transaction)
+)]
+pub async fn get_subscriber_by_email(
+  transaction: &mut Transaction<'_, Postgres>,
+  new_subscriber: &NewSubscriber,
+) -> Result<ExistingSubscriber, sqlx::Error> {
+  sqlx::query_as!(
+    ExistingSubscriber,
+    r#"
+    SELECT id, status FROM subscriptions
+    WHERE lower(email) = lower($1)
+    FOR UPDATE
+    "#,
+    new_subscriber.email.as_ref(),
+  )
+  .fetch_one(transaction)
   .await
-  .map_err(|e| {
-    tracing::error!("Failed to execute query: {:?}", e);
-    e
-    // By using the `?` operator, we can return the function early if it fails
-    // with an sqlx::Error error.
-  })?;
+}
+
+#[tracing::instrument(
+  name = "Mark an unsubscribed subscriber as pending",
+  skip(transaction)
+)]
+pub async fn resubscribe(
+  transaction: &mut Transaction<'_, Postgres>,
+  subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+  sqlx::query!(
+    r#"
+    UPDATE subscriptions SET status = 'pending_confirmation'
+    WHERE id = $1 AND status = 'unsubscribed'
+    "#,
+    subscriber_id,
+  )
+  .execute(transaction)
+  .await?;
+  Ok(())
+}
+
+#[tracing::instrument(
+  name = "Store subscription token in the database",
+  skip(subscription_token, transaction)
+)]
+pub async fn store_token(
+  transaction: &mut Transaction<'_, Postgres>,
+  subscriber_id: Uuid,
+  subscription_token: &str,
+) -> Result<(), sqlx::Error> {
+  sqlx::query!(
+    r#"
+    INSERT INTO subscription_tokens (subscription_token, subscriber_id)
+    VALUES ($1, $2)
+    "#,
+    subscription_token,
+    subscriber_id
+  )
+  .execute(transaction)
+  .await?;
   Ok(())
 }
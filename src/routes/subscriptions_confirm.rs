@@ -0,0 +1,113 @@
+use actix_web::{
+  web::{Data, Query},
+  HttpResponse,
+};
+use serde::Deserialize;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+#[derive(Deserialize)]
+pub struct Parameters {
+  subscription_token: String,
+}
+
+#[tracing::instrument(
+  name = "Confirm a pending subscriber",
+  skip(parameters, pool)
+)]
+pub async fn confirm(
+  parameters: Query<Parameters>,
+  pool: Data<PgPool>,
+) -> HttpResponse {
+  let mut transaction = match pool.begin().await {
+    Ok(transaction) => transaction,
+    Err(_) => return HttpResponse::InternalServerError().finish(),
+  };
+  // Tokens are single-use: consuming it in the same transaction as the
+  // confirmation means an old link can never flip the subscriber back.
+  let id = match take_subscriber_id_from_token(
+    &mut transaction,
+    &parameters.subscription_token,
+  )
+  .await
+  {
+    Ok(id) => id,
+    Err(_) => return HttpResponse::InternalServerError().finish(),
+  };
+  match id {
+    // Non-existing or already used token!
+    None => HttpResponse::Unauthorized().finish(),
+    Some(subscriber_id) => {
+      if confirm_subscriber(&mut transaction, subscriber_id)
+        .await
+        .is_err()
+      {
+        return HttpResponse::InternalServerError().finish();
+      }
+      if transaction.commit().await.is_err() {
+        return HttpResponse::InternalServerError().finish();
+      }
+      HttpResponse::Ok().finish()
+    }
+  }
+}
+
+#[tracing::instrument(
+  name = "Mark subscriber as confirmed",
+  skip(subscriber_id, transaction)
+)]
+pub async fn confirm_subscriber(
+  transaction: &mut Transaction<'_, Postgres>,
+  subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+  sqlx::query!(
+    r#"
+    UPDATE subscriptions SET status = 'confirmed'
+    WHERE id = $1 AND status = 'pending_confirmation'
+    "#,
+    subscriber_id,
+  )
+  .execute(&mut *transaction)
+  .await
+  .map_err(|e| {
+    tracing::error!("Failed to execute query: {:?}", e);
+    e
+  })?;
+  // Any other confirmation email they got is now moot.
+  sqlx::query!(
+    r#"DELETE FROM subscription_tokens WHERE subscriber_id = $1"#,
+    subscriber_id,
+  )
+  .execute(&mut *transaction)
+  .await
+  .map_err(|e| {
+    tracing::error!("Failed to execute query: {:?}", e);
+    e
+  })?;
+  Ok(())
+}
+
+#[tracing::instrument(
+  name = "Take subscriber_id from token",
+  skip(subscription_token, transaction)
+)]
+pub async fn take_subscriber_id_from_token(
+  transaction: &mut Transaction<'_, Postgres>,
+  subscription_token: &str,
+) -> Result<Option<Uuid>, sqlx::Error> {
+  let result = sqlx::query!(
+    r#"
+    DELETE FROM subscription_tokens
+    WHERE subscription_token = $1
+    RETURNING subscriber_id
+    "#,
+    subscription_token,
+  )
+  .fetch_optional(transaction)
+  .await
+  .map_err(|e| {
+    tracing::error!("Failed to execute query: {:?}", e);
+    e
+  })?;
+  Ok(result.map(|r| r.subscriber_id))
+}
@@ -0,0 +1,118 @@
+use actix_web::{
+  http::header::ContentType,
+  web::{Data, Query},
+  HttpResponse,
+};
+use anyhow::Context;
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use crate::{
+  domain::SubscriberEmail, unsubscribe::UnsubscribeLinks, utils::e500,
+};
+
+#[derive(Deserialize)]
+pub struct UnsubscribeParameters {
+  token: String,
+}
+
+/// Ask for confirmation before unsubscribing, as link scanners and previews
+/// follow `GET` links on their own.
+#[tracing::instrument(
+  name = "Show the unsubscribe page",
+  skip(parameters, unsubscribe_links)
+)]
+pub async fn unsubscribe_form(
+  parameters: Query<UnsubscribeParameters>,
+  unsubscribe_links: Data<UnsubscribeLinks>,
+) -> HttpResponse {
+  let email = match unsubscribe_links.verify(&parameters.token) {
+    Ok(email) => email,
+    Err(_) => return HttpResponse::Unauthorized().finish(),
+  };
+  HttpResponse::Ok()
+    .content_type(ContentType::html())
+    .body(format!(
+      r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta http-equiv="content-type" content="text/html; charset=utf-8">
+  <title>Unsubscribe</title>
+</head>
+<body>
+  <p>Stop sending our newsletter to {}?</p>
+  <form action="/subscriptions/unsubscribe?token={}" method="post">
+    <button type="submit">Unsubscribe</button>
+  </form>
+</body>
+</html>"#,
+      htmlescape::encode_minimal(email.as_ref()),
+      htmlescape::encode_attribute(&parameters.token),
+    ))
+}
+
+/// Unsubscribe from the page above, or in one click from the mail client
+/// (RFC 8058), which posts `List-Unsubscribe=One-Click` to the link.
+///
+/// Answers the same whether the address was on the list or not.
+#[tracing::instrument(
+  name = "Unsubscribe a subscriber",
+  skip(parameters, pool, unsubscribe_links)
+)]
+pub async fn unsubscribe(
+  parameters: Query<UnsubscribeParameters>,
+  pool: Data<PgPool>,
+  unsubscribe_links: Data<UnsubscribeLinks>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let email = match unsubscribe_links.verify(&parameters.token) {
+    Ok(email) => email,
+    Err(_) => return Ok(HttpResponse::Unauthorized().finish()),
+  };
+  mark_as_unsubscribed(&pool, &email)
+    .await
+    .context("Failed to unsubscribe a subscriber.")
+    .map_err(e500)?;
+  Ok(HttpResponse::Ok().content_type(ContentType::html()).body(
+    r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta http-equiv="content-type" content="text/html; charset=utf-8">
+  <title>Unsubscribed</title>
+</head>
+<body>
+  <p>You will not receive our newsletter anymore.</p>
+</body>
+</html>"#,
+  ))
+}
+
+/// Unsubscribe `email`, revoking their pending confirmation links so that an
+/// old email can't subscribe them again.
+#[tracing::instrument(name = "Mark subscriber as unsubscribed", skip(pool))]
+pub async fn mark_as_unsubscribed(
+  pool: &PgPool,
+  email: &SubscriberEmail,
+) -> Result<(), sqlx::Error> {
+  let mut transaction = pool.begin().await?;
+  sqlx::query!(
+    r#"
+    UPDATE subscriptions SET status = 'unsubscribed'
+    WHERE lower(email) = lower($1) AND status <> 'suppressed'
+    "#,
+    email.as_ref(),
+  )
+  .execute(&mut transaction)
+  .await?;
+  sqlx::query!(
+    r#"
+    DELETE FROM subscription_tokens
+    WHERE subscriber_id IN (
+      SELECT id FROM subscriptions WHERE lower(email) = lower($1)
+    )
+    "#,
+    email.as_ref(),
+  )
+  .execute(&mut transaction)
+  .await?;
+  transaction.commit().await
+}
@@ -0,0 +1,150 @@
+use actix_web::{
+  http::StatusCode,
+  web::{Data, Json, Path},
+  HttpResponse, ResponseError,
+};
+use anyhow::Context;
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use crate::{
+  authentication::AuthenticatedUser,
+  domain::SuppressedAddress,
+  routes::ErrorBody,
+  suppressions::{
+    get_suppression, list_suppressions, suppress, unsuppress, SuppressionSource,
+  },
+  utils::error_chain_fmt,
+};
+
+/// An address, or a domain, an admin wants nothing sent to.
+#[derive(Deserialize)]
+pub struct NewSuppression {
+  address: String,
+  reason: String,
+}
+
+/// Failures of the suppression API, answered with an `ErrorBody` whose
+/// `error` is `validation_error` (400), `not_found` (404) or
+/// `unexpected_error` (500).
+#[derive(thiserror::Error)]
+pub enum SuppressionError {
+  #[error("{0}")]
+  ValidationError(String),
+  #[error("The address is not on the suppression list.")]
+  NotFound,
+  #[error("Something went wrong.")]
+  UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SuppressionError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    error_chain_fmt(self, f)
+  }
+}
+
+impl ResponseError for SuppressionError {
+  fn status_code(&self) -> StatusCode {
+    match self {
+      SuppressionError::ValidationError(_) => StatusCode::BAD_REQUEST,
+      SuppressionError::NotFound => StatusCode::NOT_FOUND,
+      SuppressionError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+  }
+
+  fn error_response(&self) -> HttpResponse {
+    let error = match self {
+      SuppressionError::ValidationError(_) => "validation_error",
+      SuppressionError::NotFound => "not_found",
+      SuppressionError::UnexpectedError(_) => "unexpected_error",
+    };
+    HttpResponse::build(self.status_code()).json(ErrorBody {
+      error,
+      message: self.to_string(),
+      fields: None,
+    })
+  }
+}
+
+fn parse_address(
+  address: String,
+) -> Result<SuppressedAddress, SuppressionError> {
+  SuppressedAddress::parse(address)
+    .map_err(|e| SuppressionError::ValidationError(e.to_string()))
+}
+
+/// Every suppressed address and domain, most recent first.
+#[tracing::instrument(
+  name = "List suppressions",
+  skip(pool, user),
+  fields(user_id = %user.user_id)
+)]
+pub async fn get_suppressions(
+  user: AuthenticatedUser,
+  pool: Data<PgPool>,
+) -> Result<HttpResponse, SuppressionError> {
+  let suppressions = list_suppressions(&pool)
+    .await
+    .context("Failed to list suppressions.")?;
+  Ok(HttpResponse::Ok().json(suppressions))
+}
+
+/// Block an address or a domain by hand, answering `201 Created` with the
+/// new suppression, or `200 OK` with the existing one if it was already
+/// suppressed.
+#[tracing::instrument(
+  name = "Add a suppression",
+  skip(body, pool, user),
+  fields(address = %body.address, user_id = %user.user_id)
+)]
+pub async fn add_suppression(
+  user: AuthenticatedUser,
+  body: Json<NewSuppression>,
+  pool: Data<PgPool>,
+) -> Result<HttpResponse, SuppressionError> {
+  let NewSuppression { address, reason } = body.0;
+  let address = parse_address(address)?;
+  if reason.trim().is_empty() {
+    return Err(SuppressionError::ValidationError(
+      "The reason is empty.".into(),
+    ));
+  }
+  let inserted =
+    suppress(pool.get_ref(), &address, &reason, SuppressionSource::Admin)
+      .await
+      .context("Failed to add a suppression.")?;
+  let suppression = get_suppression(&pool, &address)
+    .await
+    .context("Failed to retrieve a suppression.")?
+    .context("A suppression disappeared right after being added.")?;
+  let status = if inserted {
+    StatusCode::CREATED
+  } else {
+    StatusCode::OK
+  };
+  Ok(HttpResponse::build(status).json(suppression))
+}
+
+/// Allow emails to an address or a domain again.
+///
+/// Subscriptions suppressed along with an address are marked unsubscribed:
+/// their owners have to subscribe again.
+#[tracing::instrument(
+  name = "Remove a suppression",
+  skip(pool, user),
+  fields(user_id = %user.user_id)
+)]
+pub async fn remove_suppression(
+  user: AuthenticatedUser,
+  address: Path<String>,
+  pool: Data<PgPool>,
+) -> Result<HttpResponse, SuppressionError> {
+  let address = parse_address(address.into_inner())?;
+  let removed = unsuppress(&pool, &address)
+    .await
+    .context("Failed to remove a suppression.")?;
+  if !removed {
+    return Err(SuppressionError::NotFound);
+  }
+  Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,39 @@
+use actix_web::{
+  web::{Bytes, Data, Path},
+  HttpRequest, HttpResponse,
+};
+use anyhow::Context;
+use sqlx::PgPool;
+
+use crate::{
+  configuration::WebhookSettings,
+  startup::ApplicationBaseUrl,
+  webhooks::{parse_webhook, store_delivery_events, WebhookError},
+};
+
+/// Record the delivery events `provider` reports, suppressing subscribers
+/// whose address bounced for good or who complained.
+#[tracing::instrument(
+  name = "Receive a webhook",
+  skip(request, body, pool, settings, base_url)
+)]
+pub async fn receive_webhook(
+  provider: Path<String>,
+  request: HttpRequest,
+  body: Bytes,
+  pool: Data<PgPool>,
+  settings: Data<WebhookSettings>,
+  base_url: Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, WebhookError> {
+  let events =
+    parse_webhook(&provider, &settings, &base_url.0, &request, &body)?;
+  store_delivery_events(&pool, &provider, &events)
+    .await
+    .context("Failed to store delivery events.")?;
+  Ok(HttpResponse::Ok().finish())
+}
+
+/// Mandrill checks a webhook's URL answers a `HEAD` before saving it.
+pub async fn webhook_check() -> HttpResponse {
+  HttpResponse::Ok().finish()
+}
@@ -0,0 +1,79 @@
+use std::future::{ready, Ready};
+
+use actix_session::{Session, SessionExt, SessionGetError, SessionInsertError};
+use actix_web::{dev::Payload, FromRequest, HttpRequest};
+use uuid::Uuid;
+
+/// Strongly-typed view over the admin session.
+///
+/// Keeps session keys in one place instead of spreading string literals across
+/// request handlers.
+pub struct TypedSession(Session);
+
+impl TypedSession {
+  const USER_ID_KEY: &'static str = "user_id";
+  const FLASH_KEY: &'static str = "_flash";
+
+  /// Rotate the session key, preventing session fixation on login.
+  pub fn renew(&self) {
+    self.0.renew();
+  }
+
+  pub fn insert_user_id(
+    &self,
+    user_id: Uuid,
+  ) -> Result<(), SessionInsertError> {
+    self.0.insert(Self::USER_ID_KEY, user_id)
+  }
+
+  pub fn get_user_id(&self) -> Result<Option<Uuid>, SessionGetError> {
+    self.0.get(Self::USER_ID_KEY)
+  }
+
+  /// Forget everything stored in the session and rotate its key.
+  ///
+  /// Unlike purging, the session stays usable for the rest of the request, so
+  /// a flash message can still be queued for the login page.
+  pub fn log_out(&self) {
+    self.0.clear();
+    self.0.renew();
+  }
+
+  /// Queue a message to be shown on the next page rendered for this session.
+  pub fn insert_flash(
+    &self,
+    message: impl Into<String>,
+  ) -> Result<(), SessionInsertError> {
+    let mut messages: Vec<String> = self
+      .0
+      .get(Self::FLASH_KEY)
+      .ok()
+      .flatten()
+      .unwrap_or_default();
+    messages.push(message.into());
+    self.0.insert(Self::FLASH_KEY, messages)
+  }
+
+  /// Take all queued flash messages. Each message is only ever returned once.
+  pub fn take_flash(&self) -> Vec<String> {
+    // Removing a missing key would still mark the session as changed and
+    // persist an empty session for every anonymous visitor.
+    if !self.0.entries().contains_key(Self::FLASH_KEY) {
+      return vec![];
+    }
+    self
+      .0
+      .remove_as::<Vec<String>>(Self::FLASH_KEY)
+      .and_then(Result::ok)
+      .unwrap_or_default()
+  }
+}
+
+impl FromRequest for TypedSession {
+  type Error = <Session as FromRequest>::Error;
+  type Future = Ready<Result<TypedSession, Self::Error>>;
+
+  fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+    ready(Ok(TypedSession(req.get_session())))
+  }
+}
@@ -0,0 +1,88 @@
+use std::{
+  collections::HashMap,
+  sync::{Arc, Mutex},
+};
+
+use actix_session::storage::{
+  LoadError, SaveError, SessionKey, SessionStore, UpdateError,
+};
+use actix_web::cookie::time::{Duration, OffsetDateTime};
+
+use crate::session_store::generate_session_key;
+
+type SessionState = HashMap<String, String>;
+
+/// Session store keeping session state in process memory.
+///
+/// State is lost on restart and is not shared between instances, which makes
+/// it a good fit for tests and a poor one for production.
+#[derive(Clone, Default)]
+pub struct InMemorySessionStore {
+  sessions: Arc<Mutex<HashMap<String, (SessionState, OffsetDateTime)>>>,
+}
+
+impl InMemorySessionStore {
+  fn insert(&self, key: &str, state: SessionState, ttl: &Duration) {
+    self
+      .sessions
+      .lock()
+      .unwrap()
+      .insert(key.to_owned(), (state, OffsetDateTime::now_utc() + *ttl));
+  }
+}
+
+impl SessionStore for InMemorySessionStore {
+  async fn load(
+    &self,
+    session_key: &SessionKey,
+  ) -> Result<Option<SessionState>, LoadError> {
+    let sessions = self.sessions.lock().unwrap();
+    Ok(
+      sessions
+        .get(session_key.as_ref())
+        .filter(|(_, expires_at)| *expires_at > OffsetDateTime::now_utc())
+        .map(|(state, _)| state.clone()),
+    )
+  }
+
+  async fn save(
+    &self,
+    session_state: SessionState,
+    ttl: &Duration,
+  ) -> Result<SessionKey, SaveError> {
+    let session_key = generate_session_key();
+    self.insert(session_key.as_ref(), session_state, ttl);
+    Ok(session_key)
+  }
+
+  async fn update(
+    &self,
+    session_key: SessionKey,
+    session_state: SessionState,
+    ttl: &Duration,
+  ) -> Result<SessionKey, UpdateError> {
+    self.insert(session_key.as_ref(), session_state, ttl);
+    Ok(session_key)
+  }
+
+  async fn update_ttl(
+    &self,
+    session_key: &SessionKey,
+    ttl: &Duration,
+  ) -> Result<(), anyhow::Error> {
+    if let Some((_, expires_at)) =
+      self.sessions.lock().unwrap().get_mut(session_key.as_ref())
+    {
+      *expires_at = OffsetDateTime::now_utc() + *ttl;
+    }
+    Ok(())
+  }
+
+  async fn delete(
+    &self,
+    session_key: &SessionKey,
+  ) -> Result<(), anyhow::Error> {
+    self.sessions.lock().unwrap().remove(session_key.as_ref());
+    Ok(())
+  }
+}
@@ -0,0 +1,23 @@
+//! Storage backends for `actix-session`.
+//!
+//! `startup::run` accepts any `SessionStore`, so production can keep sessions
+//! in Postgres while tests use process memory.
+mod memory;
+mod postgres;
+
+pub use memory::InMemorySessionStore;
+pub use postgres::PostgresSessionStore;
+
+use actix_session::storage::SessionKey;
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+
+/// Generate a random 64-characters-long session key.
+fn generate_session_key() -> SessionKey {
+  let mut rng = thread_rng();
+  let key: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+    .map(char::from)
+    .take(64)
+    .collect();
+  // 64 characters are well below the size limit enforced on session keys.
+  SessionKey::try_from(key).unwrap()
+}
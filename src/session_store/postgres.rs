@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+
+use actix_session::storage::{
+  LoadError, SaveError, SessionKey, SessionStore, UpdateError,
+};
+use actix_web::cookie::time::Duration;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+use crate::session_store::generate_session_key;
+
+type SessionState = HashMap<String, String>;
+
+/// Session store persisting session state in the `sessions` table.
+#[derive(Clone)]
+pub struct PostgresSessionStore {
+  pool: PgPool,
+}
+
+impl PostgresSessionStore {
+  pub fn new(pool: PgPool) -> Self {
+    Self { pool }
+  }
+}
+
+impl SessionStore for PostgresSessionStore {
+  async fn load(
+    &self,
+    session_key: &SessionKey,
+  ) -> Result<Option<SessionState>, LoadError> {
+    let row = sqlx::query!(
+      r#"
+      SELECT state FROM sessions
+      WHERE session_key = $1 AND expires_at > now()
+      "#,
+      session_key.as_ref(),
+    )
+    .fetch_optional(&self.pool)
+    .await
+    .context("Failed to load session state.")
+    .map_err(LoadError::Other)?;
+
+    row
+      .map(|row| serde_json::from_str(&row.state))
+      .transpose()
+      .context("Failed to deserialize session state.")
+      .map_err(LoadError::Deserialization)
+  }
+
+  async fn save(
+    &self,
+    session_state: SessionState,
+    ttl: &Duration,
+  ) -> Result<SessionKey, SaveError> {
+    let state = serde_json::to_string(&session_state)
+      .context("Failed to serialize session state.")
+      .map_err(SaveError::Serialization)?;
+    let session_key = generate_session_key();
+
+    // Expired sessions are never loaded again, sweep them while we are here.
+    sqlx::query!(r#"DELETE FROM sessions WHERE expires_at <= now()"#)
+      .execute(&self.pool)
+      .await
+      .context("Failed to delete expired sessions.")
+      .map_err(SaveError::Other)?;
+    sqlx::query!(
+      r#"
+      INSERT INTO sessions (session_key, state, expires_at)
+      VALUES ($1, $2, $3)
+      "#,
+      session_key.as_ref(),
+      state,
+      expires_at(ttl),
+    )
+    .execute(&self.pool)
+    .await
+    .context("Failed to save session state.")
+    .map_err(SaveError::Other)?;
+
+    Ok(session_key)
+  }
+
+  async fn update(
+    &self,
+    session_key: SessionKey,
+    session_state: SessionState,
+    ttl: &Duration,
+  ) -> Result<SessionKey, UpdateError> {
+    let state = serde_json::to_string(&session_state)
+      .context("Failed to serialize session state.")
+      .map_err(UpdateError::Serialization)?;
+
+    let result = sqlx::query!(
+      r#"
+      UPDATE sessions SET state = $2, expires_at = $3
+      WHERE session_key = $1 AND expires_at > now()
+      "#,
+      session_key.as_ref(),
+      state,
+      expires_at(ttl),
+    )
+    .execute(&self.pool)
+    .await
+    .context("Failed to update session state.")
+    .map_err(UpdateError::Other)?;
+
+    if result.rows_affected() == 0 {
+      // The session expired in the meantime: start a new one instead.
+      self.save(session_state, ttl).await.map_err(|e| match e {
+        SaveError::Serialization(e) => UpdateError::Serialization(e),
+        SaveError::Other(e) => UpdateError::Other(e),
+      })
+    } else {
+      Ok(session_key)
+    }
+  }
+
+  async fn update_ttl(
+    &self,
+    session_key: &SessionKey,
+    ttl: &Duration,
+  ) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+      r#"UPDATE sessions SET expires_at = $2 WHERE session_key = $1"#,
+      session_key.as_ref(),
+      expires_at(ttl),
+    )
+    .execute(&self.pool)
+    .await
+    .context("Failed to update session TTL.")?;
+    Ok(())
+  }
+
+  async fn delete(
+    &self,
+    session_key: &SessionKey,
+  ) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+      r#"DELETE FROM sessions WHERE session_key = $1"#,
+      session_key.as_ref(),
+    )
+    .execute(&self.pool)
+    .await
+    .context("Failed to delete session.")?;
+    Ok(())
+  }
+}
+
+fn expires_at(ttl: &Duration) -> DateTime<Utc> {
+  Utc::now() + chrono::Duration::seconds(ttl.whole_seconds())
+}
@@ -1,30 +1,117 @@
 use crate::{
+  configuration::WebhookSettings,
   email_client::EmailClient,
-  routes::{health_check, subscribe},
+  email_templates::EmailTemplates,
+  open_tracking::OpenTracking,
+  routes::{
+    add_suppression, admin_dashboard, api_subscribe, confirm, erase_subscriber,
+    get_suppressions, health_check, invalid_json_body, log_out, login,
+    login_form, publish_newsletter, receive_webhook, remove_suppression,
+    subscribe, track_open, unsubscribe, unsubscribe_form, webhook_check,
+    MAX_ISSUE_BODY_SIZE,
+  },
+  unsubscribe::UnsubscribeLinks,
+};
+use actix_session::{
+  config::CookieContentSecurity, storage::SessionStore, SessionMiddleware,
 };
 use actix_web::{
+  cookie::Key,
   dev::Server,
-  web::{get, post, Data},
+  web::{delete, get, head, post, resource, scope, Data, JsonConfig},
   App, HttpServer,
 };
+use secrecy::{ExposeSecret, Secret};
 use sqlx::PgPool;
 use std::{io::Error, net::TcpListener};
 use tracing_actix_web::TracingLogger;
 
-pub fn run(
+/// Public URL the app is reachable at, used to build links sent by email.
+///
+/// # Implementation Notes
+///
+/// `actix-web` retrieves app data by type, so wrapping the `String` avoids
+/// conflicts with any other `String` we might register in the future.
+pub struct ApplicationBaseUrl(pub String);
+
+/// Build the HTTP server.
+///
+/// Admin sessions are kept in `session_store`, so callers decide where session
+/// state lives (e.g. Postgres in production, memory in tests).
+#[allow(clippy::too_many_arguments)]
+pub fn run<S>(
   listener: TcpListener,
   db_pool: PgPool,
   email_client: EmailClient,
-) -> Result<Server, Error> {
+  templates: EmailTemplates,
+  base_url: String,
+  hmac_secret: Secret<String>,
+  open_tracking: OpenTracking,
+  webhooks: WebhookSettings,
+  session_store: S,
+) -> Result<Server, Error>
+where
+  S: SessionStore + Clone + Send + 'static,
+{
+  // Only ask browsers to restrict the cookie to HTTPS when we are served
+  // over HTTPS, otherwise local logins would silently fail.
+  let secure_cookies = base_url.starts_with("https://");
   let db_pool = Data::new(db_pool);
+  let email_client = Data::new(email_client);
+  let templates = Data::new(templates);
+  let unsubscribe_links =
+    Data::new(UnsubscribeLinks::new(base_url.clone(), hmac_secret.clone()));
+  let base_url = Data::new(ApplicationBaseUrl(base_url));
+  let open_tracking = Data::new(open_tracking);
+  let webhooks = Data::new(webhooks);
+  let secret_key = Key::from(hmac_secret.expose_secret().as_bytes());
 
   let server = HttpServer::new(move || {
     App::new()
+      .wrap(
+        SessionMiddleware::builder(session_store.clone(), secret_key.clone())
+          .cookie_content_security(CookieContentSecurity::Signed)
+          .cookie_http_only(true)
+          .cookie_secure(secure_cookies)
+          .build(),
+      )
       .wrap(TracingLogger::default())
       .route("/health_check", get().to(health_check))
+      .route("/login", get().to(login_form))
+      .route("/login", post().to(login))
       .route("/subscriptions", post().to(subscribe))
+      .route("/subscriptions/confirm", get().to(confirm))
+      .route("/subscriptions/unsubscribe", get().to(unsubscribe_form))
+      .route("/subscriptions/unsubscribe", post().to(unsubscribe))
+      .service(
+        resource("/newsletters")
+          .app_data(JsonConfig::default().limit(MAX_ISSUE_BODY_SIZE))
+          .route(post().to(publish_newsletter)),
+      )
+      .route("/t/o/{token}", get().to(track_open))
+      .route("/webhooks/{provider}", post().to(receive_webhook))
+      .route("/webhooks/{provider}", head().to(webhook_check))
+      .service(
+        scope("/admin")
+          .route("/dashboard", get().to(admin_dashboard))
+          .route("/logout", post().to(log_out)),
+      )
+      .service(
+        scope("/api/v1")
+          .app_data(JsonConfig::default().error_handler(invalid_json_body))
+          .route("/subscriptions", post().to(api_subscribe))
+          .route("/subscribers/{email}", delete().to(erase_subscriber))
+          .route("/suppressions", get().to(get_suppressions))
+          .route("/suppressions", post().to(add_suppression))
+          .route("/suppressions/{address}", delete().to(remove_suppression)),
+      )
       .app_data(db_pool.clone())
       .app_data(email_client.clone())
+      .app_data(templates.clone())
+      .app_data(unsubscribe_links.clone())
+      .app_data(base_url.clone())
+      .app_data(open_tracking.clone())
+      .app_data(webhooks.clone())
   })
   .listen(listener)?
   .run();
@@ -0,0 +1,203 @@
+//! Addresses and domains we must never email.
+//!
+//! Unlike a subscription's status, a suppression covers every email we send,
+//! confirmations included, and outlives the subscription: `EmailClient`
+//! checks the list before calling the provider.
+use std::collections::HashSet;
+
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Serializer};
+use sqlx::{postgres::PgExecutor, PgPool};
+
+use crate::domain::{SubscriberEmail, SuppressedAddress};
+
+/// What put an address on the list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SuppressionSource {
+  /// The address bounced for good
+  Bounce,
+  /// The recipient reported us as spam
+  Complaint,
+  /// An admin blocked it by hand
+  Admin,
+  /// Its owner asked us to erase their data
+  Erasure,
+}
+
+impl SuppressionSource {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      SuppressionSource::Bounce => "bounce",
+      SuppressionSource::Complaint => "complaint",
+      SuppressionSource::Admin => "admin",
+      SuppressionSource::Erasure => "erasure",
+    }
+  }
+}
+
+/// A row of the `suppressions` table, as the admin API shows it
+#[derive(Debug, Clone, Serialize)]
+pub struct Suppression {
+  /// An address, or a domain for all of its addresses
+  pub address: String,
+  pub reason: String,
+  /// One of `SuppressionSource`'s
+  pub source: String,
+  #[serde(serialize_with = "rfc3339")]
+  pub created_at: DateTime<Utc>,
+}
+
+fn rfc3339<S: Serializer>(
+  timestamp: &DateTime<Utc>,
+  serializer: S,
+) -> Result<S::Ok, S::Error> {
+  serializer.serialize_str(&timestamp.to_rfc3339())
+}
+
+/// Put `address` on the list, returning whether it wasn't there already.
+///
+/// An address already on the list keeps its original reason and source.
+#[tracing::instrument(skip(executor))]
+pub async fn suppress<'c>(
+  executor: impl PgExecutor<'c>,
+  address: &SuppressedAddress,
+  reason: &str,
+  source: SuppressionSource,
+) -> Result<bool, sqlx::Error> {
+  let inserted = sqlx::query!(
+    r#"
+    INSERT INTO suppressions (address, reason, source, created_at)
+    VALUES ($1, $2, $3, $4)
+    ON CONFLICT (address) DO NOTHING
+    "#,
+    address.as_ref(),
+    reason,
+    source.as_str(),
+    Utc::now()
+  )
+  .execute(executor)
+  .await?
+  .rows_affected();
+  Ok(inserted > 0)
+}
+
+/// Take `address` off the list, returning whether it was there.
+///
+/// A subscription suppressed along with the address becomes unsubscribed, so
+/// that its owner can subscribe again. Domains never suppress subscriptions.
+#[tracing::instrument(skip(pool))]
+pub async fn unsuppress(
+  pool: &PgPool,
+  address: &SuppressedAddress,
+) -> Result<bool, sqlx::Error> {
+  let mut transaction = pool.begin().await?;
+  let deleted = sqlx::query!(
+    r#"DELETE FROM suppressions WHERE address = $1"#,
+    address.as_ref()
+  )
+  .execute(&mut transaction)
+  .await?
+  .rows_affected();
+  if deleted > 0 && !address.is_domain() {
+    sqlx::query!(
+      r#"
+      UPDATE subscriptions SET status = 'unsubscribed'
+      WHERE lower(email) = $1 AND status = 'suppressed'
+      "#,
+      address.as_ref()
+    )
+    .execute(&mut transaction)
+    .await?;
+  }
+  transaction.commit().await?;
+  Ok(deleted > 0)
+}
+
+#[tracing::instrument(skip(pool))]
+pub async fn get_suppression(
+  pool: &PgPool,
+  address: &SuppressedAddress,
+) -> Result<Option<Suppression>, sqlx::Error> {
+  sqlx::query_as!(
+    Suppression,
+    r#"
+    SELECT address, reason, source, created_at
+    FROM suppressions
+    WHERE address = $1
+    "#,
+    address.as_ref()
+  )
+  .fetch_optional(pool)
+  .await
+}
+
+/// The whole list, most recent first.
+#[tracing::instrument(skip(pool))]
+pub async fn list_suppressions(
+  pool: &PgPool,
+) -> Result<Vec<Suppression>, sqlx::Error> {
+  sqlx::query_as!(
+    Suppression,
+    r#"
+    SELECT address, reason, source, created_at
+    FROM suppressions
+    ORDER BY created_at DESC, address
+    "#
+  )
+  .fetch_all(pool)
+  .await
+}
+
+/// Checks recipients against the `suppressions` table, see
+/// `EmailClient::with_suppressions`.
+#[derive(Clone)]
+pub struct SuppressionList {
+  pool: PgPool,
+}
+
+impl SuppressionList {
+  pub fn new(pool: PgPool) -> Self {
+    Self { pool }
+  }
+
+  /// Which of `emails` are suppressed, themselves or through their domain.
+  #[tracing::instrument(name = "Check the suppression list", skip_all)]
+  pub async fn suppressed<'a>(
+    &self,
+    emails: impl IntoIterator<Item = &'a SubscriberEmail>,
+  ) -> Result<HashSet<String>, sqlx::Error> {
+    let emails: Vec<&str> = emails.into_iter().map(AsRef::as_ref).collect();
+    if emails.is_empty() {
+      return Ok(HashSet::new());
+    }
+    let candidates: Vec<String> = emails
+      .iter()
+      .map(|email| email.to_lowercase())
+      .flat_map(|email| {
+        let domain =
+          email.rsplit_once('@').map(|(_, domain)| domain.to_owned());
+        std::iter::once(email).chain(domain)
+      })
+      .collect();
+    let listed: HashSet<String> = sqlx::query!(
+      r#"SELECT address FROM suppressions WHERE address = ANY($1)"#,
+      &candidates
+    )
+    .fetch_all(&self.pool)
+    .await?
+    .into_iter()
+    .map(|row| row.address)
+    .collect();
+    Ok(
+      emails
+        .into_iter()
+        .filter(|email| {
+          let email = email.to_lowercase();
+          let domain = email.rsplit_once('@').map_or("", |(_, domain)| domain);
+          listed.contains(&email) || listed.contains(domain)
+        })
+        .map(ToOwned::to_owned)
+        .collect(),
+    )
+  }
+}
@@ -1,3 +1,4 @@
+use tokio::task::{spawn_blocking, JoinHandle};
 use tracing::{subscriber::set_global_default, Subscriber};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
@@ -34,3 +35,14 @@ pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
   LogTracer::init().expect("Failed to set logger");
   set_global_default(subscriber).expect("Failed to set subscriber");
 }
+
+/// Run a CPU-intensive closure on tokio's blocking thread pool, keeping it
+/// attached to the span of the caller.
+pub fn spawn_blocking_with_tracing<F, R>(f: F) -> JoinHandle<R>
+where
+  F: FnOnce() -> R + Send + 'static,
+  R: Send + 'static,
+{
+  let current_span = tracing::Span::current();
+  spawn_blocking(move || current_span.in_scope(f))
+}
@@ -0,0 +1,142 @@
+//! Signed unsubscribe links.
+//!
+//! A token carries the subscriber's address along with an HMAC of it, so we
+//! can build one for any recipient without a database round trip, and
+//! nobody can forge one for somebody else's address.
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+
+use crate::domain::SubscriberEmail;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Clone)]
+pub struct UnsubscribeLinks {
+  base_url: String,
+  hmac_secret: Secret<String>,
+}
+
+impl UnsubscribeLinks {
+  pub fn new(base_url: String, hmac_secret: Secret<String>) -> Self {
+    Self {
+      base_url,
+      hmac_secret,
+    }
+  }
+
+  /// The page unsubscribing `email`, also the target of one-click `POST`s.
+  pub fn link(&self, email: &SubscriberEmail) -> String {
+    format!(
+      "{}/subscriptions/unsubscribe?token={}",
+      self.base_url,
+      self.token(email)
+    )
+  }
+
+  pub fn token(&self, email: &SubscriberEmail) -> String {
+    let signature = self.mac(email.as_ref()).finalize().into_bytes();
+    format!("{}.{}", encode(email.as_ref()), encode(signature))
+  }
+
+  /// The address `token` was issued for, if we signed it.
+  pub fn verify(
+    &self,
+    token: &str,
+  ) -> Result<SubscriberEmail, InvalidUnsubscribeToken> {
+    let (email, signature) =
+      token.split_once('.').ok_or(InvalidUnsubscribeToken)?;
+    let email = decode(email)
+      .and_then(|email| String::from_utf8(email).ok())
+      .ok_or(InvalidUnsubscribeToken)?;
+    let signature = decode(signature).ok_or(InvalidUnsubscribeToken)?;
+    self
+      .mac(&email)
+      .verify_slice(&signature)
+      .map_err(|_| InvalidUnsubscribeToken)?;
+    SubscriberEmail::parse(email).map_err(|_| InvalidUnsubscribeToken)
+  }
+
+  fn mac(&self, email: &str) -> HmacSha256 {
+    let mut mac =
+      HmacSha256::new_from_slice(self.hmac_secret.expose_secret().as_bytes())
+        .expect("HMAC accepts keys of any size.");
+    // The same secret signs session cookies, keep the two apart.
+    mac.update(b"unsubscribe:");
+    mac.update(email.as_bytes());
+    mac
+  }
+}
+
+fn encode(bytes: impl AsRef<[u8]>) -> String {
+  base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+fn decode(s: &str) -> Option<Vec<u8>> {
+  base64::decode_config(s, base64::URL_SAFE_NO_PAD).ok()
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("The unsubscribe token is invalid.")]
+pub struct InvalidUnsubscribeToken;
+
+#[cfg(test)]
+mod tests {
+  use claim::assert_err;
+  use secrecy::Secret;
+
+  use super::UnsubscribeLinks;
+  use crate::domain::SubscriberEmail;
+
+  fn links(secret: &str) -> UnsubscribeLinks {
+    UnsubscribeLinks::new(
+      "https://example.com".into(),
+      Secret::new(secret.into()),
+    )
+  }
+
+  fn email() -> SubscriberEmail {
+    SubscriberEmail::parse("ursula_le_guin@gmail.com".into()).unwrap()
+  }
+
+  #[test]
+  fn tokens_identify_the_subscriber() {
+    let links = links("secret");
+    let token = links.token(&email());
+
+    let verified = links.verify(&token).unwrap();
+
+    assert_eq!(verified.as_ref(), email().as_ref());
+    assert_eq!(
+      links.link(&email()),
+      format!(
+        "https://example.com/subscriptions/unsubscribe?token={}",
+        token
+      )
+    );
+  }
+
+  #[test]
+  fn tokens_signed_with_another_secret_are_rejected() {
+    let token = links("another secret").token(&email());
+    assert_err!(links("secret").verify(&token));
+  }
+
+  #[test]
+  fn tampered_tokens_are_rejected() {
+    let links = links("secret");
+    let token = links.token(&email());
+    let (_, signature) = token.split_once('.').unwrap();
+    let other =
+      base64::encode_config("someone_else@gmail.com", base64::URL_SAFE_NO_PAD);
+
+    for token in [
+      format!("{}.{}", other, signature),
+      token.replace('.', ""),
+      format!("{}x", token),
+      String::new(),
+    ] {
+      assert_err!(links.verify(&token));
+    }
+  }
+}
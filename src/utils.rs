@@ -0,0 +1,32 @@
+use actix_web::{http::header::LOCATION, HttpResponse};
+
+/// Redirect the client to `location` with a `303 See Other`.
+pub fn see_other(location: &str) -> HttpResponse {
+  HttpResponse::SeeOther()
+    .insert_header((LOCATION, location))
+    .finish()
+}
+
+/// Return an opaque 500 while preserving the error's root cause for logging.
+pub fn e500<T>(e: T) -> actix_web::Error
+where
+  T: std::fmt::Debug + std::fmt::Display + 'static,
+{
+  actix_web::error::ErrorInternalServerError(e)
+}
+
+/// Write `e` followed by every error in its source chain, one per line.
+///
+/// Meant for `Debug` impls, which is what our logs print.
+pub fn error_chain_fmt(
+  e: &impl std::error::Error,
+  f: &mut std::fmt::Formatter<'_>,
+) -> std::fmt::Result {
+  writeln!(f, "{}\n", e)?;
+  let mut current = e.source();
+  while let Some(cause) = current {
+    writeln!(f, "Caused by:\n\t{}", cause)?;
+    current = cause.source();
+  }
+  Ok(())
+}
@@ -0,0 +1,97 @@
+use actix_web::HttpRequest;
+use anyhow::Context;
+use chrono::{TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use serde::Deserialize;
+use sha1::Sha1;
+
+use super::{header, DeliveryEvent, DeliveryEventKind, WebhookError};
+
+/// Mandrill posts a form with a `mandrill_events` field, a JSON array, and
+/// signs it in `X-Mandrill-Signature`: the base64-encoded HMAC-SHA1 of the
+/// webhook's URL followed by every field's name and value, sorted by name.
+pub(super) fn events(
+  key: &Secret<String>,
+  url: &str,
+  request: &HttpRequest,
+  body: &[u8],
+) -> Result<Vec<DeliveryEvent>, WebhookError> {
+  let signature = header(request, "X-Mandrill-Signature")
+    .and_then(|signature| base64::decode(signature).ok())
+    .ok_or(WebhookError::InvalidSignature)?;
+  let mut fields: Vec<(String, String)> = serde_urlencoded::from_bytes(body)
+    .context("Failed to parse the form Mandrill posted.")
+    .map_err(WebhookError::InvalidPayload)?;
+  fields.sort();
+  let mut mac = Hmac::<Sha1>::new_from_slice(key.expose_secret().as_bytes())
+    .expect("HMAC accepts keys of any size.");
+  mac.update(url.as_bytes());
+  for (name, value) in &fields {
+    mac.update(name.as_bytes());
+    mac.update(value.as_bytes());
+  }
+  mac
+    .verify_slice(&signature)
+    .map_err(|_| WebhookError::InvalidSignature)?;
+
+  let events = fields
+    .iter()
+    .find(|(name, _)| name == "mandrill_events")
+    .map(|(_, events)| events.as_str())
+    .unwrap_or("[]");
+  let events: Vec<MandrillEvent> = serde_json::from_str(events)
+    .context("Failed to parse Mandrill's events.")
+    .map_err(WebhookError::InvalidPayload)?;
+  Ok(
+    events
+      .into_iter()
+      .filter_map(MandrillEvent::parse)
+      .collect(),
+  )
+}
+
+/// Message events, as far as we care about them; sync events, about
+/// Mandrill's own allow and deny lists, have no `msg` and are ignored.
+#[derive(Deserialize)]
+struct MandrillEvent {
+  #[serde(default)]
+  event: String,
+  #[serde(rename = "_id")]
+  id: Option<String>,
+  ts: i64,
+  msg: Option<MandrillMessage>,
+}
+
+#[derive(Deserialize)]
+struct MandrillMessage {
+  email: String,
+  bounce_description: Option<String>,
+}
+
+impl MandrillEvent {
+  fn parse(self) -> Option<DeliveryEvent> {
+    let kind = match self.event.as_str() {
+      "send" => DeliveryEventKind::Delivered,
+      "soft_bounce" => DeliveryEventKind::SoftBounce,
+      "hard_bounce" => DeliveryEventKind::HardBounce,
+      "spam" => DeliveryEventKind::Complaint,
+      "reject" => DeliveryEventKind::Reject,
+      _ => return None,
+    };
+    let msg = self.msg?;
+    Some(DeliveryEvent {
+      kind,
+      recipient: msg.email,
+      // Events have no id of their own, but a message only goes through
+      // each of them once at a given time.
+      provider_event_id: self
+        .id
+        .as_ref()
+        .map(|id| format!("{}:{}:{}", id, self.event, self.ts)),
+      provider_message_id: self.id,
+      description: msg.bounce_description,
+      occurred_at: Utc.timestamp_opt(self.ts, 0).single()?,
+    })
+  }
+}
@@ -0,0 +1,237 @@
+//! Delivery events reported by providers' webhooks.
+//!
+//! Each provider proves its requests come from it its own way, and describes
+//! events in its own words: its module checks the former and translates the
+//! latter into `DeliveryEvent`s.
+mod mandrill;
+mod postmark;
+mod sendgrid;
+
+use actix_web::{http::StatusCode, HttpRequest, HttpResponse, ResponseError};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+  configuration::WebhookSettings,
+  domain::SuppressedAddress,
+  suppressions::{suppress, SuppressionSource},
+  utils::error_chain_fmt,
+};
+
+/// Something that happened to an email, for one recipient.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeliveryEvent {
+  pub kind: DeliveryEventKind,
+  pub recipient: String,
+  pub provider_message_id: Option<String>,
+  /// Unique for the provider, when it gives events an id of their own
+  pub provider_event_id: Option<String>,
+  /// Why the email bounced or was rejected, in the provider's words
+  pub description: Option<String>,
+  pub occurred_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryEventKind {
+  /// Accepted by the recipient's mail server
+  Delivered,
+  /// Refused for now, e.g. because the mailbox is full
+  SoftBounce,
+  /// Refused for good, e.g. because the mailbox does not exist
+  HardBounce,
+  /// Reported as spam by the recipient
+  Complaint,
+  /// Not even attempted by the provider, e.g. after previous bounces
+  Reject,
+}
+
+impl DeliveryEventKind {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      DeliveryEventKind::Delivered => "delivered",
+      DeliveryEventKind::SoftBounce => "soft_bounce",
+      DeliveryEventKind::HardBounce => "hard_bounce",
+      DeliveryEventKind::Complaint => "complaint",
+      DeliveryEventKind::Reject => "reject",
+    }
+  }
+
+  /// Why we must stop emailing the recipient, if we must: sending again
+  /// would hurt our reputation with their mail provider.
+  pub fn suppression_source(&self) -> Option<SuppressionSource> {
+    match self {
+      DeliveryEventKind::HardBounce => Some(SuppressionSource::Bounce),
+      DeliveryEventKind::Complaint => Some(SuppressionSource::Complaint),
+      _ => None,
+    }
+  }
+}
+
+/// Check that `provider` sent the webhook, and read the events it reports.
+///
+/// `base_url` is the app's public URL, which some providers sign along with
+/// the payload.
+pub fn parse_webhook(
+  provider: &str,
+  settings: &WebhookSettings,
+  base_url: &str,
+  request: &HttpRequest,
+  body: &[u8],
+) -> Result<Vec<DeliveryEvent>, WebhookError> {
+  let unknown = || WebhookError::UnknownProvider(provider.to_owned());
+  match provider {
+    "mandrill" => {
+      let key = settings.mandrill_key.as_ref().ok_or_else(unknown)?;
+      let url = format!("{}/webhooks/mandrill", base_url);
+      mandrill::events(key, &url, request, body)
+    }
+    "postmark" => {
+      match (&settings.postmark_username, &settings.postmark_password) {
+        (Some(username), Some(password)) => {
+          postmark::events(username, password, request, body)
+        }
+        _ => Err(unknown()),
+      }
+    }
+    "sendgrid" => {
+      let public_key =
+        settings.sendgrid_public_key.as_ref().ok_or_else(unknown)?;
+      sendgrid::events(public_key, request, body)
+    }
+    _ => Err(unknown()),
+  }
+}
+
+/// Record `events`, suppressing the subscribers they call for and adding
+/// their addresses to the suppression list.
+///
+/// Events already recorded are skipped, as providers retry webhooks until
+/// they get an answer in time.
+#[tracing::instrument(
+  skip(pool, events),
+  fields(n_events = events.len())
+)]
+pub async fn store_delivery_events(
+  pool: &PgPool,
+  provider: &str,
+  events: &[DeliveryEvent],
+) -> Result<(), sqlx::Error> {
+  let mut transaction = pool.begin().await?;
+  let received_at = Utc::now();
+  for event in events {
+    let inserted = sqlx::query!(
+      r#"
+      INSERT INTO delivery_events (
+        delivery_event_id,
+        provider,
+        kind,
+        recipient,
+        provider_message_id,
+        provider_event_id,
+        description,
+        occurred_at,
+        received_at
+      )
+      VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+      ON CONFLICT (provider, provider_event_id) DO NOTHING
+      "#,
+      Uuid::new_v4(),
+      provider,
+      event.kind.as_str(),
+      event.recipient,
+      event.provider_message_id,
+      event.provider_event_id,
+      event.description,
+      event.occurred_at,
+      received_at
+    )
+    .execute(&mut transaction)
+    .await?
+    .rows_affected()
+      > 0;
+    let source = match event.kind.suppression_source() {
+      Some(source) if inserted => source,
+      _ => continue,
+    };
+    let suppressed = sqlx::query!(
+      r#"
+      UPDATE subscriptions SET status = 'suppressed'
+      WHERE lower(email) = lower($1) AND status <> 'suppressed'
+      "#,
+      event.recipient
+    )
+    .execute(&mut transaction)
+    .await?
+    .rows_affected();
+    if suppressed > 0 {
+      tracing::info!(
+        recipient = %event.recipient,
+        kind = event.kind.as_str(),
+        "Suppressed a subscriber",
+      );
+    }
+    let address = match SuppressedAddress::parse(event.recipient.clone()) {
+      Ok(address) => address,
+      Err(e) => {
+        tracing::warn!(
+          recipient = %event.recipient,
+          error.message = %e,
+          "Cannot add the recipient to the suppression list",
+        );
+        continue;
+      }
+    };
+    let reason = match &event.description {
+      Some(description) => {
+        format!(
+          "{} reported by {}: {}",
+          event.kind.as_str(),
+          provider,
+          description
+        )
+      }
+      None => format!("{} reported by {}", event.kind.as_str(), provider),
+    };
+    suppress(&mut transaction, &address, &reason, source).await?;
+  }
+  transaction.commit().await
+}
+
+#[derive(thiserror::Error)]
+pub enum WebhookError {
+  #[error("Webhooks from `{0}` are not accepted.")]
+  UnknownProvider(String),
+  #[error("The webhook's signature is missing or invalid.")]
+  InvalidSignature,
+  #[error("The webhook's payload is invalid.")]
+  InvalidPayload(#[source] anyhow::Error),
+  #[error(transparent)]
+  UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for WebhookError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    error_chain_fmt(self, f)
+  }
+}
+
+impl ResponseError for WebhookError {
+  fn status_code(&self) -> StatusCode {
+    match self {
+      WebhookError::UnknownProvider(_) => StatusCode::NOT_FOUND,
+      WebhookError::InvalidSignature => StatusCode::UNAUTHORIZED,
+      WebhookError::InvalidPayload(_) => StatusCode::BAD_REQUEST,
+      WebhookError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+  }
+
+  fn error_response(&self) -> HttpResponse {
+    HttpResponse::build(self.status_code()).finish()
+  }
+}
+
+/// The value of the `name` header, if it is there and readable.
+fn header<'a>(request: &'a HttpRequest, name: &str) -> Option<&'a str> {
+  request.headers().get(name)?.to_str().ok()
+}
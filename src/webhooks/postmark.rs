@@ -0,0 +1,87 @@
+use actix_web::HttpRequest;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use secrecy::{ExposeSecret, Secret};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use super::{DeliveryEvent, DeliveryEventKind, WebhookError};
+use crate::authentication::basic_authentication;
+
+/// Postmark posts one JSON event per request, without signing it: it sends
+/// the basic auth credentials of the webhook's URL instead.
+pub(super) fn events(
+  username: &str,
+  password: &Secret<String>,
+  request: &HttpRequest,
+  body: &[u8],
+) -> Result<Vec<DeliveryEvent>, WebhookError> {
+  let credentials = basic_authentication(request.headers())
+    .map_err(|_| WebhookError::InvalidSignature)?;
+  // Comparing digests keeps the time taken from telling how much of the
+  // password was right.
+  let digest = |password: &Secret<String>| {
+    Sha256::digest(password.expose_secret().as_bytes())
+  };
+  if credentials.username != username
+    || digest(&credentials.password) != digest(password)
+  {
+    return Err(WebhookError::InvalidSignature);
+  }
+  let event: PostmarkEvent = serde_json::from_slice(body)
+    .context("Failed to parse Postmark's event.")
+    .map_err(WebhookError::InvalidPayload)?;
+  Ok(event.parse().into_iter().collect())
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PostmarkEvent {
+  record_type: String,
+  /// Bounces' and complaints' own id
+  #[serde(rename = "ID")]
+  id: Option<i64>,
+  /// Kind of bounce, e.g. `HardBounce` or `Transient`
+  r#type: Option<String>,
+  #[serde(rename = "MessageID")]
+  message_id: Option<String>,
+  /// Bounces and complaints
+  email: Option<String>,
+  /// Deliveries
+  recipient: Option<String>,
+  description: Option<String>,
+  /// RFC 3339, like the other timestamps
+  bounced_at: Option<String>,
+  delivered_at: Option<String>,
+}
+
+impl PostmarkEvent {
+  fn parse(self) -> Option<DeliveryEvent> {
+    let kind = match (self.record_type.as_str(), self.r#type.as_deref()) {
+      ("Delivery", _) => DeliveryEventKind::Delivered,
+      ("SpamComplaint", _) => DeliveryEventKind::Complaint,
+      ("Bounce", Some("HardBounce" | "BadEmailAddress")) => {
+        DeliveryEventKind::HardBounce
+      }
+      ("Bounce", Some("SpamNotification")) => DeliveryEventKind::Complaint,
+      ("Bounce", _) => DeliveryEventKind::SoftBounce,
+      _ => return None,
+    };
+    Some(DeliveryEvent {
+      kind,
+      recipient: self.email.or(self.recipient)?,
+      // Deliveries have no id, but happen once per message.
+      provider_event_id: Some(match self.id {
+        Some(id) => format!("{}:{}", self.record_type, id),
+        None => format!("{}:{}", self.record_type, self.message_id.as_ref()?),
+      }),
+      provider_message_id: self.message_id,
+      description: self.description,
+      occurred_at: DateTime::parse_from_rfc3339(
+        self.bounced_at.or(self.delivered_at)?.as_str(),
+      )
+      .ok()?
+      .with_timezone(&Utc),
+    })
+  }
+}
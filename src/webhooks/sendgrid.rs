@@ -0,0 +1,83 @@
+use actix_web::HttpRequest;
+use anyhow::Context;
+use chrono::{TimeZone, Utc};
+use p256::{
+  ecdsa::{signature::Verifier, Signature, VerifyingKey},
+  pkcs8::DecodePublicKey,
+};
+use serde::Deserialize;
+
+use super::{header, DeliveryEvent, DeliveryEventKind, WebhookError};
+
+/// SendGrid posts a JSON array of events and signs it with ECDSA: the
+/// base64-encoded signature of the timestamp followed by the body, in
+/// `X-Twilio-Email-Event-Webhook-Signature`.
+pub(super) fn events(
+  public_key: &str,
+  request: &HttpRequest,
+  body: &[u8],
+) -> Result<Vec<DeliveryEvent>, WebhookError> {
+  let public_key = base64::decode(public_key.trim())
+    .context("SendGrid's public key is not base64.")
+    .and_then(|der| {
+      VerifyingKey::from_public_key_der(&der)
+        .map_err(|e| anyhow::anyhow!("{}", e))
+        .context("SendGrid's public key is invalid.")
+    })?;
+  let signature = header(request, "X-Twilio-Email-Event-Webhook-Signature")
+    .and_then(|signature| base64::decode(signature).ok())
+    .and_then(|signature| Signature::from_der(&signature).ok())
+    .ok_or(WebhookError::InvalidSignature)?;
+  let timestamp = header(request, "X-Twilio-Email-Event-Webhook-Timestamp")
+    .ok_or(WebhookError::InvalidSignature)?;
+  let signed = [timestamp.as_bytes(), body].concat();
+  public_key
+    .verify(&signed, &signature)
+    .map_err(|_| WebhookError::InvalidSignature)?;
+
+  let events: Vec<SendGridEvent> = serde_json::from_slice(body)
+    .context("Failed to parse SendGrid's events.")
+    .map_err(WebhookError::InvalidPayload)?;
+  Ok(
+    events
+      .into_iter()
+      .filter_map(SendGridEvent::parse)
+      .collect(),
+  )
+}
+
+#[derive(Deserialize)]
+struct SendGridEvent {
+  email: String,
+  timestamp: i64,
+  event: String,
+  sg_event_id: Option<String>,
+  sg_message_id: Option<String>,
+  /// For bounces, `bounce` or `blocked`
+  r#type: Option<String>,
+  reason: Option<String>,
+}
+
+impl SendGridEvent {
+  fn parse(self) -> Option<DeliveryEvent> {
+    let kind = match (self.event.as_str(), self.r#type.as_deref()) {
+      ("delivered", _) => DeliveryEventKind::Delivered,
+      ("deferred", _) => DeliveryEventKind::SoftBounce,
+      // Blocked emails were refused for a reason other than the address,
+      // e.g. the content: later ones may get through.
+      ("bounce", Some("blocked")) => DeliveryEventKind::SoftBounce,
+      ("bounce", _) => DeliveryEventKind::HardBounce,
+      ("dropped", _) => DeliveryEventKind::Reject,
+      ("spamreport", _) => DeliveryEventKind::Complaint,
+      _ => return None,
+    };
+    Some(DeliveryEvent {
+      kind,
+      recipient: self.email,
+      provider_message_id: self.sg_message_id,
+      provider_event_id: self.sg_event_id,
+      description: self.reason,
+      occurred_at: Utc.timestamp_opt(self.timestamp, 0).single()?,
+    })
+  }
+}
@@ -0,0 +1,45 @@
+use crate::api::helpers::{assert_is_redirect_to, spawn_app};
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_access_the_admin_dashboard() {
+  let app = spawn_app().await;
+
+  let response = app.get_admin_dashboard().await;
+
+  assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_log_out() {
+  let app = spawn_app().await;
+
+  let response = app.post_logout().await;
+
+  assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn logout_clears_session_state() {
+  let app = spawn_app().await;
+
+  // Login
+  app.login_as_test_user().await;
+
+  // Follow the redirect
+  let html_page = app.get_admin_dashboard_html().await;
+  assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+
+  // Logout
+  let response = app.post_logout().await;
+  assert_is_redirect_to(&response, "/login");
+
+  // Follow the redirect
+  let html_page = app.get_login_html().await;
+  assert!(
+    html_page.contains(r#"<p><i>You have successfully logged out.</i></p>"#)
+  );
+
+  // Attempt to load admin panel
+  let response = app.get_admin_dashboard().await;
+  assert_is_redirect_to(&response, "/login");
+}
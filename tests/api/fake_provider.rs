@@ -0,0 +1,182 @@
+use std::net::TcpListener;
+
+use emailer::{
+  configuration::{get_configuration, EmailProvider},
+  domain::SubscriberEmail,
+  email_client::{BatchRecipient, EmailClient, RecipientStatus},
+  fake_provider::{run, Failures},
+};
+use serde_json::Value;
+
+struct FakeProvider {
+  address: String,
+  email_client: EmailClient,
+  api_client: reqwest::Client,
+}
+
+impl FakeProvider {
+  async fn start() -> Self {
+    let listener =
+      TcpListener::bind("127.0.0.1:0").expect("Failed to bind random port");
+    let address =
+      format!("http://127.0.0.1:{}", listener.local_addr().unwrap().port());
+    tokio::spawn(run(listener, Failures::default()).unwrap());
+
+    let mut configuration =
+      get_configuration().expect("Failed to read configuration");
+    configuration.email_client.provider = EmailProvider::Mandrill;
+    configuration.email_client.base_url = address.clone();
+    configuration.email_client.sandbox.enabled = false;
+    configuration.email_client.retry.base_delay_milliseconds = 10;
+    configuration.email_client.retry.jitter = 0.0;
+    let unsubscribe_links = configuration.application.unsubscribe_links();
+    Self {
+      address,
+      email_client: configuration.email_client.client(unsubscribe_links),
+      api_client: reqwest::Client::new(),
+    }
+  }
+
+  async fn messages(&self) -> Vec<Value> {
+    self
+      .api_client
+      .get(format!("{}/messages", self.address))
+      .send()
+      .await
+      .unwrap()
+      .json()
+      .await
+      .unwrap()
+  }
+
+  async fn inject(&self, failures: Value) {
+    let response = self
+      .api_client
+      .put(format!("{}/failures", self.address))
+      .json(&failures)
+      .send()
+      .await
+      .unwrap();
+    assert!(response.status().is_success());
+  }
+}
+
+fn recipient(email: &str) -> SubscriberEmail {
+  SubscriberEmail::parse(email.into()).unwrap()
+}
+
+#[tokio::test]
+async fn sent_emails_can_be_browsed() {
+  let provider = FakeProvider::start().await;
+
+  let outcome = provider
+    .email_client
+    .send_email(
+      &recipient("ursula_le_guin@gmail.com"),
+      "Welcome!",
+      "<p>Hi!</p>",
+      "Hi!",
+      &[],
+    )
+    .await
+    .unwrap();
+
+  assert_eq!(outcome.status, RecipientStatus::Sent);
+  let messages = provider.messages().await;
+  assert_eq!(messages.len(), 1);
+  assert_eq!(messages[0]["to"], "ursula_le_guin@gmail.com");
+  assert_eq!(messages[0]["subject"], "Welcome!");
+  assert_eq!(messages[0]["id"], outcome.message_id.unwrap().as_str());
+  let inbox = reqwest::get(&provider.address)
+    .await
+    .unwrap()
+    .text()
+    .await
+    .unwrap();
+  assert!(inbox.contains("Welcome!"));
+  let page = reqwest::get(format!(
+    "{}/messages/{}",
+    provider.address,
+    messages[0]["id"].as_str().unwrap()
+  ))
+  .await
+  .unwrap();
+  assert_eq!(page.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn batches_are_stored_per_recipient_with_their_merge_variables() {
+  let provider = FakeProvider::start().await;
+  let recipients: Vec<_> = ["first@example.com", "second@example.com"]
+    .into_iter()
+    .map(|email| {
+      let mut recipient = BatchRecipient::new(self::recipient(email));
+      recipient.merge_vars.insert("email".into(), email.into());
+      recipient
+    })
+    .collect();
+
+  provider
+    .email_client
+    .send_batch(&recipients, "Issue #1", "<p>Hi!</p>", "Hi {{email}}!", &[])
+    .await;
+
+  let messages = provider.messages().await;
+  let texts: Vec<_> = messages
+    .iter()
+    .map(|message| message["text"].as_str().unwrap())
+    .collect();
+  assert_eq!(
+    texts,
+    vec!["Hi first@example.com!", "Hi second@example.com!"]
+  );
+  assert!(messages[1]["headers"]["List-Unsubscribe"]
+    .as_str()
+    .unwrap()
+    .contains("/subscriptions/unsubscribe?token="));
+}
+
+#[tokio::test]
+async fn injected_errors_are_retried_then_reported() {
+  let provider = FakeProvider::start().await;
+  provider
+    .inject(serde_json::json!({ "error_rate": 1.0 }))
+    .await;
+
+  let outcome = provider
+    .email_client
+    .send_email(
+      &recipient("ursula_le_guin@gmail.com"),
+      "Hi",
+      "Hi",
+      "Hi",
+      &[],
+    )
+    .await;
+
+  assert!(outcome.is_err());
+  assert!(provider.messages().await.is_empty());
+}
+
+#[tokio::test]
+async fn injected_rejections_are_reported_per_recipient() {
+  let provider = FakeProvider::start().await;
+  provider
+    .inject(serde_json::json!({ "reject_rate": 1.0 }))
+    .await;
+
+  let outcome = provider
+    .email_client
+    .send_email(
+      &recipient("ursula_le_guin@gmail.com"),
+      "Hi",
+      "Hi",
+      "Hi",
+      &[],
+    )
+    .await
+    .unwrap();
+
+  assert_eq!(outcome.status, RecipientStatus::Rejected);
+  assert_eq!(outcome.reject_reason.as_deref(), Some("hard-bounce"));
+}
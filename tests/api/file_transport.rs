@@ -0,0 +1,89 @@
+use std::path::PathBuf;
+
+use claim::assert_ok;
+use emailer::{
+  configuration::{get_configuration, EmailProvider, FileFormat, FileSettings},
+  domain::SubscriberEmail,
+  email_client::{written_emails, BatchRecipient, EmailClient},
+};
+use uuid::Uuid;
+
+/// A client writing to a new directory, returned along with it.
+fn file_client(format: FileFormat) -> (EmailClient, PathBuf) {
+  let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+  let mut configuration =
+    get_configuration().expect("Failed to read configuration");
+  configuration.email_client.provider = EmailProvider::File;
+  configuration.email_client.file = Some(FileSettings {
+    directory: directory.to_string_lossy().into_owned(),
+    format,
+  });
+  configuration.email_client.sandbox.enabled = false;
+  let unsubscribe_links = configuration.application.unsubscribe_links();
+  (
+    configuration.email_client.client(unsubscribe_links),
+    directory,
+  )
+}
+
+fn recipient(email: &str) -> SubscriberEmail {
+  SubscriberEmail::parse(email.into()).unwrap()
+}
+
+#[tokio::test]
+async fn emails_are_written_as_eml_files() {
+  let (email_client, directory) = file_client(FileFormat::Eml);
+
+  let outcome = email_client
+    .send_email(
+      &recipient("ursula_le_guin@gmail.com"),
+      "Welcome!",
+      "<p>Hi!</p>",
+      "Hi!",
+      &[],
+    )
+    .await;
+
+  assert_ok!(&outcome);
+  let emails = written_emails(&directory).unwrap();
+  assert_eq!(emails.len(), 1);
+  let email = &emails[0];
+  assert_eq!(email.path.extension().unwrap(), "eml");
+  assert_eq!(email.header("To").unwrap(), "ursula_le_guin@gmail.com");
+  assert_eq!(email.header("Subject").unwrap(), "Welcome!");
+  assert_eq!(email.header("Message-ID"), outcome.unwrap().message_id);
+  assert!(email
+    .header("List-Unsubscribe")
+    .unwrap()
+    .contains("/subscriptions/unsubscribe?token="));
+  assert!(email.content.contains("<p>Hi!</p>"));
+}
+
+#[tokio::test]
+async fn batches_are_delivered_to_a_maildir_in_order() {
+  let (email_client, directory) = file_client(FileFormat::Maildir);
+  let recipients: Vec<_> = ["first@example.com", "second@example.com"]
+    .into_iter()
+    .map(|email| {
+      let mut recipient = BatchRecipient::new(self::recipient(email));
+      recipient.merge_vars.insert("email".into(), email.into());
+      recipient
+    })
+    .collect();
+
+  let outcomes = email_client
+    .send_batch(&recipients, "Issue #1", "<p>Hi!</p>", "Hi {{email}}!", &[])
+    .await;
+
+  assert!(outcomes.iter().all(|outcome| outcome.is_accepted()));
+  assert!(directory.join("tmp").is_dir());
+  assert_eq!(std::fs::read_dir(directory.join("tmp")).unwrap().count(), 0);
+  let emails = written_emails(&directory).unwrap();
+  let to: Vec<_> = emails
+    .iter()
+    .map(|email| email.header("To").unwrap())
+    .collect();
+  assert_eq!(to, vec!["first@example.com", "second@example.com"]);
+  assert!(emails[0].path.starts_with(directory.join("new")));
+  assert!(emails[1].content.contains("Hi second@example.com!"));
+}
@@ -1,18 +1,31 @@
 use emailer::{
-  configuration::{get_configuration, DatabaseSettings},
+  authentication::compute_password_hash,
+  configuration::{
+    get_configuration, DatabaseSettings, Settings, WebhookSettings,
+  },
   email_client::EmailClient,
+  email_templates::EmailTemplates,
+  issue_delivery_worker::{try_execute_task, ExecutionOutcome},
+  open_tracking::OpenTracking,
+  session_store::InMemorySessionStore,
   startup::run,
+  suppressions::SuppressionList,
   telementry::{get_subscriber, init_subscriber},
 };
 use once_cell::sync::Lazy;
+use p256::{ecdsa::SigningKey, pkcs8::EncodePublicKey};
+use reqwest::{Client, Response, Url};
+use secrecy::{ExposeSecret, Secret};
 use sqlx::{Connection, Executor, PgConnection, PgPool};
 use std::{
   env::var,
   io::{sink, stdout},
   net::TcpListener,
+  time::Duration,
 };
 use tokio::spawn;
 use uuid::Uuid;
+use wiremock::{MockServer, Request, Respond, ResponseTemplate};
 // Ensures that the `tracing` stack is only initialized once using cargo `once_cell`
 static TRACING: Lazy<()> = Lazy::new(|| {
   let default_filter_level = "info".to_string();
@@ -29,18 +42,264 @@ static TRACING: Lazy<()> = Lazy::new(|| {
   }
 });
 
+/// Credentials providers' webhooks are checked against in tests
+pub const MANDRILL_WEBHOOK_KEY: &str = "mandrill-webhook-key";
+pub const POSTMARK_WEBHOOK_USERNAME: &str = "postmark";
+pub const POSTMARK_WEBHOOK_PASSWORD: &str = "postmark-webhook-password";
+
+/// The key SendGrid would sign webhooks with
+pub fn sendgrid_signing_key() -> SigningKey {
+  SigningKey::from_slice(&[7; 32]).unwrap()
+}
+
 pub struct TestApp {
   pub address: String,
+  /// The public URL the app believes it has, e.g. for links in emails
+  pub base_url: String,
+  pub port: u16,
   pub db_pool: PgPool,
+  pub email_server: MockServer,
+  pub email_client: EmailClient,
+  pub templates: EmailTemplates,
+  pub open_tracking: OpenTracking,
+  pub test_user: TestUser,
+  /// HTTP client keeping cookies around, like a browser would
+  pub api_client: Client,
+}
+
+/// An admin account stored in the `users` table of the test database
+pub struct TestUser {
+  pub user_id: Uuid,
+  pub username: String,
+  pub password: String,
+}
+
+impl TestUser {
+  pub fn generate() -> Self {
+    Self {
+      user_id: Uuid::new_v4(),
+      username: Uuid::new_v4().to_string(),
+      password: Uuid::new_v4().to_string(),
+    }
+  }
+
+  async fn store(&self, pool: &PgPool) {
+    let password_hash =
+      compute_password_hash(Secret::new(self.password.clone()))
+        .expect("Failed to hash test user password.");
+    sqlx::query!(
+      "INSERT INTO users (user_id, username, password_hash)
+      VALUES ($1, $2, $3)",
+      self.user_id,
+      self.username,
+      password_hash.expose_secret(),
+    )
+    .execute(pool)
+    .await
+    .expect("Failed to store test user.");
+  }
+}
+
+/// Confirmation links embedded in the body of an email sent to a subscriber
+pub struct ConfirmationLinks {
+  pub html: Url,
+  pub plain_text: Url,
+}
+
+impl TestApp {
+  /// Run the delivery worker until the queue is drained.
+  ///
+  /// The worker isn't spawned by `spawn_app`, so tests decide when queued
+  /// issues are actually sent.
+  pub async fn dispatch_all_pending_emails(&self) {
+    loop {
+      if let ExecutionOutcome::EmptyQueue = try_execute_task(
+        &self.db_pool,
+        &self.email_client,
+        &self.templates,
+        &self.open_tracking,
+      )
+      .await
+      .unwrap()
+      {
+        break;
+      }
+    }
+  }
+
+  pub async fn post_subscriptions(&self, body: String) -> Response {
+    Client::new()
+      .post(format!("{}/subscriptions", &self.address))
+      .header("Content-Type", "application/x-www-form-urlencoded")
+      .body(body)
+      .send()
+      .await
+      .expect("Failed to execute request")
+  }
+
+  pub async fn post_api_subscriptions(
+    &self,
+    body: serde_json::Value,
+  ) -> Response {
+    Client::new()
+      .post(format!("{}/api/v1/subscriptions", &self.address))
+      .json(&body)
+      .send()
+      .await
+      .expect("Failed to execute request")
+  }
+
+  pub async fn post_newsletters(&self, body: serde_json::Value) -> Response {
+    Client::new()
+      .post(format!("{}/newsletters", &self.address))
+      .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+      .json(&body)
+      .send()
+      .await
+      .expect("Failed to execute request")
+  }
+
+  pub async fn post_newsletters_with_idempotency_key(
+    &self,
+    body: serde_json::Value,
+    idempotency_key: &str,
+  ) -> Response {
+    Client::new()
+      .post(format!("{}/newsletters", &self.address))
+      .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+      .header("Idempotency-Key", idempotency_key)
+      .json(&body)
+      .send()
+      .await
+      .expect("Failed to execute request")
+  }
+
+  pub async fn post_suppressions(&self, body: serde_json::Value) -> Response {
+    Client::new()
+      .post(format!("{}/api/v1/suppressions", &self.address))
+      .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+      .json(&body)
+      .send()
+      .await
+      .expect("Failed to execute request")
+  }
+
+  pub async fn post_login<Body>(&self, body: &Body) -> Response
+  where
+    Body: serde::Serialize,
+  {
+    self
+      .api_client
+      .post(format!("{}/login", &self.address))
+      .form(body)
+      .send()
+      .await
+      .expect("Failed to execute request")
+  }
+
+  pub async fn get_login_html(&self) -> String {
+    self
+      .api_client
+      .get(format!("{}/login", &self.address))
+      .send()
+      .await
+      .expect("Failed to execute request")
+      .text()
+      .await
+      .unwrap()
+  }
+
+  pub async fn get_admin_dashboard(&self) -> Response {
+    self
+      .api_client
+      .get(format!("{}/admin/dashboard", &self.address))
+      .send()
+      .await
+      .expect("Failed to execute request")
+  }
+
+  pub async fn get_admin_dashboard_html(&self) -> String {
+    self.get_admin_dashboard().await.text().await.unwrap()
+  }
+
+  pub async fn post_logout(&self) -> Response {
+    self
+      .api_client
+      .post(format!("{}/admin/logout", &self.address))
+      .send()
+      .await
+      .expect("Failed to execute request")
+  }
+
+  /// Log in as `test_user`, keeping the session cookie in `api_client`
+  pub async fn login_as_test_user(&self) {
+    self
+      .post_login(&serde_json::json!({
+        "username": &self.test_user.username,
+        "password": &self.test_user.password,
+      }))
+      .await;
+  }
+
+  /// Extract the confirmation links embedded in a request to the email API
+  pub fn get_confirmation_links(
+    &self,
+    email_request: &wiremock::Request,
+  ) -> ConfirmationLinks {
+    let body: serde_json::Value =
+      serde_json::from_slice(&email_request.body).unwrap();
+
+    let get_link = |s: &str| {
+      // Every email also links to the unsubscribe page.
+      let links: Vec<_> = linkify::LinkFinder::new()
+        .links(s)
+        .filter(|l| *l.kind() == linkify::LinkKind::Url)
+        .filter(|l| l.as_str().contains("/subscriptions/confirm"))
+        .collect();
+      assert_eq!(links.len(), 1);
+      let raw_link = links[0].as_str().to_owned();
+      let mut confirmation_link = Url::parse(&raw_link).unwrap();
+      // Make sure we don't call random APIs on the web
+      assert_eq!(confirmation_link.host_str().unwrap(), "127.0.0.1");
+      confirmation_link.set_port(Some(self.port)).unwrap();
+      confirmation_link
+    };
+
+    let html = get_link(body["message"]["html"].as_str().unwrap());
+    let plain_text = get_link(body["message"]["text"].as_str().unwrap());
+    ConfirmationLinks { html, plain_text }
+  }
+
+  /// Extract the `List-Unsubscribe` link from a request to the email API
+  pub fn get_unsubscribe_link(&self, email_request: &wiremock::Request) -> Url {
+    let body: serde_json::Value =
+      serde_json::from_slice(&email_request.body).unwrap();
+    let header = body["message"]["headers"]["List-Unsubscribe"]
+      .as_str()
+      .unwrap();
+    let mut link =
+      Url::parse(header.trim_start_matches('<').trim_end_matches('>')).unwrap();
+    assert_eq!(link.host_str().unwrap(), "127.0.0.1");
+    link.set_port(Some(self.port)).unwrap();
+    link
+  }
 }
 
 /// Spin up an instance of our application and returns its address
 /// (i.e. http://localhost:XXXX)
 /// Also spins up a logical database each spawn, to insure the test's isolation
+/// and a mock server standing in for the email API.
 pub async fn spawn_app() -> TestApp {
+  spawn_app_with(|_| {}).await
+}
+
+/// `spawn_app`, with the configuration changed by `customise` first.
+pub async fn spawn_app_with(customise: impl FnOnce(&mut Settings)) -> TestApp {
   // `TRACING` is executed only once: the first time.
   Lazy::force(&TRACING);
 
+  let email_server = MockServer::start().await;
+
   let listener =
     TcpListener::bind("127.0.0.1:0").expect("Failed to bind random port");
   let port = listener.local_addr().unwrap().port();
@@ -49,30 +308,69 @@ pub async fn spawn_app() -> TestApp {
   let mut configuration =
     get_configuration().expect("Failed to read configuration");
   configuration.database.database_name = Uuid::new_v4().to_string();
+  configuration.email_client.base_url = email_server.uri();
+  // Tests check what reaches the provider, the sandbox would drop it all.
+  configuration.email_client.sandbox.enabled = false;
+  configuration.webhooks = WebhookSettings {
+    mandrill_key: Some(Secret::new(MANDRILL_WEBHOOK_KEY.into())),
+    postmark_username: Some(POSTMARK_WEBHOOK_USERNAME.into()),
+    postmark_password: Some(Secret::new(POSTMARK_WEBHOOK_PASSWORD.into())),
+    sendgrid_public_key: Some(base64::encode(
+      sendgrid_signing_key()
+        .verifying_key()
+        .to_public_key_der()
+        .unwrap(),
+    )),
+  };
+  customise(&mut configuration);
+  let base_url = configuration.application.base_url.clone();
 
   let connection_pool = configure_database(&configuration.database).await;
 
-  let timeout = configuration.email_client.timeout();
-
-  let sender_email = configuration
+  let email_client = configuration
     .email_client
-    .sender()
-    .expect("Invalid sender email address.");
-  let email_client = EmailClient::new(
-    configuration.email_client.base_url,
-    sender_email,
-    configuration.email_client.authorization_token,
-    timeout,
-  );
-
-  let server = run(listener, connection_pool.clone(), email_client)
-    .expect("Failed to bind to address");
-  let _ = spawn(server);
-
-  TestApp {
+    .client(configuration.application.unsubscribe_links())
+    .with_suppressions(SuppressionList::new(connection_pool.clone()));
+  let templates = configuration
+    .templates
+    .load()
+    .expect("Failed to load email templates.");
+  let open_tracking = configuration.application.open_tracking();
+
+  let server = run(
+    listener,
+    connection_pool.clone(),
+    email_client.clone(),
+    templates.clone(),
+    configuration.application.base_url,
+    configuration.application.hmac_secret,
+    open_tracking.clone(),
+    configuration.webhooks,
+    InMemorySessionStore::default(),
+  )
+  .expect("Failed to bind to address");
+  spawn(server);
+
+  let api_client = Client::builder()
+    .redirect(reqwest::redirect::Policy::none())
+    .cookie_store(true)
+    .build()
+    .unwrap();
+
+  let test_app = TestApp {
     address,
+    base_url,
+    port,
     db_pool: connection_pool,
-  }
+    email_server,
+    email_client,
+    templates,
+    open_tracking,
+    test_user: TestUser::generate(),
+    api_client,
+  };
+  test_app.test_user.store(&test_app.db_pool).await;
+  test_app
 }
 
 async fn configure_database(config: &DatabaseSettings) -> PgPool {
@@ -95,3 +393,37 @@ async fn configure_database(config: &DatabaseSettings) -> PgPool {
     .expect("Failed to migrate the database");
   connection_pool
 }
+
+pub fn assert_is_redirect_to(response: &Response, location: &str) {
+  assert_eq!(response.status().as_u16(), 303);
+  assert_eq!(response.headers().get("Location").unwrap(), location);
+}
+
+/// Mandrill's answer when it accepted an email for delivery.
+pub fn email_sent() -> impl Respond {
+  email_sent_after(Duration::ZERO)
+}
+
+/// Mandrill's answer when it accepted an email for delivery, taking `delay`
+/// to give it.
+pub fn email_sent_after(delay: Duration) -> impl Respond {
+  move |request: &Request| {
+    let body: serde_json::Value = serde_json::from_slice(&request.body)
+      .expect("Mandrill requests are JSON");
+    let statuses: Vec<_> = body["message"]["to"]
+      .as_array()
+      .expect("Mandrill requests have recipients")
+      .iter()
+      .map(|to| {
+        serde_json::json!({
+          "email": to["email"],
+          "status": "sent",
+          "_id": Uuid::new_v4().to_string(),
+        })
+      })
+      .collect();
+    ResponseTemplate::new(200)
+      .set_body_json(statuses)
+      .set_delay(delay)
+  }
+}
@@ -0,0 +1,63 @@
+use crate::api::helpers::{assert_is_redirect_to, spawn_app};
+
+#[tokio::test]
+async fn an_error_flash_message_is_set_on_failure() {
+  let app = spawn_app().await;
+
+  let login_body = serde_json::json!({
+    "username": "random-username",
+    "password": "random-password"
+  });
+  let response = app.post_login(&login_body).await;
+
+  assert_is_redirect_to(&response, "/login");
+  // The error must not leak through the query string
+  assert!(!response
+    .headers()
+    .get("Location")
+    .unwrap()
+    .to_str()
+    .unwrap()
+    .contains('?'));
+
+  // Follow the redirect
+  let html_page = app.get_login_html().await;
+  assert!(html_page.contains("<p><i>Authentication failed</i></p>"));
+
+  // Reload the login page
+  let html_page = app.get_login_html().await;
+  assert!(!html_page.contains("Authentication failed"));
+}
+
+#[tokio::test]
+async fn redirect_to_admin_dashboard_after_login_success() {
+  let app = spawn_app().await;
+
+  let login_body = serde_json::json!({
+    "username": &app.test_user.username,
+    "password": &app.test_user.password
+  });
+  let response = app.post_login(&login_body).await;
+  assert_is_redirect_to(&response, "/admin/dashboard");
+
+  // Follow the redirect
+  let html_page = app.get_admin_dashboard_html().await;
+  assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+}
+
+#[tokio::test]
+async fn session_cookies_are_signed_and_http_only() {
+  let app = spawn_app().await;
+
+  let login_body = serde_json::json!({
+    "username": &app.test_user.username,
+    "password": &app.test_user.password
+  });
+  let response = app.post_login(&login_body).await;
+
+  let cookie = response
+    .cookies()
+    .find(|c| c.name() == "id")
+    .expect("No session cookie was set");
+  assert!(cookie.http_only());
+}
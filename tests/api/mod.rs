@@ -1,3 +1,15 @@
+pub mod admin_dashboard;
+pub mod fake_provider;
+pub mod file_transport;
 pub mod health_check;
 pub mod helpers;
+pub mod login;
+pub mod newsletters;
+pub mod open_tracking;
+pub mod session_store;
+pub mod smtp;
 pub mod subscriptions;
+pub mod subscriptions_confirm;
+pub mod subscriptions_unsubscribe;
+pub mod suppressions;
+pub mod webhooks;
@@ -0,0 +1,554 @@
+use std::time::Duration;
+
+use emailer::issue_delivery_worker::{try_execute_task, ExecutionOutcome};
+use uuid::Uuid;
+use wiremock::{
+  matchers::{any, method, path},
+  Mock, ResponseTemplate,
+};
+
+use crate::api::helpers::{
+  email_sent, email_sent_after, spawn_app, ConfirmationLinks, TestApp,
+};
+
+/// Use the public API of the application under test to create
+/// an unconfirmed subscriber.
+async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+  let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+  let _mock_guard = Mock::given(path("/messages"))
+    .and(method("POST"))
+    .respond_with(email_sent())
+    .named("Create unconfirmed subscriber")
+    .expect(1)
+    .mount_as_scoped(&app.email_server)
+    .await;
+  app
+    .post_subscriptions(body.into())
+    .await
+    .error_for_status()
+    .unwrap();
+
+  let email_request = &app
+    .email_server
+    .received_requests()
+    .await
+    .unwrap()
+    .pop()
+    .unwrap();
+  app.get_confirmation_links(email_request)
+}
+
+async fn create_confirmed_subscriber(app: &TestApp) {
+  let confirmation_link = create_unconfirmed_subscriber(app).await;
+  reqwest::get(confirmation_link.html)
+    .await
+    .unwrap()
+    .error_for_status()
+    .unwrap();
+}
+
+fn newsletter_request_body() -> serde_json::Value {
+  serde_json::json!({
+    "title": "Newsletter title",
+    "content": {
+      "text": "Newsletter body as plain text",
+      "html": "<p>Newsletter body as HTML</p>",
+    }
+  })
+}
+
+#[tokio::test]
+async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
+  let app = spawn_app().await;
+  create_unconfirmed_subscriber(&app).await;
+
+  Mock::given(any())
+    .respond_with(email_sent())
+    .expect(0)
+    .mount(&app.email_server)
+    .await;
+
+  let response = app.post_newsletters(newsletter_request_body()).await;
+
+  assert_eq!(response.status().as_u16(), 202);
+  let receipt: serde_json::Value = response.json().await.unwrap();
+  assert_eq!(receipt["enqueued"], 0);
+  app.dispatch_all_pending_emails().await;
+  // Mock verifies on drop that we haven't sent the newsletter email
+}
+
+#[tokio::test]
+async fn newsletters_are_delivered_to_confirmed_subscribers() {
+  let app = spawn_app().await;
+  create_confirmed_subscriber(&app).await;
+
+  Mock::given(path("/messages"))
+    .and(method("POST"))
+    .respond_with(email_sent())
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+
+  let response = app.post_newsletters(newsletter_request_body()).await;
+
+  assert_eq!(response.status().as_u16(), 202);
+  let receipt: serde_json::Value = response.json().await.unwrap();
+  assert_eq!(receipt["enqueued"], 1);
+  app.dispatch_all_pending_emails().await;
+  // Mock verifies on drop that we have sent the newsletter email
+}
+
+#[tokio::test]
+async fn markdown_issues_are_delivered_as_html_and_plain_text() {
+  let app = spawn_app().await;
+  create_confirmed_subscriber(&app).await;
+
+  Mock::given(path("/messages"))
+    .and(method("POST"))
+    .respond_with(email_sent())
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+
+  let response = app
+    .post_newsletters(serde_json::json!({
+      "title": "Newsletter title",
+      "content": {
+        "markdown": "Hello **readers**! <script>alert(1)</script>"
+      }
+    }))
+    .await;
+  assert_eq!(response.status().as_u16(), 202);
+  app.dispatch_all_pending_emails().await;
+
+  // The first request was the confirmation email.
+  let requests = app.email_server.received_requests().await.unwrap();
+  let body: serde_json::Value =
+    serde_json::from_slice(&requests.last().unwrap().body).unwrap();
+  let html = body["message"]["html"].as_str().unwrap();
+  let text = body["message"]["text"].as_str().unwrap();
+  assert!(html.contains("<strong>readers</strong>"));
+  assert!(!html.contains("<script>"));
+  assert!(text.contains("Hello readers!"));
+}
+
+#[tokio::test]
+async fn attachments_are_delivered_with_the_issue() {
+  let app = spawn_app().await;
+  create_confirmed_subscriber(&app).await;
+
+  Mock::given(path("/messages"))
+    .and(method("POST"))
+    .respond_with(email_sent())
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+
+  let mut body = newsletter_request_body();
+  body["attachments"] = serde_json::json!([
+    {
+      "filename": "issue.pdf",
+      "content_type": "application/pdf",
+      "content": base64::encode("%PDF-1.4"),
+    },
+    {
+      "filename": "logo.png",
+      "content_type": "image/png",
+      "content": base64::encode(b"\x89PNG"),
+      "content_id": "logo",
+    }
+  ]);
+  let response = app.post_newsletters(body).await;
+  assert_eq!(response.status().as_u16(), 202);
+  app.dispatch_all_pending_emails().await;
+
+  let requests = app.email_server.received_requests().await.unwrap();
+  let body: serde_json::Value =
+    serde_json::from_slice(&requests.last().unwrap().body).unwrap();
+  assert_eq!(
+    body["message"]["attachments"],
+    serde_json::json!([{
+      "type": "application/pdf",
+      "name": "issue.pdf",
+      "content": base64::encode("%PDF-1.4"),
+    }])
+  );
+  assert_eq!(
+    body["message"]["images"],
+    serde_json::json!([{
+      "type": "image/png",
+      "name": "logo",
+      "content": base64::encode(b"\x89PNG"),
+    }])
+  );
+}
+
+#[tokio::test]
+async fn invalid_attachments_are_rejected_with_a_400() {
+  let app = spawn_app().await;
+  let test_cases = vec![
+    (
+      serde_json::json!({
+        "filename": "issue.pdf",
+        "content_type": "application/pdf",
+        "content": "not base64!",
+      }),
+      "content is not base64",
+    ),
+    (
+      serde_json::json!({
+        "filename": "",
+        "content_type": "application/pdf",
+        "content": base64::encode("%PDF-1.4"),
+      }),
+      "missing file name",
+    ),
+    (
+      serde_json::json!({
+        "filename": "issue.pdf",
+        "content_type": "not a MIME type",
+        "content": base64::encode("%PDF-1.4"),
+      }),
+      "invalid content type",
+    ),
+    (
+      serde_json::json!({
+        "filename": "issue.pdf",
+        "content_type": "application/pdf",
+        "content": base64::encode(vec![0; 16 * 1024 * 1024]),
+      }),
+      "too large for the provider",
+    ),
+  ];
+
+  for (attachment, error_message) in test_cases {
+    let mut body = newsletter_request_body();
+    body["attachments"] = serde_json::json!([attachment]);
+    let response = app.post_newsletters(body).await;
+
+    assert_eq!(
+      400,
+      response.status().as_u16(),
+      "The API did not fail with 400 Bad Request when the attachment's {}.",
+      error_message
+    );
+  }
+}
+
+#[tokio::test]
+async fn publishing_does_not_wait_for_delivery() {
+  let app = spawn_app().await;
+  create_confirmed_subscriber(&app).await;
+
+  Mock::given(any())
+    .respond_with(email_sent())
+    .expect(0)
+    .mount(&app.email_server)
+    .await;
+
+  let response = app.post_newsletters(newsletter_request_body()).await;
+
+  assert_eq!(response.status().as_u16(), 202);
+  let queued =
+    sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue")
+      .fetch_all(&app.db_pool)
+      .await
+      .unwrap();
+  assert_eq!(queued.len(), 1);
+  assert_eq!(queued[0].subscriber_email, "ursula_le_guin@gmail.com");
+  // Mock verifies on drop that nothing was sent before the worker ran
+}
+
+#[tokio::test]
+async fn newsletters_returns_400_for_invalid_data() {
+  let app = spawn_app().await;
+  let test_cases = vec![
+    (
+      serde_json::json!({
+        "content": {
+          "text": "Newsletter body as plain text",
+          "html": "<p>Newsletter body as HTML</p>",
+        }
+      }),
+      "missing title",
+    ),
+    (
+      serde_json::json!({ "title": "Newsletter!" }),
+      "missing content",
+    ),
+    (
+      serde_json::json!({
+        "title": "Newsletter!",
+        "content": { "html": "<p>Newsletter body as HTML</p>" }
+      }),
+      "missing plain text content",
+    ),
+  ];
+
+  for (invalid_body, error_message) in test_cases {
+    let response = app.post_newsletters(invalid_body).await;
+
+    assert_eq!(
+      400,
+      response.status().as_u16(),
+      "The API did not fail with 400 Bad Request when the payload was {}.",
+      error_message
+    );
+  }
+}
+
+#[tokio::test]
+async fn failed_deliveries_do_not_block_the_queue() {
+  let app = spawn_app().await;
+  create_confirmed_subscriber(&app).await;
+
+  Mock::given(path("/messages"))
+    .and(method("POST"))
+    .respond_with(ResponseTemplate::new(500))
+    // Every attempt allowed by the retry policy fails
+    .expect(1..)
+    .mount(&app.email_server)
+    .await;
+
+  let response = app.post_newsletters(newsletter_request_body()).await;
+  assert_eq!(response.status().as_u16(), 202);
+  app.dispatch_all_pending_emails().await;
+
+  let remaining =
+    sqlx::query!("SELECT COUNT(*) AS count FROM issue_delivery_queue")
+      .fetch_one(&app.db_pool)
+      .await
+      .unwrap();
+  assert_eq!(remaining.count, Some(0));
+}
+
+#[tokio::test]
+async fn issues_that_cannot_be_prepared_are_eventually_given_up_on() {
+  let app = spawn_app().await;
+  create_confirmed_subscriber(&app).await;
+  Mock::given(path("/messages"))
+    .and(method("POST"))
+    .respond_with(email_sent())
+    .expect(0)
+    .mount(&app.email_server)
+    .await;
+  let response = app.post_newsletters(newsletter_request_body()).await;
+  let receipt: serde_json::Value = response.json().await.unwrap();
+  let issue_id: Uuid = receipt["newsletter_issue_id"]
+    .as_str()
+    .unwrap()
+    .parse()
+    .unwrap();
+  // An attachment that can't be read back breaks every attempt.
+  sqlx::query!(
+    r#"
+    INSERT INTO newsletter_issue_attachments (
+      newsletter_issue_id, position, filename, content_type, content
+    )
+    VALUES ($1, 0, 'issue.pdf', 'not a MIME type', '')
+    "#,
+    issue_id
+  )
+  .execute(&app.db_pool)
+  .await
+  .unwrap();
+
+  let mut failed_attempts = 0;
+  for _ in 0..10 {
+    match try_execute_task(
+      &app.db_pool,
+      &app.email_client,
+      &app.templates,
+      &app.open_tracking,
+    )
+    .await
+    {
+      Ok(ExecutionOutcome::EmptyQueue) => break,
+      Ok(ExecutionOutcome::TaskCompleted) => {}
+      Err(_) => failed_attempts += 1,
+    }
+  }
+
+  assert!(failed_attempts > 0);
+  let remaining =
+    sqlx::query!("SELECT COUNT(*) AS count FROM issue_delivery_queue")
+      .fetch_one(&app.db_pool)
+      .await
+      .unwrap();
+  assert_eq!(remaining.count, Some(0));
+  let outcome = sqlx::query!(
+    "SELECT status FROM send_outcomes WHERE newsletter_issue_id = $1",
+    issue_id
+  )
+  .fetch_one(&app.db_pool)
+  .await
+  .unwrap();
+  assert_eq!(outcome.status, "failed");
+}
+
+#[tokio::test]
+async fn requests_missing_authorization_are_rejected() {
+  let app = spawn_app().await;
+
+  let response = reqwest::Client::new()
+    .post(format!("{}/newsletters", &app.address))
+    .json(&newsletter_request_body())
+    .send()
+    .await
+    .expect("Failed to execute request.");
+
+  assert_eq!(401, response.status().as_u16());
+  assert_eq!(
+    r#"Basic realm="publish""#,
+    response.headers()["WWW-Authenticate"]
+  );
+}
+
+#[tokio::test]
+async fn non_existing_user_is_rejected() {
+  let app = spawn_app().await;
+  let username = Uuid::new_v4().to_string();
+  let password = Uuid::new_v4().to_string();
+
+  let response = reqwest::Client::new()
+    .post(format!("{}/newsletters", &app.address))
+    .basic_auth(username, Some(password))
+    .json(&newsletter_request_body())
+    .send()
+    .await
+    .expect("Failed to execute request.");
+
+  assert_eq!(401, response.status().as_u16());
+  assert_eq!(
+    r#"Basic realm="publish""#,
+    response.headers()["WWW-Authenticate"]
+  );
+}
+
+#[tokio::test]
+async fn invalid_password_is_rejected() {
+  let app = spawn_app().await;
+  let username = &app.test_user.username;
+  // Random password
+  let password = Uuid::new_v4().to_string();
+  assert_ne!(app.test_user.password, password);
+
+  let response = reqwest::Client::new()
+    .post(format!("{}/newsletters", &app.address))
+    .basic_auth(username, Some(password))
+    .json(&newsletter_request_body())
+    .send()
+    .await
+    .expect("Failed to execute request.");
+
+  assert_eq!(401, response.status().as_u16());
+  assert_eq!(
+    r#"Basic realm="publish""#,
+    response.headers()["WWW-Authenticate"]
+  );
+}
+
+#[tokio::test]
+async fn newsletter_creation_is_idempotent() {
+  let app = spawn_app().await;
+  create_confirmed_subscriber(&app).await;
+
+  Mock::given(path("/messages"))
+    .and(method("POST"))
+    .respond_with(email_sent())
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+
+  // Submit newsletter form
+  let idempotency_key = Uuid::new_v4().to_string();
+  let response = app
+    .post_newsletters_with_idempotency_key(
+      newsletter_request_body(),
+      &idempotency_key,
+    )
+    .await;
+  assert_eq!(response.status().as_u16(), 202);
+  let first_body = response.text().await.unwrap();
+
+  // Submit newsletter form **again**
+  let response = app
+    .post_newsletters_with_idempotency_key(
+      newsletter_request_body(),
+      &idempotency_key,
+    )
+    .await;
+  assert_eq!(response.status().as_u16(), 202);
+  assert_eq!(
+    response.headers()["Content-Type"],
+    "application/json",
+    "The saved headers were not replayed"
+  );
+  assert_eq!(response.text().await.unwrap(), first_body);
+
+  app.dispatch_all_pending_emails().await;
+  // Mock verifies on Drop that we have sent the newsletter email **once**
+}
+
+#[tokio::test]
+async fn concurrent_form_submission_is_handled_gracefully() {
+  let app = spawn_app().await;
+  create_confirmed_subscriber(&app).await;
+
+  Mock::given(path("/messages"))
+    .and(method("POST"))
+    // Setting a long delay to ensure that the second request
+    // arrives before the first one completes
+    .respond_with(email_sent_after(Duration::from_secs(2)))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+
+  // Submit two newsletter forms concurrently
+  let idempotency_key = Uuid::new_v4().to_string();
+  let response1 = app.post_newsletters_with_idempotency_key(
+    newsletter_request_body(),
+    &idempotency_key,
+  );
+  let response2 = app.post_newsletters_with_idempotency_key(
+    newsletter_request_body(),
+    &idempotency_key,
+  );
+  let (response1, response2) = tokio::join!(response1, response2);
+
+  assert_eq!(response1.status(), response2.status());
+  assert_eq!(
+    response1.text().await.unwrap(),
+    response2.text().await.unwrap()
+  );
+
+  app.dispatch_all_pending_emails().await;
+  // Mock verifies on Drop that we have sent the newsletter email **once**
+}
+
+#[tokio::test]
+async fn invalid_idempotency_keys_are_rejected() {
+  let app = spawn_app().await;
+
+  let test_cases = vec![
+    ("".to_string(), "empty key"),
+    ("a".repeat(50), "key too long"),
+  ];
+
+  for (idempotency_key, description) in test_cases {
+    let response = app
+      .post_newsletters_with_idempotency_key(
+        newsletter_request_body(),
+        &idempotency_key,
+      )
+      .await;
+
+    assert_eq!(
+      400,
+      response.status().as_u16(),
+      "The API did not reject an invalid idempotency key ({}).",
+      description
+    );
+  }
+}
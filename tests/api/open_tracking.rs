@@ -0,0 +1,261 @@
+use chrono::Utc;
+use emailer::{
+  configuration::get_configuration, domain::SubscriberEmail,
+  open_tracking::OpenTracking,
+};
+use reqwest::{Client, Response};
+use uuid::Uuid;
+use wiremock::{
+  matchers::{method, path},
+  Mock,
+};
+
+use crate::api::helpers::{email_sent, spawn_app, spawn_app_with, TestApp};
+
+const BROWSER: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) \
+  AppleWebKit/605.1.15 (KHTML, like Gecko)";
+
+/// Publish an issue to a single confirmed subscriber, returning the message
+/// the email API received.
+async fn publish(
+  app: &TestApp,
+  track_opens: Option<bool>,
+) -> serde_json::Value {
+  sqlx::query!(
+    r#"
+    INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+    VALUES ($1, 'ursula_le_guin@gmail.com', 'le guin', $2, 'confirmed')
+    "#,
+    Uuid::new_v4(),
+    Utc::now(),
+  )
+  .execute(&app.db_pool)
+  .await
+  .unwrap();
+  Mock::given(path("/messages"))
+    .and(method("POST"))
+    .respond_with(email_sent())
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+  let mut body = serde_json::json!({
+    "title": "Newsletter title",
+    "content": {
+      "text": "Newsletter body as plain text",
+      "html": "<p>Newsletter body as HTML</p>",
+    }
+  });
+  if let Some(track_opens) = track_opens {
+    body["track_opens"] = track_opens.into();
+  }
+  app.post_newsletters(body).await.error_for_status().unwrap();
+  app.dispatch_all_pending_emails().await;
+  let request = &app.email_server.received_requests().await.unwrap()[0];
+  let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+  body["message"].clone()
+}
+
+/// The recipient's pixel URL, reachable at the test app's address.
+fn pixel_url(app: &TestApp, message: &serde_json::Value) -> String {
+  message["merge_vars"][0]["vars"]
+    .as_array()
+    .unwrap()
+    .iter()
+    .find(|var| var["name"] == "open_pixel_url")
+    .and_then(|var| var["content"].as_str())
+    .expect("No pixel URL among the merge variables")
+    .replace(&app.base_url, &app.address)
+}
+
+async fn load_pixel(url: &str, user_agent: &str) -> Response {
+  Client::new()
+    .get(url)
+    .header("User-Agent", user_agent)
+    .send()
+    .await
+    .expect("Failed to execute request")
+}
+
+async fn opens(app: &TestApp) -> Vec<(String, Option<String>, Option<String>)> {
+  sqlx::query!("SELECT recipient, user_agent, machine_open FROM email_opens")
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|open| (open.recipient, open.user_agent, open.machine_open))
+    .collect()
+}
+
+#[tokio::test]
+async fn opens_of_tracked_issues_are_recorded() {
+  let app = spawn_app().await;
+  let message = publish(&app, Some(true)).await;
+  assert!(message["html"]
+    .as_str()
+    .unwrap()
+    .contains(r#"<img src="{{open_pixel_url}}""#));
+
+  let response = load_pixel(&pixel_url(&app, &message), BROWSER).await;
+
+  assert_eq!(response.status().as_u16(), 200);
+  assert_eq!(response.headers()["Content-Type"], "image/gif");
+  assert_eq!(response.headers()["Cache-Control"], "no-store");
+  assert_eq!(
+    opens(&app).await,
+    vec![(
+      "ursula_le_guin@gmail.com".into(),
+      Some(BROWSER.into()),
+      None
+    )]
+  );
+}
+
+#[tokio::test]
+async fn issues_are_not_tracked_unless_asked_to() {
+  let app = spawn_app().await;
+
+  let message = publish(&app, None).await;
+
+  assert!(!message.to_string().contains("open_pixel_url"));
+}
+
+#[tokio::test]
+async fn machine_opens_are_flagged() {
+  let app = spawn_app().await;
+  let message = publish(&app, Some(true)).await;
+
+  load_pixel(&pixel_url(&app, &message), "Mozilla/5.0")
+    .await
+    .error_for_status()
+    .unwrap();
+
+  assert_eq!(
+    opens(&app).await,
+    vec![(
+      "ursula_le_guin@gmail.com".into(),
+      Some("Mozilla/5.0".into()),
+      Some("apple_mail_privacy".into())
+    )]
+  );
+}
+
+#[tokio::test]
+async fn invalid_pixel_tokens_are_not_found() {
+  let app = spawn_app().await;
+
+  let response =
+    load_pixel(&format!("{}/t/o/not-a-token", app.address), BROWSER).await;
+
+  assert_eq!(response.status().as_u16(), 404);
+  assert!(opens(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn disabling_open_tracking_disables_pixels_and_opens() {
+  let app = spawn_app_with(|configuration| {
+    configuration.application.open_tracking = false;
+  })
+  .await;
+  let message = publish(&app, Some(true)).await;
+  assert!(!message.to_string().contains("open_pixel_url"));
+  // A pixel sent before tracking was disabled
+  let configuration = get_configuration().unwrap();
+  let issue_id =
+    sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+      .fetch_one(&app.db_pool)
+      .await
+      .unwrap()
+      .newsletter_issue_id;
+  let url = OpenTracking::new(
+    app.address.clone(),
+    configuration.application.hmac_secret,
+    true,
+  )
+  .pixel_url(
+    issue_id,
+    &SubscriberEmail::parse("ursula_le_guin@gmail.com".into()).unwrap(),
+  );
+
+  let response = load_pixel(&url, BROWSER).await;
+
+  assert_eq!(response.status().as_u16(), 200);
+  assert!(opens(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn opens_of_erased_subscribers_are_not_recorded() {
+  let app = spawn_app().await;
+  let message = publish(&app, Some(true)).await;
+  Client::new()
+    .delete(format!(
+      "{}/api/v1/subscribers/ursula_le_guin@gmail.com",
+      app.address
+    ))
+    .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+    .send()
+    .await
+    .unwrap()
+    .error_for_status()
+    .unwrap();
+
+  let response = load_pixel(&pixel_url(&app, &message), BROWSER).await;
+
+  assert_eq!(response.status().as_u16(), 200);
+  assert!(opens(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn forwarded_headers_are_not_trusted() {
+  let app = spawn_app().await;
+  let message = publish(&app, Some(true)).await;
+
+  // An address on Apple's network would flag the open as a machine one.
+  Client::new()
+    .get(pixel_url(&app, &message))
+    .header("User-Agent", BROWSER)
+    .header("X-Forwarded-For", "17.58.0.1")
+    .send()
+    .await
+    .unwrap()
+    .error_for_status()
+    .unwrap();
+
+  assert_eq!(
+    opens(&app).await,
+    vec![(
+      "ursula_le_guin@gmail.com".into(),
+      Some(BROWSER.into()),
+      None
+    )]
+  );
+}
+
+#[tokio::test]
+async fn forwarded_headers_from_trusted_proxies_are_believed() {
+  let app = spawn_app_with(|c| {
+    c.application.trusted_proxies =
+      vec!["127.0.0.0/8".to_string().try_into().unwrap()]
+  })
+  .await;
+  let message = publish(&app, Some(true)).await;
+
+  // The test client connects from 127.0.0.1, standing in for a load balancer.
+  Client::new()
+    .get(pixel_url(&app, &message))
+    .header("User-Agent", BROWSER)
+    .header("X-Forwarded-For", "17.58.0.1")
+    .send()
+    .await
+    .unwrap()
+    .error_for_status()
+    .unwrap();
+
+  assert_eq!(
+    opens(&app).await,
+    vec![(
+      "ursula_le_guin@gmail.com".into(),
+      Some(BROWSER.into()),
+      Some("apple_mail_privacy".into())
+    )]
+  );
+}
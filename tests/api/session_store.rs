@@ -0,0 +1,53 @@
+use std::collections::HashMap;
+
+use actix_session::storage::SessionStore;
+use actix_web::cookie::time::Duration;
+use claim::{assert_none, assert_ok};
+use emailer::session_store::PostgresSessionStore;
+
+use crate::api::helpers::spawn_app;
+
+fn session_state() -> HashMap<String, String> {
+  HashMap::from([("user_id".to_string(), "\"42\"".to_string())])
+}
+
+#[tokio::test]
+async fn postgres_store_loads_what_it_saved() {
+  let app = spawn_app().await;
+  let store = PostgresSessionStore::new(app.db_pool.clone());
+
+  let key = store
+    .save(session_state(), &Duration::minutes(10))
+    .await
+    .unwrap();
+  let loaded = store.load(&key).await.unwrap();
+
+  assert_eq!(loaded, Some(session_state()));
+}
+
+#[tokio::test]
+async fn postgres_store_does_not_load_expired_sessions() {
+  let app = spawn_app().await;
+  let store = PostgresSessionStore::new(app.db_pool.clone());
+
+  let key = store
+    .save(session_state(), &Duration::seconds(-1))
+    .await
+    .unwrap();
+
+  assert_none!(store.load(&key).await.unwrap());
+}
+
+#[tokio::test]
+async fn postgres_store_forgets_deleted_sessions() {
+  let app = spawn_app().await;
+  let store = PostgresSessionStore::new(app.db_pool.clone());
+
+  let key = store
+    .save(session_state(), &Duration::minutes(10))
+    .await
+    .unwrap();
+  assert_ok!(store.delete(&key).await);
+
+  assert_none!(store.load(&key).await.unwrap());
+}
@@ -0,0 +1,475 @@
+use std::sync::{Arc, Mutex};
+
+use claim::{assert_err, assert_ok};
+use emailer::{
+  configuration::{
+    get_configuration, EmailProvider, SmtpAuthMechanism, SmtpSettings, SmtpTls,
+  },
+  domain::SubscriberEmail,
+  email_client::{
+    Attachment, BatchRecipient, EmailClient, Message, RecipientStatus,
+  },
+};
+use secrecy::Secret;
+use tokio::{
+  io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+  net::{TcpListener, TcpStream},
+};
+
+/// What the stand-in saw from its clients.
+#[derive(Default)]
+struct Received {
+  connections: usize,
+  /// `(mechanism, username, password)` for every successful `AUTH`
+  logins: Vec<(String, String, String)>,
+  messages: Vec<ReceivedMessage>,
+}
+
+struct ReceivedMessage {
+  from: String,
+  to: Vec<String>,
+  data: String,
+}
+
+/// A minimal in-process SMTP server, just enough of RFC 5321 for our client.
+struct SmtpStandIn {
+  port: u16,
+  received: Arc<Mutex<Received>>,
+  /// Reply to the next `RCPT TO`s, to simulate a relay refusing recipients
+  rcpt_rejection: Arc<Mutex<Option<RcptRejection>>>,
+}
+
+struct RcptRejection {
+  reply: String,
+  times: usize,
+}
+
+impl SmtpStandIn {
+  async fn start() -> Self {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let stand_in = Self {
+      port,
+      received: Arc::default(),
+      rcpt_rejection: Arc::default(),
+    };
+    let received = stand_in.received.clone();
+    let rcpt_rejection = stand_in.rcpt_rejection.clone();
+    tokio::spawn(async move {
+      loop {
+        let (stream, _) = listener.accept().await.unwrap();
+        received.lock().unwrap().connections += 1;
+        tokio::spawn(serve(stream, received.clone(), rcpt_rejection.clone()));
+      }
+    });
+    stand_in
+  }
+
+  /// Answer the next `times` recipients with `reply` instead of accepting them.
+  fn reject_recipients(&self, reply: &str, times: usize) {
+    *self.rcpt_rejection.lock().unwrap() = Some(RcptRejection {
+      reply: reply.into(),
+      times,
+    });
+  }
+
+  fn settings(&self, auth_mechanisms: Vec<SmtpAuthMechanism>) -> SmtpSettings {
+    SmtpSettings {
+      host: "127.0.0.1".into(),
+      port: self.port,
+      tls: SmtpTls::None,
+      username: Some("relay-user".into()),
+      password: Some(Secret::new("relay-password".into())),
+      auth_mechanisms,
+      pool_max_size: 2,
+    }
+  }
+}
+
+async fn serve(
+  stream: TcpStream,
+  received: Arc<Mutex<Received>>,
+  rcpt_rejection: Arc<Mutex<Option<RcptRejection>>>,
+) {
+  let (reader, mut writer) = stream.into_split();
+  let mut lines = BufReader::new(reader).lines();
+  let (mut from, mut to) = (String::new(), Vec::new());
+
+  writer
+    .write_all(reply("220 localhost ESMTP stand-in").as_bytes())
+    .await
+    .unwrap();
+  while let Ok(Some(line)) = lines.next_line().await {
+    let upper = line.to_uppercase();
+    let response = if upper.starts_with("EHLO") {
+      "250-localhost\r\n250-AUTH PLAIN LOGIN\r\n250 8BITMIME".to_string()
+    } else if let Some(initial) = line.strip_prefix("AUTH PLAIN ") {
+      let decoded = decode(initial);
+      let mut parts = decoded.split('\0').skip(1);
+      let (username, password) = (parts.next().unwrap(), parts.next().unwrap());
+      received.lock().unwrap().logins.push((
+        "PLAIN".into(),
+        username.into(),
+        password.into(),
+      ));
+      "235 2.7.0 Authentication successful".into()
+    } else if upper == "AUTH LOGIN" {
+      writer
+        .write_all(
+          reply(&format!("334 {}", base64::encode("Username:"))).as_bytes(),
+        )
+        .await
+        .unwrap();
+      let username = decode(&lines.next_line().await.unwrap().unwrap());
+      writer
+        .write_all(
+          reply(&format!("334 {}", base64::encode("Password:"))).as_bytes(),
+        )
+        .await
+        .unwrap();
+      let password = decode(&lines.next_line().await.unwrap().unwrap());
+      received.lock().unwrap().logins.push((
+        "LOGIN".into(),
+        username,
+        password,
+      ));
+      "235 2.7.0 Authentication successful".into()
+    } else if upper.starts_with("MAIL FROM:") {
+      from = address(&line);
+      to.clear();
+      "250 2.1.0 OK".into()
+    } else if upper.starts_with("RCPT TO:") {
+      let mut rejection = rcpt_rejection.lock().unwrap();
+      match rejection.as_mut() {
+        Some(RcptRejection { reply, times }) if *times > 0 => {
+          *times -= 1;
+          reply.clone()
+        }
+        _ => {
+          to.push(address(&line));
+          "250 2.1.5 OK".into()
+        }
+      }
+    } else if upper == "DATA" {
+      writer
+        .write_all(reply("354 End data with <CR><LF>.<CR><LF>").as_bytes())
+        .await
+        .unwrap();
+      let mut data = String::new();
+      while let Ok(Some(line)) = lines.next_line().await {
+        if line == "." {
+          break;
+        }
+        data.push_str(&line);
+        data.push('\n');
+      }
+      received.lock().unwrap().messages.push(ReceivedMessage {
+        from: from.clone(),
+        to: to.clone(),
+        data,
+      });
+      "250 2.0.0 OK: queued".into()
+    } else if upper == "QUIT" {
+      let _ = writer.write_all(reply("221 2.0.0 Bye").as_bytes()).await;
+      return;
+    } else {
+      // NOOP and RSET, used by the client's connection pool
+      "250 2.0.0 OK".into()
+    };
+    writer.write_all(reply(&response).as_bytes()).await.unwrap();
+  }
+}
+
+fn reply(line: &str) -> String {
+  format!("{}\r\n", line)
+}
+
+fn decode(value: &str) -> String {
+  String::from_utf8(base64::decode(value.trim()).unwrap()).unwrap()
+}
+
+/// `MAIL FROM:<a@b.c> SIZE=123` -> `a@b.c`
+fn address(line: &str) -> String {
+  let start = line.find('<').unwrap() + 1;
+  let end = line.find('>').unwrap();
+  line[start..end].to_string()
+}
+
+fn smtp_client(settings: SmtpSettings) -> EmailClient {
+  let mut configuration =
+    get_configuration().expect("Failed to read configuration");
+  configuration.email_client.provider = EmailProvider::Smtp;
+  configuration.email_client.smtp = Some(settings);
+  configuration.email_client.sandbox.enabled = false;
+  configuration.email_client.retry.base_delay_milliseconds = 10;
+  configuration.email_client.retry.jitter = 0.0;
+  let unsubscribe_links = configuration.application.unsubscribe_links();
+  configuration.email_client.client(unsubscribe_links)
+}
+
+fn recipient() -> SubscriberEmail {
+  SubscriberEmail::parse("ursula_le_guin@gmail.com".into()).unwrap()
+}
+
+#[tokio::test]
+async fn emails_are_relayed_over_smtp() {
+  let stand_in = SmtpStandIn::start().await;
+  let email_client = smtp_client(stand_in.settings(vec![]));
+
+  let outcome = email_client
+    .send_email(&recipient(), "Welcome!", "<p>Hi!</p>", "Hi!", &[])
+    .await;
+
+  assert_ok!(outcome);
+  let received = stand_in.received.lock().unwrap();
+  let message = &received.messages[0];
+  assert_eq!(message.to, vec!["ursula_le_guin@gmail.com"]);
+  assert_eq!(message.from, "test@gmail.com");
+  assert!(message.data.contains("Subject: Welcome!"));
+  assert!(message.data.contains("multipart/alternative"));
+  assert!(message.data.contains("<p>Hi!</p>"));
+  assert!(message
+    .data
+    .contains("List-Unsubscribe: <http://127.0.0.1/subscriptions/unsubscribe"));
+  assert!(message
+    .data
+    .contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click"));
+}
+
+#[tokio::test]
+async fn envelope_and_custom_headers_are_relayed_over_smtp() {
+  let stand_in = SmtpStandIn::start().await;
+  let email_client = smtp_client(stand_in.settings(vec![]));
+  let address = |email: &str| SubscriberEmail::parse(email.into()).unwrap();
+  let message = Message::builder("Welcome!", "<p>Hi!</p>", "Hi!")
+    .sender_name("Our newsletter")
+    .reply_to(address("editor@example.com"))
+    .cc(address("cc@example.com"))
+    .bcc(address("bcc@example.com"))
+    .header("X-Campaign", "spring")
+    .build()
+    .unwrap();
+
+  let outcome = email_client.send_message(&recipient(), &message).await;
+
+  assert_ok!(outcome);
+  let received = stand_in.received.lock().unwrap();
+  let message = &received.messages[0];
+  assert_eq!(
+    message.to,
+    vec![
+      "ursula_le_guin@gmail.com",
+      "cc@example.com",
+      "bcc@example.com"
+    ]
+  );
+  assert!(message
+    .data
+    .contains("From: \"Our newsletter\" <test@gmail.com>"));
+  assert!(message.data.contains("Reply-To: editor@example.com"));
+  assert!(message.data.contains("Cc: cc@example.com"));
+  assert!(!message.data.contains("bcc@example.com"));
+  assert!(message.data.contains("X-Campaign: spring"));
+}
+
+#[tokio::test]
+async fn attachments_and_inline_images_are_relayed_over_smtp() {
+  let stand_in = SmtpStandIn::start().await;
+  let email_client = smtp_client(stand_in.settings(vec![]));
+  let attachments = [
+    Attachment::new("issue.pdf".into(), "application/pdf", b"%PDF".to_vec())
+      .unwrap(),
+    Attachment::inline(
+      "logo".into(),
+      "logo.png".into(),
+      "image/png",
+      b"PNG".to_vec(),
+    )
+    .unwrap(),
+  ];
+
+  let outcome = email_client
+    .send_email(
+      &recipient(),
+      "Welcome!",
+      "<img src=\"cid:logo\">",
+      "Hi!",
+      &attachments,
+    )
+    .await;
+
+  assert_ok!(outcome);
+  let received = stand_in.received.lock().unwrap();
+  let data = &received.messages[0].data;
+  assert!(data.contains("multipart/mixed"));
+  assert!(data.contains("multipart/related"));
+  assert!(data.contains("Content-ID: <logo>"));
+  assert!(data.contains("filename=\"issue.pdf\""));
+}
+
+#[tokio::test]
+async fn starttls_is_required_of_the_relay() {
+  // The stand-in doesn't offer `STARTTLS`: nothing may go out in plain text.
+  let stand_in = SmtpStandIn::start().await;
+  let email_client = smtp_client(SmtpSettings {
+    tls: SmtpTls::StartTls,
+    ..stand_in.settings(vec![])
+  });
+
+  let outcome = email_client
+    .send_email(&recipient(), "Welcome!", "<p>Hi!</p>", "Hi!", &[])
+    .await;
+
+  assert_err!(outcome);
+  let received = stand_in.received.lock().unwrap();
+  assert!(received.connections > 0);
+  assert!(received.logins.is_empty());
+  assert!(received.messages.is_empty());
+}
+
+#[tokio::test]
+async fn implicit_tls_does_not_fall_back_to_plain_text() {
+  let stand_in = SmtpStandIn::start().await;
+  let email_client = smtp_client(SmtpSettings {
+    tls: SmtpTls::Implicit,
+    ..stand_in.settings(vec![])
+  });
+
+  let outcome = email_client
+    .send_email(&recipient(), "Welcome!", "<p>Hi!</p>", "Hi!", &[])
+    .await;
+
+  assert_err!(outcome);
+  let received = stand_in.received.lock().unwrap();
+  assert!(received.connections > 0);
+  assert!(received.logins.is_empty());
+  assert!(received.messages.is_empty());
+}
+
+#[tokio::test]
+async fn smtp_client_authenticates_with_auth_plain() {
+  let stand_in = SmtpStandIn::start().await;
+  let email_client =
+    smtp_client(stand_in.settings(vec![SmtpAuthMechanism::Plain]));
+
+  assert_ok!(
+    email_client
+      .send_email(&recipient(), "Welcome!", "<p>Hi!</p>", "Hi!", &[])
+      .await
+  );
+
+  assert_eq!(
+    stand_in.received.lock().unwrap().logins,
+    vec![(
+      "PLAIN".to_string(),
+      "relay-user".to_string(),
+      "relay-password".to_string()
+    )]
+  );
+}
+
+#[tokio::test]
+async fn smtp_client_authenticates_with_auth_login() {
+  let stand_in = SmtpStandIn::start().await;
+  let email_client =
+    smtp_client(stand_in.settings(vec![SmtpAuthMechanism::Login]));
+
+  assert_ok!(
+    email_client
+      .send_email(&recipient(), "Welcome!", "<p>Hi!</p>", "Hi!", &[])
+      .await
+  );
+
+  assert_eq!(
+    stand_in.received.lock().unwrap().logins,
+    vec![(
+      "LOGIN".to_string(),
+      "relay-user".to_string(),
+      "relay-password".to_string()
+    )]
+  );
+}
+
+#[tokio::test]
+async fn smtp_connections_are_reused_across_emails() {
+  let stand_in = SmtpStandIn::start().await;
+  let email_client = smtp_client(stand_in.settings(vec![]));
+
+  for _ in 0..3 {
+    assert_ok!(
+      email_client
+        .send_email(&recipient(), "Welcome!", "<p>Hi!</p>", "Hi!", &[])
+        .await
+    );
+    // Connections go back to the pool in the background, give it a moment.
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+  }
+
+  let received = stand_in.received.lock().unwrap();
+  assert_eq!(received.messages.len(), 3);
+  assert_eq!(received.connections, 1);
+  assert_eq!(received.logins.len(), 1);
+}
+
+#[tokio::test]
+async fn permanent_smtp_rejections_are_not_retried() {
+  let stand_in = SmtpStandIn::start().await;
+  stand_in.reject_recipients("550 5.1.1 No such user", 1);
+  let email_client = smtp_client(stand_in.settings(vec![]));
+
+  let outcome = email_client
+    .send_email(&recipient(), "Welcome!", "<p>Hi!</p>", "Hi!", &[])
+    .await;
+
+  assert_err!(outcome);
+  assert!(stand_in.received.lock().unwrap().messages.is_empty());
+}
+
+#[tokio::test]
+async fn transient_smtp_rejections_are_retried() {
+  let stand_in = SmtpStandIn::start().await;
+  stand_in.reject_recipients("451 4.3.0 Try again later", 1);
+  let email_client = smtp_client(stand_in.settings(vec![]));
+
+  let outcome = email_client
+    .send_email(&recipient(), "Welcome!", "<p>Hi!</p>", "Hi!", &[])
+    .await;
+
+  assert_ok!(outcome);
+  assert_eq!(stand_in.received.lock().unwrap().messages.len(), 1);
+}
+
+#[tokio::test]
+async fn batches_are_relayed_as_one_merged_message_per_recipient() {
+  let stand_in = SmtpStandIn::start().await;
+  let email_client = smtp_client(stand_in.settings(vec![]));
+  let recipients: Vec<_> = ["a@example.com", "b@example.com"]
+    .iter()
+    .map(|email| {
+      let mut recipient =
+        BatchRecipient::new(SubscriberEmail::parse(email.to_string()).unwrap());
+      recipient
+        .merge_vars
+        .insert("email".into(), email.to_string());
+      recipient
+    })
+    .collect();
+
+  let results = email_client
+    .send_batch(&recipients, "Hi {{email}}", "<p>Hi!</p>", "Hi!", &[])
+    .await;
+
+  assert!(results
+    .iter()
+    .all(|result| result.status == RecipientStatus::Sent));
+  let received = stand_in.received.lock().unwrap();
+  assert_eq!(received.messages.len(), 2);
+  for (message, email) in received
+    .messages
+    .iter()
+    .zip(["a@example.com", "b@example.com"])
+  {
+    assert_eq!(message.to, vec![email]);
+    assert!(message.data.contains(&format!("Subject: Hi {}", email)));
+  }
+}
@@ -1,36 +1,52 @@
-use reqwest::Client;
+use wiremock::{
+  matchers::{method, path},
+  Mock, ResponseTemplate,
+};
 
-use crate::api::helpers::spawn_app;
+use crate::api::helpers::{email_sent, spawn_app};
 
 #[tokio::test]
 async fn subscribe_returns_a_200_for_valid_form_data() {
   let app = spawn_app().await;
-  let client = Client::new();
-
   let body = "name=le%20guin&email=jau%40gmail.com";
-  let response = client
-    .post(&format!("{}/subscriptions", &app.address))
-    .header("Content-Type", "application/x-www-form-urlencoded")
-    .body(body)
-    .send()
-    .await
-    .expect("Failed to execute request");
+
+  Mock::given(path("/messages"))
+    .and(method("POST"))
+    .respond_with(email_sent())
+    .mount(&app.email_server)
+    .await;
+
+  let response = app.post_subscriptions(body.into()).await;
 
   assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn subscribe_persists_the_new_subscriber() {
+  let app = spawn_app().await;
+  let body = "name=le%20guin&email=jau%40gmail.com";
+
+  Mock::given(path("/messages"))
+    .and(method("POST"))
+    .respond_with(email_sent())
+    .mount(&app.email_server)
+    .await;
 
-  let saved = sqlx::query!("SELECT email, name FROM subscriptions",)
+  app.post_subscriptions(body.into()).await;
+
+  let saved = sqlx::query!("SELECT email, name, status FROM subscriptions",)
     .fetch_one(&app.db_pool)
     .await
     .expect("Failed to fetch saved subscriptions");
 
   assert_eq!(saved.email, "jau@gmail.com");
   assert_eq!(saved.name, "le guin");
+  assert_eq!(saved.status, "pending_confirmation");
 }
 
 #[tokio::test]
 async fn subscribe_returns_a_400_when_data_is_missing() {
   let app = spawn_app().await;
-  let client = Client::new();
 
   let test_cases = vec![
     ("name=le%20guin", "missing the email"),
@@ -39,13 +55,7 @@ async fn subscribe_returns_a_400_when_data_is_missing() {
   ];
 
   for (invalid_body, error_message) in test_cases {
-    let response = client
-      .post(&format!("{}/subscriptions", &app.address))
-      .header("Content-Type", "application/x-www-form-urlencoded")
-      .body(invalid_body)
-      .send()
-      .await
-      .expect("Failed to execute request");
+    let response = app.post_subscriptions(invalid_body.into()).await;
 
     assert_eq!(
       400,
@@ -59,7 +69,6 @@ async fn subscribe_returns_a_400_when_data_is_missing() {
 #[tokio::test]
 async fn subscribe_returns_a_400_when_fields_are_present_but_invalid() {
   let app = spawn_app().await;
-  let client = reqwest::Client::new();
   let test_cases = vec![
     ("name=&email=ursula_le_guin%40gmail.com", "empty name"),
     ("name=Ursula&email=", "empty email"),
@@ -67,13 +76,7 @@ async fn subscribe_returns_a_400_when_fields_are_present_but_invalid() {
   ];
 
   for (body, description) in test_cases {
-    let response = client
-      .post(&format!("{}/subscriptions", &app.address))
-      .header("Content-Type", "application/x-www-form-urlencoded")
-      .body(body)
-      .send()
-      .await
-      .expect("Failed to execute request");
+    let response = app.post_subscriptions(body.into()).await;
 
     assert_eq!(
       400,
@@ -83,3 +86,369 @@ async fn subscribe_returns_a_400_when_fields_are_present_but_invalid() {
     );
   }
 }
+
+#[tokio::test]
+async fn subscribe_explains_why_the_data_is_invalid() {
+  let app = spawn_app().await;
+  let body = "name=&email=definitely-not-an-email";
+
+  let response = app.post_subscriptions(body.into()).await;
+
+  assert_eq!(response.status().as_u16(), 400);
+  let body: serde_json::Value = response.json().await.unwrap();
+  assert_eq!(
+    body,
+    serde_json::json!({
+      "error": "validation_error",
+      "message": "The email address is invalid. The name is empty.",
+      "fields": {
+        "email": "The email address is invalid.",
+        "name": "The name is empty.",
+      },
+    })
+  );
+}
+
+#[tokio::test]
+async fn subscribe_sends_a_confirmation_email_for_valid_data() {
+  let app = spawn_app().await;
+  let body = "name=le%20guin&email=jau%40gmail.com";
+
+  Mock::given(path("/messages"))
+    .and(method("POST"))
+    .respond_with(email_sent())
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+
+  app.post_subscriptions(body.into()).await;
+
+  // Mock asserts on drop
+}
+
+#[tokio::test]
+async fn subscribe_sends_a_confirmation_email_with_a_link() {
+  let app = spawn_app().await;
+  let body = "name=le%20guin&email=jau%40gmail.com";
+
+  Mock::given(path("/messages"))
+    .and(method("POST"))
+    .respond_with(email_sent())
+    .mount(&app.email_server)
+    .await;
+
+  app.post_subscriptions(body.into()).await;
+
+  let email_request = &app.email_server.received_requests().await.unwrap()[0];
+  let confirmation_links = app.get_confirmation_links(email_request);
+
+  // The two links should be identical
+  assert_eq!(confirmation_links.html, confirmation_links.plain_text);
+}
+
+#[tokio::test]
+async fn subscribe_stores_the_outcome_of_the_confirmation_email() {
+  let app = spawn_app().await;
+  let body = "name=le%20guin&email=jau%40gmail.com";
+
+  Mock::given(path("/messages"))
+    .and(method("POST"))
+    .respond_with(ResponseTemplate::new(200).set_body_json(
+      serde_json::json!([{
+        "email": "jau@gmail.com",
+        "status": "rejected",
+        "reject_reason": "hard-bounce",
+        "_id": "abc123abc123abc123abc123abc123"
+      }]),
+    ))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+
+  app.post_subscriptions(body.into()).await;
+
+  let saved = sqlx::query!(
+    "SELECT recipient, status, reject_reason, provider_message_id \
+    FROM send_outcomes",
+  )
+  .fetch_one(&app.db_pool)
+  .await
+  .expect("Failed to fetch the stored send outcome.");
+  assert_eq!(saved.recipient, "jau@gmail.com");
+  assert_eq!(saved.status, "rejected");
+  assert_eq!(saved.reject_reason.as_deref(), Some("hard-bounce"));
+  assert_eq!(
+    saved.provider_message_id.as_deref(),
+    Some("abc123abc123abc123abc123abc123")
+  );
+}
+
+#[tokio::test]
+async fn subscribe_fails_if_there_is_a_fatal_database_error() {
+  let app = spawn_app().await;
+  let body = "name=le%20guin&email=jau%40gmail.com";
+
+  // Sabotage the database
+  sqlx::query!(
+    "ALTER TABLE subscription_tokens DROP COLUMN subscription_token;",
+  )
+  .execute(&app.db_pool)
+  .await
+  .unwrap();
+
+  let response = app.post_subscriptions(body.into()).await;
+
+  assert_eq!(response.status().as_u16(), 500);
+  let body: serde_json::Value = response.json().await.unwrap();
+  assert_eq!(
+    body,
+    serde_json::json!({
+      "error": "unexpected_error",
+      "message": "Something went wrong.",
+    })
+  );
+}
+
+#[tokio::test]
+async fn api_subscribe_returns_the_created_subscriber() {
+  let app = spawn_app().await;
+  Mock::given(path("/messages"))
+    .and(method("POST"))
+    .respond_with(email_sent())
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+
+  let response = app
+    .post_api_subscriptions(serde_json::json!({
+      "name": "le guin",
+      "email": "jau@gmail.com",
+    }))
+    .await;
+
+  assert_eq!(response.status().as_u16(), 201);
+  let body: serde_json::Value = response.json().await.unwrap();
+  let saved = sqlx::query!("SELECT id, email, name, status FROM subscriptions")
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch saved subscription.");
+  assert_eq!(
+    body,
+    serde_json::json!({
+      "id": saved.id,
+      "email": "jau@gmail.com",
+      "name": "le guin",
+      "status": "pending_confirmation",
+    })
+  );
+  assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn api_subscribe_reports_each_invalid_field() {
+  let app = spawn_app().await;
+  let test_cases = vec![
+    (
+      serde_json::json!({"name": "", "email": "jau@gmail.com"}),
+      serde_json::json!({"name": "The name is empty."}),
+    ),
+    (
+      serde_json::json!({"name": "le guin", "email": "not-an-email"}),
+      serde_json::json!({"email": "The email address is invalid."}),
+    ),
+    (
+      serde_json::json!({"name": "le (guin)", "email": ""}),
+      serde_json::json!({
+        "email": "The email address is invalid.",
+        "name": "The name contains one of / ( ) \" < > \\ { } %.",
+      }),
+    ),
+  ];
+
+  for (body, fields) in test_cases {
+    let response = app.post_api_subscriptions(body.clone()).await;
+
+    assert_eq!(response.status().as_u16(), 400, "Payload: {}", body);
+    let error: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(error["error"], "validation_error", "Payload: {}", body);
+    assert_eq!(error["fields"], fields, "Payload: {}", body);
+  }
+}
+
+#[tokio::test]
+async fn api_subscribe_rejects_malformed_bodies_with_an_error_body() {
+  let app = spawn_app().await;
+  let test_cases = vec![
+    serde_json::json!({"name": "le guin"}),
+    serde_json::json!({"email": "jau@gmail.com"}),
+    serde_json::json!("le guin <jau@gmail.com>"),
+  ];
+
+  for body in test_cases {
+    let response = app.post_api_subscriptions(body.clone()).await;
+
+    assert_eq!(response.status().as_u16(), 400, "Payload: {}", body);
+    let error: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(error["error"], "invalid_body", "Payload: {}", body);
+  }
+}
+
+#[tokio::test]
+async fn subscribing_twice_while_pending_resends_the_confirmation_email() {
+  let app = spawn_app().await;
+  let body = "name=le%20guin&email=jau%40gmail.com";
+  Mock::given(path("/messages"))
+    .and(method("POST"))
+    .respond_with(email_sent())
+    .expect(2)
+    .mount(&app.email_server)
+    .await;
+
+  let first = app.post_subscriptions(body.into()).await;
+  let second = app.post_subscriptions(body.into()).await;
+
+  assert_eq!(first.status().as_u16(), 200);
+  assert_eq!(second.status().as_u16(), 200);
+  let requests = app.email_server.received_requests().await.unwrap();
+  let first_link = app.get_confirmation_links(&requests[0]).html;
+  let second_link = app.get_confirmation_links(&requests[1]).html;
+  assert_ne!(first_link, second_link);
+  reqwest::get(second_link)
+    .await
+    .unwrap()
+    .error_for_status()
+    .unwrap();
+  let saved = sqlx::query!("SELECT status FROM subscriptions")
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+  assert_eq!(saved.len(), 1);
+  assert_eq!(saved[0].status, "confirmed");
+}
+
+#[tokio::test]
+async fn addresses_differing_only_in_case_are_the_same_subscriber() {
+  let app = spawn_app().await;
+  Mock::given(path("/messages"))
+    .and(method("POST"))
+    .respond_with(email_sent())
+    .expect(2)
+    .mount(&app.email_server)
+    .await;
+
+  app
+    .post_subscriptions("name=le%20guin&email=jau%40gmail.com".into())
+    .await
+    .error_for_status()
+    .unwrap();
+  let response = app
+    .post_subscriptions("name=le%20guin&email=JAU%40Gmail.com".into())
+    .await;
+
+  assert_eq!(response.status().as_u16(), 200);
+  let saved = sqlx::query!("SELECT email FROM subscriptions")
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+  assert_eq!(saved.len(), 1);
+  assert_eq!(saved[0].email, "jau@gmail.com");
+}
+
+#[tokio::test]
+async fn subscribing_again_once_confirmed_changes_nothing() {
+  let app = spawn_app().await;
+  let body = "name=le%20guin&email=jau%40gmail.com";
+  Mock::given(path("/messages"))
+    .and(method("POST"))
+    .respond_with(email_sent())
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+  app.post_subscriptions(body.into()).await;
+  let email_request = &app.email_server.received_requests().await.unwrap()[0];
+  let confirmation_links = app.get_confirmation_links(email_request);
+  reqwest::get(confirmation_links.html)
+    .await
+    .unwrap()
+    .error_for_status()
+    .unwrap();
+
+  let response = app.post_subscriptions(body.into()).await;
+
+  assert_eq!(response.status().as_u16(), 200);
+  let saved = sqlx::query!("SELECT status FROM subscriptions")
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+  assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn unsubscribed_subscribers_must_confirm_again() {
+  let app = spawn_app().await;
+  let body = "name=le%20guin&email=jau%40gmail.com";
+  Mock::given(path("/messages"))
+    .and(method("POST"))
+    .respond_with(email_sent())
+    .expect(2)
+    .mount(&app.email_server)
+    .await;
+  app.post_subscriptions(body.into()).await;
+  sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed'")
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+  let response = app.post_subscriptions(body.into()).await;
+
+  assert_eq!(response.status().as_u16(), 200);
+  let saved = sqlx::query!("SELECT status FROM subscriptions")
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+  assert_eq!(saved.status, "pending_confirmation");
+  let email_request = &app.email_server.received_requests().await.unwrap()[1];
+  let confirmation_links = app.get_confirmation_links(email_request);
+  reqwest::get(confirmation_links.html)
+    .await
+    .unwrap()
+    .error_for_status()
+    .unwrap();
+}
+
+#[tokio::test]
+async fn api_answers_do_not_reveal_whether_an_address_is_on_the_list() {
+  let app = spawn_app().await;
+  Mock::given(path("/messages"))
+    .and(method("POST"))
+    .respond_with(email_sent())
+    .mount(&app.email_server)
+    .await;
+  let subscriber = serde_json::json!({
+    "name": "le guin",
+    "email": "jau@gmail.com",
+  });
+  app.post_api_subscriptions(subscriber.clone()).await;
+  sqlx::query!("UPDATE subscriptions SET status = 'confirmed'")
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+  let known = app.post_api_subscriptions(subscriber).await;
+  let unknown = app
+    .post_api_subscriptions(serde_json::json!({
+      "name": "le guin",
+      "email": "someone_else@gmail.com",
+    }))
+    .await;
+
+  assert_eq!(known.status(), unknown.status());
+  let mut known: serde_json::Value = known.json().await.unwrap();
+  let mut unknown: serde_json::Value = unknown.json().await.unwrap();
+  for body in [&mut known, &mut unknown] {
+    let body = body.as_object_mut().unwrap();
+    body.remove("id");
+    body.remove("email");
+  }
+  assert_eq!(known, unknown);
+}
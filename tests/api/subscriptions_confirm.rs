@@ -0,0 +1,109 @@
+use reqwest::Client;
+use wiremock::{
+  matchers::{method, path},
+  Mock,
+};
+
+use crate::api::helpers::{email_sent, spawn_app};
+
+#[tokio::test]
+async fn confirmations_without_token_are_rejected_with_a_400() {
+  let app = spawn_app().await;
+
+  let response = Client::new()
+    .get(format!("{}/subscriptions/confirm", app.address))
+    .send()
+    .await
+    .unwrap();
+
+  assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn confirmations_with_an_unknown_token_are_rejected_with_a_401() {
+  let app = spawn_app().await;
+
+  let response = Client::new()
+    .get(format!(
+      "{}/subscriptions/confirm?subscription_token=unknowntoken",
+      app.address
+    ))
+    .send()
+    .await
+    .unwrap();
+
+  assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn the_link_returned_by_subscribe_returns_a_200_if_called() {
+  let app = spawn_app().await;
+  let body = "name=le%20guin&email=jau%40gmail.com";
+
+  Mock::given(path("/messages"))
+    .and(method("POST"))
+    .respond_with(email_sent())
+    .mount(&app.email_server)
+    .await;
+
+  app.post_subscriptions(body.into()).await;
+  let email_request = &app.email_server.received_requests().await.unwrap()[0];
+  let confirmation_links = app.get_confirmation_links(email_request);
+
+  let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+  assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
+  let app = spawn_app().await;
+  let body = "name=le%20guin&email=jau%40gmail.com";
+
+  Mock::given(path("/messages"))
+    .and(method("POST"))
+    .respond_with(email_sent())
+    .mount(&app.email_server)
+    .await;
+
+  app.post_subscriptions(body.into()).await;
+  let email_request = &app.email_server.received_requests().await.unwrap()[0];
+  let confirmation_links = app.get_confirmation_links(email_request);
+
+  reqwest::get(confirmation_links.html)
+    .await
+    .unwrap()
+    .error_for_status()
+    .unwrap();
+
+  let saved = sqlx::query!("SELECT email, name, status FROM subscriptions",)
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch saved subscription.");
+
+  assert_eq!(saved.email, "jau@gmail.com");
+  assert_eq!(saved.name, "le guin");
+  assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn confirmation_links_can_only_be_used_once() {
+  let app = spawn_app().await;
+  let body = "name=le%20guin&email=jau%40gmail.com";
+
+  Mock::given(path("/messages"))
+    .and(method("POST"))
+    .respond_with(email_sent())
+    .mount(&app.email_server)
+    .await;
+
+  app.post_subscriptions(body.into()).await;
+  let email_request = &app.email_server.received_requests().await.unwrap()[0];
+  let confirmation_links = app.get_confirmation_links(email_request);
+
+  let first = reqwest::get(confirmation_links.html.clone()).await.unwrap();
+  assert_eq!(first.status().as_u16(), 200);
+
+  let second = reqwest::get(confirmation_links.html).await.unwrap();
+  assert_eq!(second.status().as_u16(), 401);
+}
@@ -0,0 +1,144 @@
+use reqwest::{Client, Url};
+use wiremock::{
+  matchers::{method, path},
+  Mock,
+};
+
+use crate::api::helpers::{email_sent, spawn_app, TestApp};
+
+/// Subscribe, returning the unsubscribe link of the confirmation email.
+async fn subscribe(app: &TestApp) -> Url {
+  let _mock_guard = Mock::given(path("/messages"))
+    .and(method("POST"))
+    .respond_with(email_sent())
+    .expect(1)
+    .mount_as_scoped(&app.email_server)
+    .await;
+  app
+    .post_subscriptions("name=le%20guin&email=jau%40gmail.com".into())
+    .await
+    .error_for_status()
+    .unwrap();
+  let email_request = &app.email_server.received_requests().await.unwrap()[0];
+  app.get_unsubscribe_link(email_request)
+}
+
+async fn status(app: &TestApp) -> String {
+  sqlx::query!("SELECT status FROM subscriptions")
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .status
+}
+
+#[tokio::test]
+async fn the_unsubscribe_page_asks_for_confirmation() {
+  let app = spawn_app().await;
+  let link = subscribe(&app).await;
+
+  let response = reqwest::get(link).await.unwrap();
+
+  assert_eq!(response.status().as_u16(), 200);
+  let html = response.text().await.unwrap();
+  assert!(html.contains("jau@gmail.com"));
+  assert!(html.contains(r#"method="post""#));
+  assert_eq!(status(&app).await, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn posting_to_the_unsubscribe_link_unsubscribes() {
+  let app = spawn_app().await;
+  let link = subscribe(&app).await;
+
+  // What mail clients send for RFC 8058 one-click unsubscribe
+  let response = Client::new()
+    .post(link)
+    .header("Content-Type", "application/x-www-form-urlencoded")
+    .body("List-Unsubscribe=One-Click")
+    .send()
+    .await
+    .unwrap();
+
+  assert_eq!(response.status().as_u16(), 200);
+  assert_eq!(status(&app).await, "unsubscribed");
+}
+
+#[tokio::test]
+async fn forged_unsubscribe_tokens_are_rejected_with_a_401() {
+  let app = spawn_app().await;
+  let mut link = subscribe(&app).await;
+  let token = link.query_pairs().next().unwrap().1.into_owned();
+  let (_, signature) = token.split_once('.').unwrap();
+  let forged = format!(
+    "{}.{}",
+    base64::encode_config("someone_else@gmail.com", base64::URL_SAFE_NO_PAD),
+    signature
+  );
+  link.set_query(Some(&format!("token={}", forged)));
+
+  let page = reqwest::get(link.clone()).await.unwrap();
+  let unsubscribe = Client::new().post(link).send().await.unwrap();
+
+  assert_eq!(page.status().as_u16(), 401);
+  assert_eq!(unsubscribe.status().as_u16(), 401);
+  assert_eq!(status(&app).await, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn old_confirmation_links_do_not_undo_an_unsubscribe() {
+  let app = spawn_app().await;
+  Mock::given(path("/messages"))
+    .and(method("POST"))
+    .respond_with(email_sent())
+    .expect(2)
+    .mount(&app.email_server)
+    .await;
+  for _ in 0..2 {
+    app
+      .post_subscriptions("name=le%20guin&email=jau%40gmail.com".into())
+      .await
+      .error_for_status()
+      .unwrap();
+  }
+  let requests = app.email_server.received_requests().await.unwrap();
+  let first = app.get_confirmation_links(&requests[0]).html;
+  let second = app.get_confirmation_links(&requests[1]).html;
+  assert_ne!(first, second);
+  reqwest::get(first)
+    .await
+    .unwrap()
+    .error_for_status()
+    .unwrap();
+  Client::new()
+    .post(app.get_unsubscribe_link(&requests[0]))
+    .send()
+    .await
+    .unwrap()
+    .error_for_status()
+    .unwrap();
+
+  let response = reqwest::get(second).await.unwrap();
+
+  assert_eq!(response.status().as_u16(), 401);
+  assert_eq!(status(&app).await, "unsubscribed");
+}
+
+#[tokio::test]
+async fn unsubscribing_ignores_the_case_of_the_address() {
+  let app = spawn_app().await;
+  let link = subscribe(&app).await;
+  sqlx::query!("UPDATE subscriptions SET email = 'Jau@Gmail.com'")
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+  Client::new()
+    .post(link)
+    .send()
+    .await
+    .unwrap()
+    .error_for_status()
+    .unwrap();
+
+  assert_eq!(status(&app).await, "unsubscribed");
+}
@@ -0,0 +1,246 @@
+use emailer::{
+  domain::SubscriberEmail,
+  email_client::{BatchRecipient, RecipientStatus},
+};
+use reqwest::{Client, Response};
+use wiremock::{
+  matchers::{any, method, path},
+  Mock, ResponseTemplate,
+};
+
+use crate::api::helpers::{email_sent, spawn_app, TestApp};
+
+async fn get_suppressions(app: &TestApp) -> serde_json::Value {
+  Client::new()
+    .get(format!("{}/api/v1/suppressions", app.address))
+    .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+    .send()
+    .await
+    .unwrap()
+    .json()
+    .await
+    .unwrap()
+}
+
+async fn delete(app: &TestApp, path: &str) -> Response {
+  Client::new()
+    .delete(format!("{}/api/v1/{}", app.address, path))
+    .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+    .send()
+    .await
+    .expect("Failed to execute request")
+}
+
+async fn suppress(app: &TestApp, address: &str) {
+  app
+    .post_suppressions(serde_json::json!({
+      "address": address,
+      "reason": "Asked us by phone",
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+}
+
+#[tokio::test]
+async fn admins_can_add_list_and_remove_suppressions() {
+  let app = spawn_app().await;
+
+  let response = app
+    .post_suppressions(serde_json::json!({
+      "address": "Ursula@Example.com",
+      "reason": "Asked us by phone",
+    }))
+    .await;
+  assert_eq!(response.status().as_u16(), 201);
+  let suppression: serde_json::Value = response.json().await.unwrap();
+  assert_eq!(suppression["address"], "ursula@example.com");
+  assert_eq!(suppression["source"], "admin");
+
+  // Suppressing again changes nothing.
+  let response = app
+    .post_suppressions(serde_json::json!({
+      "address": "ursula@example.com",
+      "reason": "Another reason",
+    }))
+    .await;
+  assert_eq!(response.status().as_u16(), 200);
+  let suppressions = get_suppressions(&app).await;
+  assert_eq!(suppressions.as_array().unwrap().len(), 1);
+  assert_eq!(suppressions[0]["reason"], "Asked us by phone");
+
+  let response = delete(&app, "suppressions/ursula@example.com").await;
+  assert_eq!(response.status().as_u16(), 204);
+  assert_eq!(get_suppressions(&app).await, serde_json::json!([]));
+  let response = delete(&app, "suppressions/ursula@example.com").await;
+  assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn invalid_suppressions_are_rejected_with_a_400() {
+  let app = spawn_app().await;
+  let test_cases = vec![
+    (
+      serde_json::json!({"address": "ursula@", "reason": "Typo"}),
+      "an invalid address",
+    ),
+    (
+      serde_json::json!({"address": "example.com", "reason": " "}),
+      "an empty reason",
+    ),
+  ];
+
+  for (body, description) in test_cases {
+    let response = app.post_suppressions(body).await;
+
+    assert_eq!(
+      response.status().as_u16(),
+      400,
+      "The API did not fail with 400 Bad Request for {}.",
+      description
+    );
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["error"], "validation_error");
+  }
+}
+
+#[tokio::test]
+async fn the_suppression_api_requires_authentication() {
+  let app = spawn_app().await;
+
+  let response = Client::new()
+    .get(format!("{}/api/v1/suppressions", app.address))
+    .send()
+    .await
+    .unwrap();
+
+  assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn suppressed_addresses_get_no_confirmation_email() {
+  let app = spawn_app().await;
+  suppress(&app, "ursula_le_guin@gmail.com").await;
+  Mock::given(any())
+    .respond_with(ResponseTemplate::new(200))
+    .expect(0)
+    .mount(&app.email_server)
+    .await;
+
+  let response = app
+    .post_subscriptions(
+      "name=le%20guin&email=ursula_le_guin%40gmail.com".into(),
+    )
+    .await;
+
+  assert_eq!(response.status().as_u16(), 200);
+  let outcome = sqlx::query!("SELECT status FROM send_outcomes")
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+  assert_eq!(outcome.status, "suppressed");
+}
+
+#[tokio::test]
+async fn suppressed_domains_are_left_out_of_batches() {
+  let app = spawn_app().await;
+  suppress(&app, "example.com").await;
+  Mock::given(path("/messages"))
+    .and(method("POST"))
+    .respond_with(email_sent())
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+  let recipients: Vec<_> = ["ursula@Example.com", "ursula_le_guin@gmail.com"]
+    .into_iter()
+    .map(|email| {
+      BatchRecipient::new(SubscriberEmail::parse(email.into()).unwrap())
+    })
+    .collect();
+
+  let outcomes = app
+    .email_client
+    .send_batch(&recipients, "Subject", "<p>HTML</p>", "Text", &[])
+    .await;
+
+  let statuses: Vec<_> =
+    outcomes.iter().map(|outcome| outcome.status).collect();
+  assert_eq!(
+    statuses,
+    vec![RecipientStatus::Suppressed, RecipientStatus::Sent]
+  );
+  let body: serde_json::Value = serde_json::from_slice(
+    &app.email_server.received_requests().await.unwrap()[0].body,
+  )
+  .unwrap();
+  assert_eq!(body["message"]["to"].as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn erasing_a_subscriber_deletes_their_data_and_suppresses_them() {
+  let app = spawn_app().await;
+  Mock::given(path("/messages"))
+    .and(method("POST"))
+    .respond_with(email_sent())
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+  app
+    .post_subscriptions(
+      "name=le%20guin&email=ursula_le_guin%40gmail.com".into(),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+
+  let response = delete(&app, "subscribers/Ursula_Le_Guin@gmail.com").await;
+
+  assert_eq!(response.status().as_u16(), 204);
+  let remaining = sqlx::query!(
+    r#"
+    SELECT
+      (SELECT COUNT(*) FROM subscriptions) AS "subscriptions!",
+      (SELECT COUNT(*) FROM subscription_tokens) AS "tokens!",
+      (SELECT COUNT(*) FROM send_outcomes) AS "send_outcomes!"
+    "#
+  )
+  .fetch_one(&app.db_pool)
+  .await
+  .unwrap();
+  assert_eq!(
+    (
+      remaining.subscriptions,
+      remaining.tokens,
+      remaining.send_outcomes
+    ),
+    (0, 0, 0)
+  );
+  let suppressions = get_suppressions(&app).await;
+  assert_eq!(suppressions[0]["address"], "ursula_le_guin@gmail.com");
+  assert_eq!(suppressions[0]["source"], "erasure");
+}
+
+#[tokio::test]
+async fn removing_a_suppression_lets_its_subscriber_subscribe_again() {
+  let app = spawn_app().await;
+  sqlx::query!(
+    r#"
+    INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+    VALUES ($1, 'Ursula@example.com', 'le guin', now(), 'suppressed')
+    "#,
+    uuid::Uuid::new_v4(),
+  )
+  .execute(&app.db_pool)
+  .await
+  .unwrap();
+  suppress(&app, "ursula@example.com").await;
+
+  let response = delete(&app, "suppressions/ursula@example.com").await;
+
+  assert_eq!(response.status().as_u16(), 204);
+  let subscription = sqlx::query!("SELECT status FROM subscriptions")
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+  assert_eq!(subscription.status, "unsubscribed");
+}
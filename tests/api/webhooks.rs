@@ -0,0 +1,318 @@
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use p256::ecdsa::{signature::Signer, Signature};
+use reqwest::{Client, Response};
+use sha1::Sha1;
+use uuid::Uuid;
+use wiremock::{matchers::any, Mock, ResponseTemplate};
+
+use crate::api::helpers::{
+  sendgrid_signing_key, spawn_app, TestApp, MANDRILL_WEBHOOK_KEY,
+  POSTMARK_WEBHOOK_PASSWORD, POSTMARK_WEBHOOK_USERNAME,
+};
+
+async fn insert_subscriber(app: &TestApp, email: &str, status: &str) {
+  sqlx::query!(
+    r#"
+    INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+    VALUES ($1, $2, 'le guin', $3, $4)
+    "#,
+    Uuid::new_v4(),
+    email,
+    Utc::now(),
+    status,
+  )
+  .execute(&app.db_pool)
+  .await
+  .unwrap();
+}
+
+async fn status(app: &TestApp, email: &str) -> String {
+  sqlx::query!("SELECT status FROM subscriptions WHERE email = $1", email)
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .status
+}
+
+async fn delivery_events(app: &TestApp) -> Vec<(String, String, String)> {
+  sqlx::query!(
+    "SELECT provider, kind, recipient FROM delivery_events ORDER BY occurred_at"
+  )
+  .fetch_all(&app.db_pool)
+  .await
+  .unwrap()
+  .into_iter()
+  .map(|event| (event.provider, event.kind, event.recipient))
+  .collect()
+}
+
+/// Post `events` like Mandrill does, signed with `key`.
+async fn post_mandrill(
+  app: &TestApp,
+  events: serde_json::Value,
+  key: &str,
+) -> Response {
+  let events = events.to_string();
+  let mut mac = Hmac::<Sha1>::new_from_slice(key.as_bytes()).unwrap();
+  mac.update(format!("{}/webhooks/mandrill", app.base_url).as_bytes());
+  mac.update(b"mandrill_events");
+  mac.update(events.as_bytes());
+  let signature = base64::encode(mac.finalize().into_bytes());
+  Client::new()
+    .post(format!("{}/webhooks/mandrill", app.address))
+    .header("X-Mandrill-Signature", signature)
+    .form(&[("mandrill_events", events)])
+    .send()
+    .await
+    .expect("Failed to execute request")
+}
+
+fn mandrill_hard_bounce(email: &str) -> serde_json::Value {
+  serde_json::json!([{
+    "event": "hard_bounce",
+    "_id": "abc123",
+    "ts": 1657762212,
+    "msg": {
+      "email": email,
+      "bounce_description": "bad_mailbox"
+    }
+  }])
+}
+
+/// Post `events` like SendGrid does, signed with its key.
+async fn post_sendgrid(app: &TestApp, events: serde_json::Value) -> Response {
+  let body = events.to_string();
+  let timestamp = Utc::now().timestamp().to_string();
+  let signature: Signature =
+    sendgrid_signing_key().sign(format!("{}{}", timestamp, body).as_bytes());
+  Client::new()
+    .post(format!("{}/webhooks/sendgrid", app.address))
+    .header(
+      "X-Twilio-Email-Event-Webhook-Signature",
+      base64::encode(signature.to_der()),
+    )
+    .header("X-Twilio-Email-Event-Webhook-Timestamp", timestamp)
+    .header("Content-Type", "application/json")
+    .body(body)
+    .send()
+    .await
+    .expect("Failed to execute request")
+}
+
+#[tokio::test]
+async fn a_hard_bounce_suppresses_the_subscriber() {
+  let app = spawn_app().await;
+  insert_subscriber(&app, "ursula@example.com", "confirmed").await;
+  insert_subscriber(&app, "octavia@example.com", "confirmed").await;
+
+  let response = post_mandrill(
+    &app,
+    mandrill_hard_bounce("ursula@example.com"),
+    MANDRILL_WEBHOOK_KEY,
+  )
+  .await;
+
+  assert_eq!(response.status().as_u16(), 200);
+  assert_eq!(status(&app, "ursula@example.com").await, "suppressed");
+  assert_eq!(status(&app, "octavia@example.com").await, "confirmed");
+  assert_eq!(
+    delivery_events(&app).await,
+    vec![(
+      "mandrill".into(),
+      "hard_bounce".into(),
+      "ursula@example.com".into()
+    )]
+  );
+  let suppression = sqlx::query!("SELECT address, source FROM suppressions")
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+  assert_eq!(
+    (suppression.address.as_str(), suppression.source.as_str()),
+    ("ursula@example.com", "bounce")
+  );
+}
+
+#[tokio::test]
+async fn bounces_suppress_subscribers_whatever_the_case_of_their_address() {
+  let app = spawn_app().await;
+  insert_subscriber(&app, "Ursula@Example.com", "confirmed").await;
+
+  let response = post_mandrill(
+    &app,
+    mandrill_hard_bounce("ursula@EXAMPLE.com"),
+    MANDRILL_WEBHOOK_KEY,
+  )
+  .await;
+
+  assert_eq!(response.status().as_u16(), 200);
+  assert_eq!(status(&app, "Ursula@Example.com").await, "suppressed");
+}
+
+#[tokio::test]
+async fn webhooks_with_an_invalid_signature_are_rejected() {
+  let app = spawn_app().await;
+  insert_subscriber(&app, "ursula@example.com", "confirmed").await;
+
+  let response = post_mandrill(
+    &app,
+    mandrill_hard_bounce("ursula@example.com"),
+    "not-the-key",
+  )
+  .await;
+
+  assert_eq!(response.status().as_u16(), 401);
+  assert_eq!(status(&app, "ursula@example.com").await, "confirmed");
+  assert!(delivery_events(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn events_retried_by_the_provider_are_recorded_once() {
+  let app = spawn_app().await;
+
+  for _ in 0..2 {
+    let response = post_mandrill(
+      &app,
+      mandrill_hard_bounce("ursula@example.com"),
+      MANDRILL_WEBHOOK_KEY,
+    )
+    .await;
+    assert_eq!(response.status().as_u16(), 200);
+  }
+
+  assert_eq!(delivery_events(&app).await.len(), 1);
+}
+
+#[tokio::test]
+async fn a_postmark_spam_complaint_suppresses_the_subscriber() {
+  let app = spawn_app().await;
+  insert_subscriber(&app, "ursula@example.com", "confirmed").await;
+  let complaint = serde_json::json!({
+    "RecordType": "SpamComplaint",
+    "ID": 42,
+    "Type": "SpamComplaint",
+    "MessageID": "00000000-0000-0000-0000-000000000000",
+    "Email": "ursula@example.com",
+    "BouncedAt": "2022-07-14T01:30:12Z"
+  });
+  let post = |password: &str| {
+    Client::new()
+      .post(format!("{}/webhooks/postmark", app.address))
+      .basic_auth(POSTMARK_WEBHOOK_USERNAME, Some(password))
+      .json(&complaint)
+      .send()
+  };
+
+  let response = post("not-the-password").await.unwrap();
+  assert_eq!(response.status().as_u16(), 401);
+  assert_eq!(status(&app, "ursula@example.com").await, "confirmed");
+
+  let response = post(POSTMARK_WEBHOOK_PASSWORD).await.unwrap();
+  assert_eq!(response.status().as_u16(), 200);
+  assert_eq!(status(&app, "ursula@example.com").await, "suppressed");
+  assert_eq!(
+    delivery_events(&app).await,
+    vec![(
+      "postmark".into(),
+      "complaint".into(),
+      "ursula@example.com".into()
+    )]
+  );
+}
+
+#[tokio::test]
+async fn sendgrid_deliveries_are_recorded_without_suppressing() {
+  let app = spawn_app().await;
+  insert_subscriber(&app, "ursula@example.com", "confirmed").await;
+
+  let response = post_sendgrid(
+    &app,
+    serde_json::json!([
+      {
+        "email": "ursula@example.com",
+        "timestamp": 1657762212,
+        "event": "delivered",
+        "sg_event_id": "event-1",
+        "sg_message_id": "message-1"
+      },
+      {
+        "email": "ursula@example.com",
+        "timestamp": 1657762213,
+        "event": "open",
+        "sg_event_id": "event-2",
+        "sg_message_id": "message-1"
+      }
+    ]),
+  )
+  .await;
+
+  assert_eq!(response.status().as_u16(), 200);
+  assert_eq!(status(&app, "ursula@example.com").await, "confirmed");
+  assert_eq!(
+    delivery_events(&app).await,
+    vec![(
+      "sendgrid".into(),
+      "delivered".into(),
+      "ursula@example.com".into()
+    )]
+  );
+}
+
+#[tokio::test]
+async fn webhooks_from_unknown_providers_are_not_found() {
+  let app = spawn_app().await;
+
+  let response = Client::new()
+    .post(format!("{}/webhooks/mailchimp", app.address))
+    .json(&serde_json::json!([]))
+    .send()
+    .await
+    .unwrap();
+
+  assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn suppressed_subscribers_are_not_emailed_when_subscribing_again() {
+  let app = spawn_app().await;
+  insert_subscriber(&app, "ursula@example.com", "confirmed").await;
+  post_mandrill(
+    &app,
+    mandrill_hard_bounce("ursula@example.com"),
+    MANDRILL_WEBHOOK_KEY,
+  )
+  .await
+  .error_for_status()
+  .unwrap();
+  Mock::given(any())
+    .respond_with(ResponseTemplate::new(200))
+    .expect(0)
+    .mount(&app.email_server)
+    .await;
+
+  let response = app
+    .post_subscriptions("name=le%20guin&email=ursula%40example.com".into())
+    .await;
+
+  assert_eq!(response.status().as_u16(), 200);
+  assert_eq!(status(&app, "ursula@example.com").await, "suppressed");
+}
+
+#[tokio::test]
+async fn subscribers_suppressed_before_the_list_are_not_emailed_either() {
+  let app = spawn_app().await;
+  insert_subscriber(&app, "ursula@example.com", "suppressed").await;
+  Mock::given(any())
+    .respond_with(ResponseTemplate::new(200))
+    .expect(0)
+    .mount(&app.email_server)
+    .await;
+
+  let response = app
+    .post_subscriptions("name=le%20guin&email=ursula%40example.com".into())
+    .await;
+
+  assert_eq!(response.status().as_u16(), 200);
+  assert_eq!(status(&app, "ursula@example.com").await, "suppressed");
+}